@@ -1,8 +1,9 @@
+use std::sync::Arc;
+
 use rocket::http::Status;
 
 use rocket::request::Outcome;
 use rocket::request::{self, FromRequest, Request};
-use rocket::State;
 
 pub struct ApiKey(pub String);
 
@@ -18,12 +19,17 @@ fn key_is_authorized(state: &crate::ServerState, key: &str) -> bool {
 }
 
 fn load_state_check_key(
-    _request: &Request<'_>,
+    request: &Request<'_>,
     header_api_key: &str,
 ) -> request::Outcome<ApiKey, ApiKeyError> {
-    let state = crate::get_server_state();
+    let state = match request.rocket().state::<Arc<crate::ServerState>>() {
+        Some(state) => state,
+        None => {
+            return request::Outcome::Error((Status::InternalServerError, ApiKeyError::Invalid))
+        }
+    };
 
-    if key_is_authorized(&state, header_api_key) {
+    if key_is_authorized(state, header_api_key) {
         Outcome::Success(ApiKey(header_api_key.to_string()))
     } else {
         request::Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid))
@@ -42,3 +48,31 @@ impl<'r> FromRequest<'r> for ApiKey {
         }
     }
 }
+
+// Operator only key for routes that expose or change player data. The API key ships inside every
+// game build, so it can't be trusted with those.
+pub struct AdminKey(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminKey {
+    type Error = ApiKeyError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let state = match request.rocket().state::<Arc<crate::ServerState>>() {
+            Some(state) => state,
+            None => {
+                return request::Outcome::Error((Status::InternalServerError, ApiKeyError::Invalid))
+            }
+        };
+        let admin_key = &state.secrets.keys.admin_key;
+
+        match request.headers().get_one("X-Admin-Key") {
+            // No admin key set turns the admin routes off, instead of letting an empty one in
+            Some(key) if !admin_key.is_empty() && key == admin_key => {
+                Outcome::Success(AdminKey(key.to_string()))
+            }
+            Some(_) => request::Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid)),
+            None => request::Outcome::Error((Status::Unauthorized, ApiKeyError::Missing)),
+        }
+    }
+}
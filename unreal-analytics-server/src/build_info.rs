@@ -0,0 +1,50 @@
+use rocket::http::Status;
+
+use rocket::request::Outcome;
+use rocket::request::{self, FromRequest, Request};
+
+// Build details sent in the X-Build-* headers. Games that can't add them to their collectors can
+// set these once on their HTTP requests instead, values in the uploaded data always win.
+#[derive(Debug, Clone, Default)]
+pub struct BuildInfo {
+    pub build_version: Option<String>,
+    pub changelist: Option<u64>,
+    pub platform: Option<String>,
+    pub build_configuration: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum BuildInfoError {
+    InvalidChangelist,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BuildInfo {
+    type Error = BuildInfoError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get_one(name)
+                .map(str::trim)
+                .filter(|value| !value.is_empty() && value.len() <= 128)
+                .map(str::to_string)
+        };
+
+        let changelist = match header("X-Build-Changelist").map(|cl| cl.parse::<u64>()) {
+            Some(Ok(changelist)) => Some(changelist),
+            Some(Err(_)) => {
+                return Outcome::Error((Status::BadRequest, BuildInfoError::InvalidChangelist))
+            }
+            None => None,
+        };
+
+        Outcome::Success(BuildInfo {
+            build_version: header("X-Build-Version"),
+            changelist,
+            platform: header("X-Build-Platform"),
+            build_configuration: header("X-Build-Configuration"),
+        })
+    }
+}
@@ -9,39 +9,22 @@ use std::net::IpAddr;
 fn cc2n(code: &str) -> Option<&str> {
     use dia_i18n::iso_3166_1::{ALPHA2_CODES, ALPHA3_CODES, NUMERIC_CODES};
 
-    match code.len() {
-        2 => match code.chars().all(|c| match c {
-            'a'..='z' | 'A'..='Z' => true,
-            _ => false,
-        }) {
-            true => match ALPHA2_CODES
-                .iter()
-                .find(|c| c.code().eq_ignore_ascii_case(&code))
-            {
-                Some(code) => Some(code.country_name()),
-                None => return None,
-            },
-            false => return None,
-        },
-        3 => match code.chars().all(|c| match c {
-            'a'..='z' | 'A'..='Z' => true,
-            _ => false,
-        }) {
-            true => match ALPHA3_CODES
-                .iter()
-                .find(|c| c.code().eq_ignore_ascii_case(&code))
-            {
-                Some(code) => Some(code.country_name()),
-                None => return None,
-            },
-            false => match code.chars().all(|c| c >= '0' && c <= '9') {
-                true => match NUMERIC_CODES.iter().find(|c| c.code() == code) {
-                    Some(code) => Some(code.country_name()),
-                    None => return None,
-                },
-                false => return None,
-            },
-        },
+    let is_alpha = code.chars().all(|c| c.is_ascii_alphabetic());
+    let is_numeric = code.chars().all(|c| c.is_ascii_digit());
+
+    match (code.len(), is_alpha, is_numeric) {
+        (2, true, _) => ALPHA2_CODES
+            .iter()
+            .find(|c| c.code().eq_ignore_ascii_case(code))
+            .map(|code| code.country_name()),
+        (3, true, _) => ALPHA3_CODES
+            .iter()
+            .find(|c| c.code().eq_ignore_ascii_case(code))
+            .map(|code| code.country_name()),
+        (3, _, true) => NUMERIC_CODES
+            .iter()
+            .find(|c| c.code() == code)
+            .map(|code| code.country_name()),
         _ => None,
     }
 }
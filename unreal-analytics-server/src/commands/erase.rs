@@ -0,0 +1,119 @@
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::privacy::{ErasureMode, ErasureRequest, ErasureTarget};
+use crate::ServerState;
+
+// Returns the request, or an error message for the user
+fn parse_request(
+    options: &[ResolvedOption],
+    requested_by: String,
+) -> Result<ErasureRequest, String> {
+    let mut net_id = None;
+    let mut ip = None;
+    let mut mode = None;
+
+    for option in options {
+        match (option.name, &option.value) {
+            ("net_id", ResolvedValue::String(value)) => net_id = Some(value.trim().to_string()),
+            ("ip", ResolvedValue::String(value)) => {
+                ip = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("`{}` isn't an IP address", value))?,
+                )
+            }
+            ("mode", ResolvedValue::String(value)) => mode = ErasureMode::parse(value),
+            _ => {}
+        }
+    }
+
+    let target = match (net_id, ip) {
+        (Some(net_id), None) if !net_id.is_empty() => ErasureTarget::NetId(net_id),
+        (None, Some(ip)) => ErasureTarget::Ip(ip),
+        _ => return Err("Give either a NetID or an IP".to_string()),
+    };
+
+    Ok(ErasureRequest {
+        target,
+        mode: mode.ok_or("Missing mode")?,
+        requested_by,
+    })
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    if !super::is_admin(interaction.member.as_deref()) {
+        interaction
+            .create_response(ctx, super::not_allowed("erase player data"))
+            .await?;
+        return Ok(());
+    }
+
+    let requested_by = format!("discord:{}", interaction.user.name);
+    let request = match parse_request(&interaction.data.options(), requested_by) {
+        Ok(request) => request,
+        Err(message) => {
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(message)
+                            .ephemeral(true),
+                    ),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Going through every collection and the archives takes longer than Discord waits
+    interaction.defer_ephemeral(&ctx.http).await?;
+
+    let config = state.read_config().unwrap_or(state.default_config.clone());
+
+    let content = match state.storage.erase(&config, &state.secrets, &request).await {
+        Ok(report) => {
+            println!("Erasure by {}: {}", request.requested_by, report);
+            format!("Erased ({}): {}", request.mode.as_str(), report)
+        }
+        Err(e) => {
+            eprintln!("Failed to erase player data! Error: {}", e);
+            "Failed to erase player data, the request was recorded as failed".to_string()
+        }
+    };
+
+    interaction
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("erase")
+        .description("Deletes or anonymizes everything stored about a player")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "mode", "What to do with it")
+                .add_string_choice("Delete", ErasureMode::Delete.as_str())
+                .add_string_choice("Anonymize", ErasureMode::Anonymize.as_str())
+                .required(true),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "net_id",
+            "The player's NetID",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "ip",
+            "Only the sessions uploaded from this IP",
+        ))
+}
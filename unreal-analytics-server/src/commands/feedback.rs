@@ -0,0 +1,130 @@
+use futures::TryFutureExt;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::feedback::{FeedbackItem, FeedbackQuery, FeedbackStatus};
+use crate::ServerState;
+
+// Most comments listed in one message
+const MAX_LISTED: i64 = 15;
+// Discord messages are limited to 2000 characters
+const MAX_MESSAGE_LENGTH: usize = 1900;
+
+// Choices for the status option, shared with feedback_status
+pub fn status_option(description: &str) -> CreateCommandOption {
+    CreateCommandOption::new(CommandOptionType::String, "status", description)
+        .add_string_choice("New", FeedbackStatus::New.as_str())
+        .add_string_choice("Acknowledged", FeedbackStatus::Acknowledged.as_str())
+        .add_string_choice("Fixed", FeedbackStatus::Fixed.as_str())
+        .add_string_choice("Won't fix", FeedbackStatus::WontFix.as_str())
+}
+
+pub fn format_item(item: &FeedbackItem) -> String {
+    let mut comment: String = item.comment.chars().take(200).collect();
+    if comment.len() < item.comment.len() {
+        comment += "...";
+    }
+
+    let mut details = vec![
+        item.status.as_str().to_string(),
+        item.session_time.format("%Y-%m-%d").to_string(),
+    ];
+    if let Some(assignee) = &item.assignee {
+        details.push(format!("@{}", assignee));
+    }
+    if !item.tags.is_empty() {
+        details.push(item.tags.join(", "));
+    }
+
+    format!(
+        "`{}` ({})\n> {}\n",
+        item.id.to_hex(),
+        details.join(" | "),
+        comment
+    )
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    if !super::is_admin(interaction.member.as_deref()) {
+        interaction
+            .create_response(ctx, super::not_allowed("read player feedback"))
+            .await?;
+        return Ok(());
+    }
+
+    let mut query = FeedbackQuery::default();
+    for option in interaction.data.options() {
+        match (option.name, option.value) {
+            ("status", ResolvedValue::String(status)) => {
+                query.status = FeedbackStatus::parse(status)
+            }
+            ("tag", ResolvedValue::String(tag)) => query.tag = Some(tag.trim().to_lowercase()),
+            ("assignee", ResolvedValue::String(assignee)) => {
+                query.assignee = Some(assignee.trim().to_string())
+            }
+            ("search", ResolvedValue::String(search)) => query.search = Some(search.to_string()),
+            _ => {}
+        }
+    }
+
+    let items = state
+        .storage
+        .get_feedback(&query, MAX_LISTED, 0)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    let mut content = String::new();
+    for item in &items {
+        let line = format_item(item);
+        if content.len() + line.len() > MAX_MESSAGE_LENGTH {
+            break;
+        }
+        content += &line;
+    }
+    if content.is_empty() {
+        content = "No feedback found".to_string();
+    }
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("feedback")
+        .description("Lists player feedback comments, newest first")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(status_option("Only show comments with this status"))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "tag",
+            "Only show comments with this tag",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "assignee",
+            "Only show comments assigned to this person",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "search",
+            "Only show comments containing this text",
+        ))
+}
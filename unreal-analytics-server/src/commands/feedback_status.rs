@@ -0,0 +1,108 @@
+use futures::TryFutureExt;
+use mongodb::bson::oid::ObjectId;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::feedback::{FeedbackStatus, FeedbackUpdate};
+use crate::ServerState;
+
+// Returns the comment id and the changes, or an error message for the user
+fn parse_update(options: &[ResolvedOption]) -> Result<(ObjectId, FeedbackUpdate), String> {
+    let mut id = None;
+    let mut update = FeedbackUpdate::default();
+
+    for option in options {
+        match (option.name, &option.value) {
+            ("id", ResolvedValue::String(value)) => {
+                id = Some(
+                    ObjectId::parse_str(value.trim())
+                        .map_err(|_| format!("`{}` isn't a feedback id", value))?,
+                )
+            }
+            ("status", ResolvedValue::String(status)) => {
+                update.status = FeedbackStatus::parse(status)
+            }
+            ("assignee", ResolvedValue::String(assignee)) => {
+                update.assignee = Some(assignee.to_string())
+            }
+            ("tags", ResolvedValue::String(tags)) => {
+                update.tags = Some(tags.split(',').map(String::from).collect())
+            }
+            _ => {}
+        }
+    }
+
+    let id = id.ok_or("Missing feedback id")?;
+    Ok((id, update))
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    if !super::is_admin(interaction.member.as_deref()) {
+        interaction
+            .create_response(ctx, super::not_allowed("triage player feedback"))
+            .await?;
+        return Ok(());
+    }
+
+    let content = match parse_update(&interaction.data.options()) {
+        Ok((id, update)) => {
+            let item = state
+                .storage
+                .update_feedback(id, &update)
+                .map_err(|err| {
+                    eprintln!("Database error! {err:?}");
+                    serenity::Error::Other("Database error")
+                })
+                .await?;
+
+            match item {
+                Some(item) => format!("Updated:\n{}", super::feedback::format_item(&item)),
+                None => format!("No feedback with id `{}`", id.to_hex()),
+            }
+        }
+        Err(message) => message,
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("feedback_status")
+        .description("Changes the status, assignee or tags of a feedback comment")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "id",
+                "Feedback id, as shown by /feedback",
+            )
+            .required(true),
+        )
+        .add_option(super::feedback::status_option("New status"))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "assignee",
+            "Who is looking into it, a single space unassigns",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "tags",
+            "Comma separated tags, replaces the current tags",
+        ))
+}
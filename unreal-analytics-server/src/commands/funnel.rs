@@ -0,0 +1,139 @@
+use futures::TryFutureExt;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::database::EventQuery;
+use crate::funnel::{FunnelQuery, FunnelStep, FunnelStepResult};
+use crate::ServerState;
+
+fn build_table(results: &[FunnelStepResult]) -> String {
+    let mut table = format!(
+        "{:<24} {:>7} {:>6} {:>6} {:>6}\n",
+        "Step", "Players", "Total", "Step", "Lost"
+    );
+
+    for result in results {
+        let mut step = result.step.clone();
+        if step.len() > 24 {
+            step = format!("{}~", &step[..step.floor_char_boundary(23)]);
+        }
+
+        table += &format!(
+            "{:<24} {:>7} {:>5.0}% {:>5.0}% {:>6}\n",
+            step,
+            result.players,
+            result.conversion * 100.0,
+            result.step_conversion * 100.0,
+            result.drop_off,
+        );
+    }
+
+    table
+}
+
+// Returns an error message for the user when an option is invalid
+fn parse_query(options: &[ResolvedOption]) -> Result<FunnelQuery, String> {
+    let parse_date = |date: &str| {
+        crate::utils::parse_date_param(date).ok_or(format!("Invalid date: `{}`", date))
+    };
+
+    let mut query = FunnelQuery {
+        steps: Vec::new(),
+        events: EventQuery::default(),
+        window: None,
+    };
+
+    for option in options {
+        match (option.name, &option.value) {
+            ("steps", ResolvedValue::String(steps)) => query.steps = FunnelStep::parse_list(steps)?,
+            ("build", ResolvedValue::String(build)) => query.events.build = Some(build.to_string()),
+            ("from", ResolvedValue::String(from)) => query.events.from = Some(parse_date(from)?),
+            ("to", ResolvedValue::String(to)) => query.events.to = Some(parse_date(to)?),
+            ("window_hours", ResolvedValue::Integer(hours)) => {
+                query.window = Some(chrono::Duration::hours(*hours))
+            }
+            _ => {}
+        }
+    }
+
+    if query.steps.is_empty() {
+        return Err("A funnel needs at least one step".to_string());
+    }
+
+    Ok(query)
+}
+
+async fn build_content(
+    state: &ServerState,
+    query: &FunnelQuery,
+) -> Result<String, serenity::Error> {
+    let results = state
+        .storage
+        .get_funnel(query)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    Ok(format!("Funnel: ```{}```", build_table(&results)))
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    let content = match parse_query(&interaction.data.options()) {
+        Ok(query) => build_content(state, &query).await?,
+        Err(message) => message,
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().content(content),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("funnel")
+        .description("Shows how many players made it through a list of events")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "steps",
+                "Comma separated events, like TutorialStart, LevelComplete:Level=2, BossReached",
+            )
+            .required(true),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "build",
+            "Only include events from this build",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "from",
+            "Only include events on or after this date (YYYY-MM-DD)",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "to",
+            "Only include events before this date (YYYY-MM-DD)",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "window_hours",
+                "Every step has to happen within this many hours of the first one",
+            )
+            .min_int_value(1),
+        )
+}
@@ -0,0 +1,70 @@
+use futures::TryFutureExt;
+use mongodb::bson::oid::ObjectId;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::ServerState;
+
+// Custom id of the button on session messages, followed by the session's _id
+pub const BUTTON_PREFIX: &str = "gitlab_feedback:";
+
+pub fn button(session_object_id: ObjectId) -> CreateButton {
+    CreateButton::new(format!("{}{}", BUTTON_PREFIX, session_object_id.to_hex()))
+        .label("Create GitLab issue")
+}
+
+// Files every comment from the session as an issue, comments that already have one keep it
+pub async fn run(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    let session_object_id = interaction
+        .data
+        .custom_id
+        .strip_prefix(BUTTON_PREFIX)
+        .and_then(|id| ObjectId::parse_str(id).ok())
+        .ok_or(serenity::Error::Other("Invalid button id"))?;
+
+    // Buttons can't be hidden like commands, so this is the only check
+    if !super::is_admin(interaction.member.as_ref()) {
+        interaction
+            .create_response(ctx, super::not_allowed("create GitLab issues"))
+            .await?;
+        return Ok(());
+    }
+
+    // Talking to GitLab can take longer than Discord waits for a response
+    interaction.defer(&ctx.http).await?;
+
+    let feedback = state
+        .storage
+        .get_session_feedback(session_object_id)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    let mut content = String::new();
+    for item in &feedback {
+        match crate::gitlab::file_feedback(state, item.id).await {
+            Ok(Some(issue)) => content += &format!("#{} {}\n", issue.iid, issue.web_url),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Failed to create GitLab issue! Error: {}", e);
+                content += &format!("Failed to create issue: {}\n", e);
+            }
+        }
+    }
+    if content.is_empty() {
+        content = "This session has no feedback comments".to_string();
+    }
+
+    interaction
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
@@ -1,4 +1,108 @@
+pub mod erase;
+pub mod feedback;
+pub mod feedback_status;
+pub mod funnel;
+pub mod gitlab_issue;
 pub mod modal;
+pub mod performance;
 pub mod ping;
+pub mod player;
 pub mod print_config;
+pub mod retention;
+pub mod search;
 pub mod test_command;
+
+use serenity::builder::{
+    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::model::application::{CommandOptionType, ResolvedOption, ResolvedValue};
+use serenity::model::guild::Member;
+
+use crate::database::StatsFilter;
+
+// Commands that show or change player data are only for administrators, like the admin key routes
+// they mirror. default_member_permissions hides them from everyone else, but the guild can change
+// that, so they check again when they're run.
+pub fn is_admin(member: Option<&Member>) -> bool {
+    member
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.administrator())
+}
+
+// Response for someone that isn't allowed to do `what`, only they see it
+pub fn not_allowed(what: &str) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!("Only administrators can {}", what))
+            .ephemeral(true),
+    )
+}
+
+// Options shared by every command that shows stats
+pub fn stats_filter_options() -> Vec<CreateCommandOption> {
+    vec![
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "build",
+            "Only include this build version",
+        ),
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "platform",
+            "Only include this platform",
+        ),
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "from",
+            "Only include sessions started on or after this date (YYYY-MM-DD)",
+        ),
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "to",
+            "Only include sessions started before this date (YYYY-MM-DD)",
+        ),
+        CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "pie",
+            "True for PIE sessions only, false for game sessions only",
+        ),
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "country",
+            "Only include this country code, like US",
+        ),
+        CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "steam",
+            "True for steam players only, false for everyone else",
+        ),
+    ]
+}
+
+// Returns an error message for the user when a date can't be parsed
+pub fn stats_filter_from_options(options: &[ResolvedOption]) -> Result<StatsFilter, String> {
+    let parse_date = |date: &str| {
+        crate::utils::parse_date_param(date).ok_or(format!("Invalid date: `{}`", date))
+    };
+
+    let mut filter = StatsFilter::default();
+    for option in options {
+        match (option.name, &option.value) {
+            ("build", ResolvedValue::String(build)) => {
+                filter.build_version = Some(build.to_string())
+            }
+            ("platform", ResolvedValue::String(platform)) => {
+                filter.platform = Some(platform.to_string())
+            }
+            ("from", ResolvedValue::String(from)) => filter.from = Some(parse_date(from)?),
+            ("to", ResolvedValue::String(to)) => filter.to = Some(parse_date(to)?),
+            ("pie", ResolvedValue::Boolean(pie)) => filter.pie = Some(*pie),
+            ("country", ResolvedValue::String(country)) => {
+                filter.country = Some(country.to_string())
+            }
+            ("steam", ResolvedValue::Boolean(steam)) => filter.steam = Some(*steam),
+            _ => {}
+        }
+    }
+    Ok(filter)
+}
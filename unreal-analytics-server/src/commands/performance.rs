@@ -0,0 +1,92 @@
+use futures::TryFutureExt;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::database::{PerformanceStats, StatsFilter};
+use crate::ServerState;
+
+fn format_ms(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{:.1}", value))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn build_table(stats: &[PerformanceStats]) -> String {
+    let mut table = format!(
+        "{:<12} {:<16} {:>8} {:>7} {:>7} {:>7} {:>8} {:>9}\n",
+        "Build", "Hardware", "Sessions", "p50", "p95", "p99", "Hitches", "Peak MB"
+    );
+
+    for stat in stats {
+        table += &format!(
+            "{:<12} {:<16} {:>8} {:>7} {:>7} {:>7} {:>8.1} {:>9}\n",
+            stat.build_version.as_deref().unwrap_or("unknown"),
+            stat.hardware_class.as_deref().unwrap_or("unknown"),
+            stat.sessions,
+            format_ms(stat.p50_frame_time_ms),
+            format_ms(stat.p95_frame_time_ms),
+            format_ms(stat.p99_frame_time_ms),
+            stat.hitches_per_session,
+            stat.max_peak_memory_mb
+                .map(|memory| format!("{:.0}", memory))
+                .unwrap_or_else(|| "-".to_string()),
+        );
+    }
+
+    table
+}
+
+async fn build_content(
+    state: &ServerState,
+    filter: &StatsFilter,
+) -> Result<String, serenity::Error> {
+    let stats = state
+        .storage
+        .get_performance_stats(filter)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    Ok(if stats.is_empty() {
+        "No performance data yet".to_string()
+    } else {
+        // Discord messages are limited to 2000 characters
+        let mut table = build_table(&stats);
+        if table.len() > 1900 {
+            let cut = table[..1900].rfind('\n').unwrap_or(0);
+            table.truncate(cut);
+        }
+        format!("Frame times (ms), {}: ```{}```", filter, table)
+    })
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    let content = match super::stats_filter_from_options(&interaction.data.options()) {
+        Ok(filter) => build_content(state, &filter).await?,
+        Err(message) => message,
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().content(content),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("performance")
+        .description("Shows frame time percentiles per build and hardware class")
+        .set_options(super::stats_filter_options())
+}
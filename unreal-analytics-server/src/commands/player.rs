@@ -0,0 +1,147 @@
+use futures::TryFutureExt;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::database::PlayerProfile;
+use crate::ServerState;
+
+// Discord messages are limited to 2000 characters
+const MAX_MESSAGE_LENGTH: usize = 1900;
+
+fn format_date(date: Option<chrono::DateTime<chrono::Utc>>) -> String {
+    date.map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn format_list(list: &[String]) -> String {
+    if list.is_empty() {
+        "-".to_string()
+    } else {
+        list.join(", ")
+    }
+}
+
+fn build_content(profile: &PlayerProfile) -> String {
+    let mut content = String::new();
+    if profile.is_steam_player {
+        content += &format!("https://steamcommunity.com/profiles/{}\n", profile.net_id);
+    } else {
+        content += &format!("{}\n", profile.net_id);
+    }
+
+    let total_play_time = chrono::TimeDelta::seconds(profile.total_play_time_secs);
+    content += &format!(
+        "```First Seen: {}\nLast Seen: {}\nSessions: {} ({} PIE)\nTotal Play Time: {}\nCountries: {}\nBuilds: {}\nPlatforms: {}```",
+        format_date(profile.first_seen),
+        format_date(profile.last_seen),
+        profile.sessions,
+        profile.pie_sessions,
+        crate::utils::session_duration_to_string(&total_play_time),
+        format_list(&profile.countries),
+        format_list(&profile.builds),
+        format_list(&profile.platforms),
+    );
+
+    let mut sessions = String::new();
+    for session in &profile.recent_sessions {
+        let play_time = session
+            .play_time_secs
+            .map(|secs| crate::utils::session_duration_to_string(&chrono::TimeDelta::seconds(secs)))
+            .unwrap_or_else(|| "-".to_string());
+        sessions += &format!(
+            "{} {:>9} {:<10} {}{}\n",
+            format_date(session.start_time),
+            play_time,
+            session.build_version.as_deref().unwrap_or("-"),
+            session.country_code.as_deref().unwrap_or("-"),
+            if session.is_play_in_editor_session {
+                " PIE"
+            } else {
+                ""
+            },
+        );
+    }
+    if !sessions.is_empty() {
+        content += &format!("Recent sessions:```{}```", sessions);
+    }
+
+    let comments: Vec<&String> = profile
+        .feedback
+        .iter()
+        .flat_map(|feedback| &feedback.comments)
+        .collect();
+    if !comments.is_empty() {
+        content += "Feedback comments:";
+        for comment in comments {
+            let line = format!("\n`{}`", comment);
+            if content.len() + line.len() > MAX_MESSAGE_LENGTH {
+                content += "\n...";
+                break;
+            }
+            content += &line;
+        }
+    }
+
+    content
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    if !super::is_admin(interaction.member.as_deref()) {
+        interaction
+            .create_response(ctx, super::not_allowed("look up players"))
+            .await?;
+        return Ok(());
+    }
+
+    let net_id = interaction
+        .data
+        .options()
+        .into_iter()
+        .find_map(|option| match (option.name, option.value) {
+            ("netid", ResolvedValue::String(net_id)) => Some(net_id.trim().to_string()),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let profile = state
+        .storage
+        .get_player_profile(&net_id)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    let content = match profile {
+        Some(profile) => build_content(&profile),
+        None => format!("No sessions found for `{}`", net_id),
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("player")
+        .description("Shows everything a player has done across their sessions")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "netid", "The player's Net ID")
+                .required(true),
+        )
+}
@@ -1,14 +1,14 @@
-use futures::TryFutureExt;
-use serde::Serialize;
 use serenity::builder::*;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
-    let state = crate::SERVER_STATE
-        .get()
-        .ok_or(serenity::Error::Other("Failed to load server state"))?;
+use crate::ServerState;
 
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
     let config = state.read_config().ok_or(serenity::Error::Other(
         "Failed to acquire read lock on server state",
     ))?;
@@ -0,0 +1,89 @@
+use futures::TryFutureExt;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::database::StatsFilter;
+use crate::reporting::RetentionCohort;
+use crate::ServerState;
+
+fn format_rate(rate: Option<f64>) -> String {
+    rate.map(|rate| format!("{:.0}%", rate * 100.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn build_table(cohorts: &[RetentionCohort]) -> String {
+    let mut table = format!(
+        "{:<10} {:>7} {:>5} {:>5} {:>5}\n",
+        "Cohort", "Players", "D1", "D7", "D30"
+    );
+
+    for cohort in cohorts {
+        table += &format!(
+            "{:<10} {:>7} {:>5} {:>5} {:>5}\n",
+            cohort.date.format("%Y-%m-%d"),
+            cohort.players,
+            format_rate(cohort.d1),
+            format_rate(cohort.d7),
+            format_rate(cohort.d30),
+        );
+    }
+
+    table
+}
+
+async fn build_content(
+    state: &ServerState,
+    filter: &StatsFilter,
+) -> Result<String, serenity::Error> {
+    let cache_ttl_secs = state
+        .read_config()
+        .ok_or(serenity::Error::Other("Failed to read config"))?
+        .reporting
+        .cache_ttl_secs;
+
+    let cohorts = state
+        .storage
+        .get_retention_cohorts(filter, cache_ttl_secs)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    // Discord messages are limited to 2000 characters, keep the newest cohorts
+    let skip = cohorts.len().saturating_sub(40);
+    Ok(format!(
+        "Retention, {}: ```{}```",
+        filter,
+        build_table(&cohorts[skip..])
+    ))
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    let content = match super::stats_filter_from_options(&interaction.data.options()) {
+        Ok(filter) => build_content(state, &filter).await?,
+        Err(message) => message,
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new().content(content),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("retention")
+        .description("Shows D1/D7/D30 retention for each day's new players")
+        .set_options(super::stats_filter_options())
+}
@@ -0,0 +1,149 @@
+use futures::TryFutureExt;
+use serenity::builder::*;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::database::{SearchHit, SearchHitKind, SearchQuery};
+use crate::ServerState;
+
+// Discord messages are limited to 2000 characters
+const MAX_MESSAGE_LENGTH: usize = 1900;
+const MAX_RESULTS: u64 = 10;
+
+fn format_hit(hit: &SearchHit, session_url: &str) -> String {
+    let mut text = hit.text.replace(['`', '\n'], " ");
+    if text.chars().count() > 120 {
+        text = format!("{}...", text.chars().take(117).collect::<String>());
+    }
+
+    let date = hit
+        .time
+        .map(|time| time.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let kind = match hit.kind {
+        SearchHitKind::Feedback => "Feedback",
+        SearchHitKind::Session => "Session",
+    };
+
+    // Angle brackets stop discord from embedding every link
+    format!(
+        "{} {} `{}`\n<{}>\n",
+        date,
+        kind,
+        text,
+        session_url.replace("{id}", &hit.session)
+    )
+}
+
+// Returns an error message for the user when an option is invalid
+fn parse_query(options: &[ResolvedOption]) -> Result<SearchQuery, String> {
+    let parse_date = |date: &str| {
+        crate::utils::parse_date_param(date).ok_or(format!("Invalid date: `{}`", date))
+    };
+
+    let mut query = SearchQuery::default();
+    for option in options {
+        match (option.name, &option.value) {
+            ("query", ResolvedValue::String(text)) => query.text = text.to_string(),
+            ("from", ResolvedValue::String(from)) => query.from = Some(parse_date(from)?),
+            ("to", ResolvedValue::String(to)) => query.to = Some(parse_date(to)?),
+            _ => {}
+        }
+    }
+
+    if query.text.trim().is_empty() {
+        return Err("Search for at least one word".to_string());
+    }
+
+    Ok(query)
+}
+
+async fn build_content(
+    state: &ServerState,
+    query: &SearchQuery,
+) -> Result<String, serenity::Error> {
+    let session_url = state
+        .read_config()
+        .ok_or(serenity::Error::Other("Failed to read config"))?
+        .search
+        .session_url;
+
+    let hits = state
+        .storage
+        .search(query, MAX_RESULTS, 0)
+        .map_err(|err| {
+            eprintln!("Database error! {err:?}");
+            serenity::Error::Other("Database error")
+        })
+        .await?;
+
+    if hits.is_empty() {
+        return Ok(format!("Nothing matches `{}`", query.text));
+    }
+
+    let mut content = format!("Top matches for `{}`:\n", query.text);
+    for hit in &hits {
+        let line = format_hit(hit, &session_url);
+        if content.len() + line.len() > MAX_MESSAGE_LENGTH {
+            break;
+        }
+        content += &line;
+    }
+
+    Ok(content)
+}
+
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    if !super::is_admin(interaction.member.as_deref()) {
+        interaction
+            .create_response(ctx, super::not_allowed("search player data"))
+            .await?;
+        return Ok(());
+    }
+
+    let content = match parse_query(&interaction.data.options()) {
+        Ok(query) => build_content(state, &query).await?,
+        Err(message) => message,
+    };
+
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("search")
+        .description("Searches feedback comments and sessions")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "query",
+                "Words to look for, use \"quotes\" for a phrase",
+            )
+            .required(true),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "from",
+            "Only include sessions on or after this date (YYYY-MM-DD)",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "to",
+            "Only include sessions before this date (YYYY-MM-DD)",
+        ))
+}
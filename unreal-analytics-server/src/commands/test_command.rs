@@ -3,26 +3,34 @@ use serenity::builder::*;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(), serenity::Error> {
-    let state = crate::SERVER_STATE
-        .get()
-        .ok_or(serenity::Error::Other("Failed to load server state"))?;
+use crate::ServerState;
 
-    let player_stats = state
-        .db
-        .get_players_stats()
-        .map_err(|err| {
-            eprintln!("Database error! {err:?}");
-            serenity::Error::Other("Database error")
-        })
-        .await?;
+pub async fn run(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    state: &ServerState,
+) -> Result<(), serenity::Error> {
+    let content = match super::stats_filter_from_options(&interaction.data.options()) {
+        Ok(filter) => {
+            let player_stats = state
+                .storage
+                .get_players_stats(&filter)
+                .map_err(|err| {
+                    eprintln!("Database error! {err:?}");
+                    serenity::Error::Other("Database error")
+                })
+                .await?;
+
+            format!("Player stats, {}: ```{}```", filter, player_stats)
+        }
+        Err(message) => message,
+    };
 
     interaction
         .create_response(
             ctx,
             CreateInteractionResponse::Message(
-                CreateInteractionResponseMessage::new()
-                    .content(format!("Player stats: ```{}```", player_stats)),
+                CreateInteractionResponseMessage::new().content(content),
             ),
         )
         .await?;
@@ -31,5 +39,7 @@ pub async fn run(ctx: &Context, interaction: &CommandInteraction) -> Result<(),
 }
 
 pub fn register() -> CreateCommand {
-    CreateCommand::new("test_command").description("Asks some details about you")
+    CreateCommand::new("test_command")
+        .description("Asks some details about you")
+        .set_options(super::stats_filter_options())
 }
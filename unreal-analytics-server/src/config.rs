@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::process::exit;
 
@@ -8,11 +9,197 @@ pub struct DiscordConfig {
     pub notify_editor_sessions: bool,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LiveSessionConfig {
+    // Active sessions that haven't sent a heartbeat for this long are marked as abandoned
+    pub heartbeat_timeout_secs: u64,
+    // How often to look for abandoned sessions
+    pub sweep_interval_secs: u64,
+}
+
+// Where crash report attachments (logs, minidumps) are kept
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobStorage {
+    Disk,
+    Gridfs,
+}
+
+// Collection names, so environments can share a database without sharing data
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CollectionNames {
+    pub sessions: String,
+    pub live_sessions: String,
+    pub crashes: String,
+    pub crash_groups: String,
+    // GridFS bucket for crash attachments
+    pub crash_files: String,
+    pub events: String,
+    pub positions: String,
+    pub feedback: String,
+    pub report_cache: String,
+    // Per day, build, platform and country session counters
+    pub daily_rollups: String,
+    // The IPs that played on each day, build, platform and country, one document each
+    pub rollup_players: String,
+    // What each run of the retention job pruned
+    pub retention_reports: String,
+    // Which migrations have been applied, and whether the rollups are being rebuilt
+    pub migrations: String,
+    // Audit log of player data erasure requests
+    pub erasure_requests: String,
+    // NetIDs of players that turned telemetry off
+    pub opt_outs: String,
+    // Per day and build counts of sessions that weren't stored because of consent
+    pub anonymous_sessions: String,
+    // Idempotency keys of the sessions that were only counted, so retries aren't counted twice
+    pub anonymous_session_keys: String,
+    // The day each player was first seen on, per build, platform and country. Kept when their
+    // sessions are pruned, so retention cohorts don't need every session ever stored.
+    pub first_seen: String,
+    // The days each player played on, per build, platform and country. Kept when their sessions
+    // are pruned, so active player and retention reports don't change once they're pruned.
+    pub player_days: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DatabaseConfig {
+    pub name: String,
+    pub collections: CollectionNames,
+}
+
+// Where everything the server records is stored, see storage::Storage
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Mongo,
+    // Nothing is kept between restarts, for trying things out without a mongod
+    Memory,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CrashReportConfig {
+    pub blob_storage: BlobStorage,
+    // Directory attachments are written to when blob_storage is "disk"
+    pub storage_dir: String,
+}
+
+// World space area of a map that heatmaps cover, in unreal units
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct MapBounds {
+    pub min_x: f64,
+    pub max_x: f64,
+    pub min_y: f64,
+    pub max_y: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HeatmapConfig {
+    // Number of grid cells along the longest side of the map
+    pub resolution: u32,
+    // Maps without bounds here use the bounds of their recorded positions
+    pub maps: HashMap<String, MapBounds>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitlabConfig {
+    // Point this at a mock server to try things out without making real issues
+    pub base_url: String,
+    // Numeric id or "group/project" path of the project issues are made in
+    pub project_id: String,
+    pub feedback_labels: Vec<String>,
+    pub crash_labels: Vec<String>,
+    // Adds a "Create GitLab issue" button to session messages with feedback. Discord only allows
+    // buttons on webhooks owned by the bot's application.
+    pub session_message_button: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SearchConfig {
+    // Link to a session in search results, {id} is replaced with the session's _id
+    pub session_url: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    // Sessions older than this are archived to disk and deleted. They stay counted in the daily
+    // rollups. Feedback and crash records of pruned sessions are kept, they point at a session
+    // that is only in the archive from then on.
+    pub pie_session_days: u32,
+    pub game_session_days: u32,
+    // Where the gzipped JSON archives are written
+    pub archive_dir: String,
+    pub interval_secs: u64,
+}
+
+// How player IPs are stored in sessions
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpMode {
+    Raw,
+    // HMAC-SHA256 keyed with ip_hash_salt from the secrets
+    Hash,
+    // Only the /24 of IPv4 and the /48 of IPv6 addresses
+    Truncate,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PrivacyConfig {
+    // Unique players are counted by stored IP, so truncating counts networks instead of players
+    pub ip_mode: IpMode,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScrubRule {
+    // Recorded in the session's Redactions and used in the replacement
+    pub name: String,
+    pub pattern: String,
+}
+
+// Personal details players type into feedback, removed before sessions are stored or sent to
+// discord
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScrubbingConfig {
+    pub enabled: bool,
+    // Applied in order, so a rule doesn't see what an earlier one replaced
+    pub rules: Vec<ScrubRule>,
+    // Words or names to redact, one per line. Empty for none.
+    pub blocklist_file: String,
+    // What a match is replaced with, {rule} is replaced with the rule's name
+    pub replacement: String,
+    // Collectors besides the feedback collector that the game sends free text in
+    pub extra_collectors: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReportingConfig {
+    // How long computed report rows are reused for. Sessions uploaded late can still change old
+    // days, so this shouldn't be too long.
+    pub cache_ttl_secs: u64,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     pub block_http: bool,
     pub mongodb_connection_string: String,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
     pub discord_config: DiscordConfig,
+    pub live_sessions: LiveSessionConfig,
+    pub crash_reports: CrashReportConfig,
+    pub heatmaps: HeatmapConfig,
+    pub reporting: ReportingConfig,
+    pub retention: RetentionConfig,
+    pub privacy: PrivacyConfig,
+    pub scrubbing: ScrubbingConfig,
+    pub gitlab: GitlabConfig,
+    pub search: SearchConfig,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -24,9 +211,14 @@ pub struct Secrets {
 pub struct Keys {
     pub todolist_auth_key: String,
     pub cactus_auth_key: String,
+    // Sent as X-Admin-Key by operators for the admin routes, never put it in a game build. The
+    // admin routes are off while it's empty.
+    pub admin_key: String,
     pub discord_webhook: String,
     pub discord_token: String,
     pub gitlab_token: String,
+    // Key for hashed IPs and erasure audit records, changing it makes old hashes unmatchable
+    pub ip_hash_salt: String,
 }
 
 pub fn read_config() -> Config {
@@ -41,7 +233,7 @@ pub fn read_config() -> Config {
         Ok(d) => d,
         Err(e) => {
             eprintln!("Unable to load data from `{}`", filename);
-            eprintln!("Error: {}", e.to_string());
+            eprintln!("Error: {}", e);
             exit(1);
         }
     }
@@ -59,7 +251,7 @@ pub fn read_secrets() -> Secrets {
         Ok(d) => d,
         Err(e) => {
             eprintln!("Unable to load data from `{}`", filename);
-            eprintln!("Error: {}", e.to_string());
+            eprintln!("Error: {}", e);
             exit(1);
         }
     }
@@ -0,0 +1,359 @@
+use std::path::{Path, PathBuf};
+
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use rocket::fs::TempFile;
+use rocket::tokio::io::AsyncReadExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::{BlobStorage, CrashReportConfig};
+use crate::database::Database;
+
+// How many callstack frames go into the crash signature. Frames further down are usually the same
+// engine loop for every crash and only make unrelated crashes look different.
+const SIGNATURE_FRAMES: usize = 8;
+
+// The interesting parts of the CrashContext.runtime-xml file written by Unreal's crash reporter
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CrashContext {
+    #[serde(rename = "CrashGUID")]
+    pub crash_guid: Option<String>,
+    #[serde(rename = "CrashType")]
+    pub crash_type: Option<String>,
+    #[serde(rename = "ErrorMessage")]
+    pub error_message: Option<String>,
+    #[serde(rename = "CallStack")]
+    pub call_stack: Option<String>,
+    #[serde(rename = "GameName")]
+    pub game_name: Option<String>,
+    #[serde(rename = "EngineVersion")]
+    pub engine_version: Option<String>,
+    #[serde(rename = "BuildVersion")]
+    pub build_version: Option<String>,
+    #[serde(rename = "PlatformName")]
+    pub platform_name: Option<String>,
+}
+
+impl CrashContext {
+    pub fn parse(xml: &str) -> Result<Self, roxmltree::Error> {
+        let document = roxmltree::Document::parse(xml)?;
+
+        // Everything we care about lives in <RuntimeProperties>
+        let properties = document
+            .descendants()
+            .find(|node| node.has_tag_name("RuntimeProperties"))
+            .unwrap_or(document.root_element());
+
+        let property = |name: &str| {
+            properties
+                .children()
+                .find(|node| node.has_tag_name(name))
+                .and_then(|node| node.text())
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string)
+        };
+
+        Ok(CrashContext {
+            crash_guid: property("CrashGUID"),
+            crash_type: property("CrashType"),
+            error_message: property("ErrorMessage"),
+            call_stack: property("CallStack"),
+            game_name: property("GameName"),
+            engine_version: property("EngineVersion"),
+            build_version: property("BuildVersion"),
+            platform_name: property("PlatformName"),
+        })
+    }
+
+    // Returns the frames that identify this crash, with addresses and source locations removed so
+    // that the same crash on different machines and builds produces the same frames
+    pub fn signature_frames(&self) -> Vec<String> {
+        let call_stack = match &self.call_stack {
+            Some(call_stack) => call_stack,
+            None => return Vec::new(),
+        };
+
+        call_stack
+            .lines()
+            .map(|line| {
+                let line = line
+                    .split_whitespace()
+                    .filter(|token| !token.starts_with("0x"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                // Inlined frames are marked "[Inline Frame] Module!Function()". Whether something
+                // got inlined depends on the build configuration, so the marker is dropped.
+                let line = match line.strip_prefix('[').and_then(|line| line.split_once(']')) {
+                    Some((_, frame)) => frame.trim_start(),
+                    None => line.as_str(),
+                };
+
+                // Drop the source file, "Module!Function() [C:\path\File.cpp:123]"
+                line.split(" [").next().unwrap_or(line).to_string()
+            })
+            .filter(|frame| !frame.is_empty())
+            .take(SIGNATURE_FRAMES)
+            .collect()
+    }
+
+    // Hash used to group repeated crashes together. Falls back to the error message when there's
+    // no callstack, with numbers stripped since those are usually addresses or counts.
+    pub fn signature(&self) -> String {
+        let mut hasher = Sha256::new();
+
+        let frames = self.signature_frames();
+        if !frames.is_empty() {
+            hasher.update(frames.join("\n"));
+        } else {
+            let message = self.error_message.as_deref().unwrap_or("");
+            let message: String = message.chars().filter(|c| !c.is_ascii_digit()).collect();
+            hasher.update(self.crash_type.as_deref().unwrap_or(""));
+            hasher.update(message);
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AttachmentKind {
+    Context,
+    Log,
+    Minidump,
+}
+
+impl AttachmentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttachmentKind::Context => "context",
+            AttachmentKind::Log => "log",
+            AttachmentKind::Minidump => "minidump",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            AttachmentKind::Context => "CrashContext.runtime-xml",
+            AttachmentKind::Log => "Game.log",
+            AttachmentKind::Minidump => "UEMinidump.dmp",
+        }
+    }
+}
+
+// Where an attachment ended up, stored alongside the crash metadata
+pub fn attachment_document(
+    kind: AttachmentKind,
+    size: u64,
+    storage: &str,
+    location: Bson,
+) -> Document {
+    doc! {
+        "Kind": kind.as_str(),
+        "FileName": kind.file_name(),
+        "Size": size as i64,
+        "Storage": storage,
+        "Location": location,
+    }
+}
+
+pub async fn read_temp_file(file: &TempFile<'_>) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(file.len() as usize);
+    file.open().await?.read_to_end(&mut bytes).await?;
+    Ok(bytes)
+}
+
+fn crash_dir(config: &CrashReportConfig, crash_id: &ObjectId) -> PathBuf {
+    Path::new(&config.storage_dir).join(crash_id.to_hex())
+}
+
+// Saves an uploaded file to the crash's directory and returns the attachment document
+pub async fn store_on_disk(
+    config: &CrashReportConfig,
+    crash_id: &ObjectId,
+    kind: AttachmentKind,
+    file: &mut TempFile<'_>,
+) -> Result<Document, Box<dyn std::error::Error + Send + Sync>> {
+    let size = file.len();
+    let dir = crash_dir(config, crash_id);
+    rocket::tokio::fs::create_dir_all(&dir).await?;
+
+    let path = dir.join(kind.file_name());
+    file.move_copy_to(&path).await?;
+
+    Ok(attachment_document(
+        kind,
+        size,
+        "disk",
+        Bson::String(path.to_string_lossy().to_string()),
+    ))
+}
+
+// Saves an uploaded file to the configured blob storage and returns the attachment document
+pub async fn store_attachment(
+    db: &Database,
+    config: &CrashReportConfig,
+    crash_id: &ObjectId,
+    kind: AttachmentKind,
+    file: &mut TempFile<'_>,
+) -> Result<Document, Box<dyn std::error::Error + Send + Sync>> {
+    match config.blob_storage {
+        BlobStorage::Disk => store_on_disk(config, crash_id, kind, file).await,
+        BlobStorage::Gridfs => {
+            let size = file.len();
+            let bytes = read_temp_file(file).await?;
+            let bucket = db.crash_files_bucket();
+
+            let file_name = format!("{}/{}", crash_id.to_hex(), kind.file_name());
+            let id = bucket
+                .upload_from_futures_0_3_reader(file_name, futures::io::Cursor::new(bytes), None)
+                .await?;
+
+            Ok(attachment_document(
+                kind,
+                size,
+                "gridfs",
+                Bson::ObjectId(id),
+            ))
+        }
+    }
+}
+
+// Removes a file stored by store_on_disk, and the crash's directory once it's empty
+pub async fn delete_from_disk(path: &str) -> std::io::Result<()> {
+    match rocket::tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if let Some(dir) = Path::new(path).parent() {
+        let _ = rocket::tokio::fs::remove_dir(dir).await;
+    }
+
+    Ok(())
+}
+
+// Removes the stored files of a crash's attachments, wherever they were stored
+pub async fn delete_attachments(
+    db: &Database,
+    crash: &Document,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let attachments = crash.get_array("Attachments").into_iter().flatten();
+    for attachment in attachments.filter_map(|attachment| attachment.as_document()) {
+        match (attachment.get_str("Storage"), attachment.get("Location")) {
+            (Ok("disk"), Some(Bson::String(path))) => delete_from_disk(path).await?,
+            (Ok("gridfs"), Some(id)) => db.crash_files_bucket().delete(id.clone()).await?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(call_stack: &str) -> CrashContext {
+        CrashContext {
+            call_stack: Some(call_stack.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_runtime_properties() {
+        let context = CrashContext::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <FGenericCrashContext>
+                <RuntimeProperties>
+                    <CrashGUID>UECC-Windows-1234</CrashGUID>
+                    <ErrorMessage>  Assertion failed: Index != INDEX_NONE  </ErrorMessage>
+                    <CallStack></CallStack>
+                    <BuildVersion>++Game+Main-CL-42</BuildVersion>
+                </RuntimeProperties>
+            </FGenericCrashContext>"#,
+        )
+        .unwrap();
+
+        assert_eq!(context.crash_guid.as_deref(), Some("UECC-Windows-1234"));
+        assert_eq!(
+            context.error_message.as_deref(),
+            Some("Assertion failed: Index != INDEX_NONE")
+        );
+        assert_eq!(context.call_stack, None);
+        assert_eq!(context.build_version.as_deref(), Some("++Game+Main-CL-42"));
+        assert!(CrashContext::parse("<Unclosed>").is_err());
+    }
+
+    #[test]
+    fn frames_leave_out_addresses_and_source_files() {
+        let context = context(
+            "0x00007ffb1ac5a799 KERNELBASE.dll!UnknownFunction []\n\
+             Game-Core.dll!FDebug::AssertFailed() [C:\\Build\\Core\\AssertionMacros.cpp:417]\n\
+             \n\
+             Game.exe!AGameMode::Tick(float) [C:\\Build\\Game\\GameMode.cpp:88]",
+        );
+
+        assert_eq!(
+            context.signature_frames(),
+            vec![
+                "KERNELBASE.dll!UnknownFunction",
+                "Game-Core.dll!FDebug::AssertFailed()",
+                "Game.exe!AGameMode::Tick(float)",
+            ]
+        );
+    }
+
+    #[test]
+    fn inlined_frames_match_called_ones() {
+        let inlined = context(
+            "[Inline Frame] Game.exe!UInventory::Remove() [C:\\Build\\Inventory.cpp:12]\n\
+             0x00007ff6 Game.exe!AGameMode::Tick(float) [C:\\Build\\GameMode.cpp:88]",
+        );
+        let called = context(
+            "0x00007ff7 Game.exe!UInventory::Remove() [D:\\Other\\Inventory.cpp:12]\n\
+             0x00007ff8 Game.exe!AGameMode::Tick(float) [D:\\Other\\GameMode.cpp:90]",
+        );
+
+        assert_eq!(inlined.signature_frames(), called.signature_frames());
+        assert_eq!(inlined.signature(), called.signature());
+    }
+
+    #[test]
+    fn signature_only_uses_the_top_frames() {
+        let frames: Vec<String> = (0..SIGNATURE_FRAMES + 2)
+            .map(|i| format!("Game.exe!Function{}()", i))
+            .collect();
+        let mut other = frames.clone();
+        other[SIGNATURE_FRAMES + 1] = "Game.exe!Different()".to_string();
+
+        assert_eq!(
+            context(&frames.join("\n")).signature_frames().len(),
+            SIGNATURE_FRAMES
+        );
+        assert_eq!(
+            context(&frames.join("\n")).signature(),
+            context(&other.join("\n")).signature()
+        );
+    }
+
+    #[test]
+    fn signature_without_call_stack_ignores_numbers() {
+        let crash = |message: &str| CrashContext {
+            crash_type: Some("Assert".to_string()),
+            error_message: Some(message.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            crash("Array index 12 out of bounds 4").signature(),
+            crash("Array index 3 out of bounds 2").signature()
+        );
+        assert_ne!(
+            crash("Array index 12 out of bounds 4").signature(),
+            crash("Null pointer").signature()
+        );
+        assert_eq!(CrashContext::default().signature(), context("").signature());
+    }
+}
@@ -1,102 +1,682 @@
 use futures::executor::block_on;
-use futures::StreamExt;
-use mongodb::results::InsertOneResult;
+use std::collections::HashMap;
+
+use futures::TryStreamExt;
+use mongodb::error::{BulkWriteFailure, ErrorKind, WriteFailure};
+use mongodb::gridfs::GridFsBucket;
+use mongodb::options::{
+    AggregateOptions, FindOneAndUpdateOptions, FindOptions, GridFsBucketOptions, IndexOptions,
+    InsertManyOptions, ReturnDocument, UpdateOptions,
+};
 use mongodb::{
-    bson::{doc, Document},
+    bson::{doc, oid::ObjectId, Bson, Document},
     options::ClientOptions,
-    Client, Collection,
+    Client, IndexModel,
 };
-use rocket::http::ext::IntoCollection;
-use rocket::serde::json::Value;
 
-use chrono::{NaiveDateTime, TimeDelta, Utc};
-use rocket::Data;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 
+use crate::cloudflare::CloudflareInfo;
 use crate::config;
+use crate::config::MapBounds;
+use crate::feedback::{self, FeedbackItem, FeedbackQuery, FeedbackUpdate};
+use crate::funnel::{FunnelEvent, FunnelQuery, FunnelStepResult};
+use crate::gameplay_event::GameplayEvent;
+use crate::gitlab::GitlabIssue;
+use crate::heatmap::{HeatmapGrid, PositionSample};
+use crate::reporting;
+use crate::rollups;
+use crate::session::{
+    AnalyticsSession, LiveSessionEvent, FEEDBACK_COLLECTOR, SESSION_COLLECTOR,
+    UNREAL_DATE_TIME_FORMAT,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Database {
     pub client: mongodb::Client,
     pub database: mongodb::Database,
+    pub collections: config::CollectionNames,
+}
+
+// Error code mongo returns when an insert violates a unique index
+const DUPLICATE_KEY_ERROR: i32 = 11000;
+
+// How long filing a GitLab issue is claimed for. A server that died while filing one leaves its
+// claim behind, after this long the issue can be filed again.
+pub const GITLAB_CLAIM_EXPIRY: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddSessionResult {
+    Inserted(ObjectId),
+    // The session was already uploaded with the same idempotency key, this is its original id
+    Duplicate(ObjectId),
+}
+
+impl AddSessionResult {
+    pub fn id(&self) -> ObjectId {
+        match self {
+            AddSessionResult::Inserted(id) | AddSessionResult::Duplicate(id) => *id,
+        }
+    }
+}
+
+// State of a session in the live_sessions collection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LiveSessionStatus {
+    Active,
+    Ended,
+    // Stopped sending heartbeats without ending, the game probably crashed or was killed
+    Abandoned,
+}
+
+impl LiveSessionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LiveSessionStatus::Active => "active",
+            LiveSessionStatus::Ended => "ended",
+            LiveSessionStatus::Abandoned => "abandoned",
+        }
+    }
+}
+
+// Every crash with the same callstack signature, see crash::CrashContext::signature
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CrashGroup {
+    #[serde(rename = "Signature")]
+    pub signature: String,
+    #[serde(rename = "Count")]
+    pub count: i64,
+    #[serde(
+        rename = "FirstSeen",
+        deserialize_with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime::deserialize"
+    )]
+    pub first_seen: chrono::DateTime<Utc>,
+    #[serde(
+        rename = "LastSeen",
+        deserialize_with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime::deserialize"
+    )]
+    pub last_seen: chrono::DateTime<Utc>,
+    #[serde(rename = "CrashType", default)]
+    pub crash_type: Option<String>,
+    #[serde(rename = "ErrorMessage", default)]
+    pub error_message: Option<String>,
+    #[serde(rename = "SignatureFrames", default)]
+    pub signature_frames: Vec<String>,
+    #[serde(rename = "BuildVersions", default)]
+    pub build_versions: Vec<String>,
+    #[serde(
+        rename = "LastCrashID",
+        serialize_with = "mongodb::bson::serde_helpers::serialize_object_id_as_hex_string"
+    )]
+    pub last_crash_id: ObjectId,
+    #[serde(
+        rename = "GitlabIssue",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gitlab_issue: Option<GitlabIssue>,
+}
+
+// Feedback left by a player in one session
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerFeedback {
+    pub session_id: Option<String>,
+    pub time: Option<chrono::DateTime<Utc>>,
+    pub comments: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerSessionSummary {
+    pub id: String,
+    pub session_id: Option<String>,
+    pub start_time: Option<chrono::DateTime<Utc>>,
+    pub play_time_secs: Option<i64>,
+    pub build_version: Option<String>,
+    pub platform: Option<String>,
+    pub country_code: Option<String>,
+    pub is_play_in_editor_session: bool,
+}
+
+// Everything a player has done across all of their sessions
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerProfile {
+    pub net_id: String,
+    pub is_steam_player: bool,
+    pub first_seen: Option<chrono::DateTime<Utc>>,
+    pub last_seen: Option<chrono::DateTime<Utc>>,
+    pub sessions: u64,
+    pub pie_sessions: u64,
+    // Game sessions only
+    pub total_play_time_secs: i64,
+    pub countries: Vec<String>,
+    pub builds: Vec<String>,
+    pub platforms: Vec<String>,
+    pub feedback: Vec<PlayerFeedback>,
+    // Newest first
+    pub recent_sessions: Vec<PlayerSessionSummary>,
+}
+
+// Number of sessions listed in PlayerProfile::recent_sessions
+pub const PLAYER_RECENT_SESSIONS: i64 = 20;
+
+pub fn get_chrono(document: &Document, key: &str) -> Option<chrono::DateTime<Utc>> {
+    document.get_datetime(key).ok().map(|date| date.to_chrono())
+}
+
+pub fn get_strings(document: &Document, key: &str) -> Vec<String> {
+    let mut strings: Vec<String> = document
+        .get_array(key)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|value| value.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    strings.sort();
+    strings
+}
+
+// Narrows down which gameplay events a query looks at
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    pub name: Option<String>,
+    pub build: Option<String>,
+    pub from: Option<chrono::DateTime<Utc>>,
+    pub to: Option<chrono::DateTime<Utc>>,
+}
+
+impl EventQuery {
+    pub fn to_match_document(&self) -> Document {
+        let mut filter = doc! {};
+
+        if let Some(name) = &self.name {
+            filter.insert("Name", name);
+        }
+
+        if let Some(build) = &self.build {
+            filter.insert("Build", build);
+        }
+
+        let mut timestamp = doc! {};
+        if let Some(from) = self.from {
+            timestamp.insert("$gte", from);
+        }
+        if let Some(to) = self.to {
+            timestamp.insert("$lt", to);
+        }
+        if !timestamp.is_empty() {
+            filter.insert("Timestamp", timestamp);
+        }
+
+        filter
+    }
+}
+
+// Most results a search can page through
+pub const MAX_SEARCH_RESULTS: u64 = 1000;
+
+// Session fields in the sessions text index. Feedback comments are searched through the feedback
+// collection instead.
+const SESSION_SEARCH_FIELDS: [&str; 5] = [
+    "BP_SessionAnalyicsCollector_C.SessionID",
+    "BP_SessionAnalyicsCollector_C.BuildVersion",
+    "BP_SessionAnalyicsCollector_C.Platform",
+    "BP_SessionAnalyicsCollector_C.CountryName",
+    "BP_SessionAnalyicsCollector_C.PlayerControllerData.NetID",
+];
+
+// Full text search over feedback comments and session fields
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    // Words to look for, "quoted phrases" and -negated words work like mongo $text
+    pub text: String,
+    pub from: Option<chrono::DateTime<Utc>>,
+    pub to: Option<chrono::DateTime<Utc>>,
+}
+
+impl SearchQuery {
+    fn to_match_document(&self, time_field: &str) -> Document {
+        let mut filter = doc! {"$text": {"$search": &self.text}};
+
+        let mut time = doc! {};
+        if let Some(from) = self.from {
+            time.insert("$gte", from);
+        }
+        if let Some(to) = self.to {
+            time.insert("$lt", to);
+        }
+        if !time.is_empty() {
+            filter.insert(time_field, time);
+        }
+
+        filter
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchHitKind {
+    Feedback,
+    Session,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+    pub kind: SearchHitKind,
+    // _id of the session the hit belongs to
+    pub session: String,
+    // _id of the comment for feedback hits
+    pub feedback: Option<String>,
+    // The comment, or a summary of the session's searchable fields
+    pub text: String,
+    pub time: Option<chrono::DateTime<Utc>>,
+    pub score: f64,
+}
+
+// A hit for a comment from the feedback collection
+pub fn feedback_search_hit(comment: &Document, score: f64) -> Option<SearchHit> {
+    Some(SearchHit {
+        kind: SearchHitKind::Feedback,
+        session: comment.get_object_id("SessionObjectID").ok()?.to_hex(),
+        feedback: comment.get_object_id("_id").ok().map(|id| id.to_hex()),
+        text: comment.get_str("Comment").unwrap_or_default().to_string(),
+        time: get_chrono(comment, "SessionTime"),
+        score,
+    })
+}
+
+// A hit for a stored session, its text is the searchable session fields
+pub fn session_search_hit(session: &Document, score: f64) -> Option<SearchHit> {
+    let collector = session.get_document(SESSION_COLLECTOR).ok()?;
+    let net_ids = collector
+        .get_array("PlayerControllerData")
+        .map(|player_controllers| {
+            player_controllers
+                .iter()
+                .filter_map(|player_controller| {
+                    player_controller.as_document()?.get_str("NetID").ok()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut text: Vec<&str> = ["SessionID", "BuildVersion", "Platform", "CountryName"]
+        .iter()
+        .filter_map(|field| collector.get_str(field).ok())
+        .collect();
+    text.extend(net_ids);
+
+    Some(SearchHit {
+        kind: SearchHitKind::Session,
+        session: session.get_object_id("_id").ok()?.to_hex(),
+        feedback: None,
+        text: text.join(" / "),
+        time: get_chrono(collector, "StartTime"),
+        score,
+    })
+}
+
+// What to group gameplay events by when counting them
+#[derive(Debug, Clone)]
+pub enum EventGrouping {
+    Name,
+    Build,
+    // The value of a property, see gameplay_event::is_valid_property_name
+    Property(String),
+}
+
+impl EventGrouping {
+    pub fn field_path(&self) -> String {
+        match self {
+            EventGrouping::Name => "$Name".to_string(),
+            EventGrouping::Build => "$Build".to_string(),
+            EventGrouping::Property(property) => format!("$Properties.{}", property),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EventCount {
+    pub key: rocket::serde::json::Value,
+    pub count: i64,
+}
+
+// Frame time and memory numbers for one build and hardware class
+#[derive(Serialize, Debug, Clone)]
+pub struct PerformanceStats {
+    pub build_version: Option<String>,
+    pub hardware_class: Option<String>,
+    pub sessions: i64,
+    pub frames: i64,
+    pub p50_frame_time_ms: Option<f64>,
+    pub p95_frame_time_ms: Option<f64>,
+    pub p99_frame_time_ms: Option<f64>,
+    pub hitches_per_session: f64,
+    pub avg_peak_memory_mb: Option<f64>,
+    pub max_peak_memory_mb: Option<f64>,
+}
+
+// (build version, hardware class)
+type PerformanceGroup = (Option<String>, Option<String>);
+
+// Returns the upper bound of the bucket the given percentile (0-1) falls in. `buckets` is a list of
+// (upper bound, count) sorted by upper bound.
+pub fn histogram_percentile(buckets: &[(f64, i64)], percentile: f64) -> Option<f64> {
+    let total: i64 = buckets.iter().map(|(_, count)| count).sum();
+    if total <= 0 {
+        return None;
+    }
+
+    let target = (total as f64 * percentile).ceil().max(1.0) as i64;
+    let mut cumulative = 0;
+    for (upper_bound, count) in buckets {
+        cumulative += count;
+        if cumulative >= target {
+            return Some(*upper_bound);
+        }
+    }
+
+    buckets.last().map(|(upper_bound, _)| *upper_bound)
+}
+
+// Narrows stats down to a subset of sessions, like a single playtest build or the last week.
+// Every field is optional, an empty filter matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct StatsFilter {
+    pub build_version: Option<String>,
+    pub platform: Option<String>,
+    // Sessions that started in [from, to)
+    pub from: Option<chrono::DateTime<Utc>>,
+    pub to: Option<chrono::DateTime<Utc>>,
+    // true for PIE sessions only, false for game sessions only
+    pub pie: Option<bool>,
+    // ISO country code as sent by cloudflare
+    pub country: Option<String>,
+    // true for players logged into steam only, false for everyone else
+    pub steam: Option<bool>,
+}
+
+impl StatsFilter {
+    // Filter for documents in the sessions collection
+    pub fn session_filter(&self) -> Document {
+        let mut filter = Document::new();
+        if let Some(build_version) = &self.build_version {
+            filter.insert("BP_SessionAnalyicsCollector_C.BuildVersion", build_version);
+        }
+        if let Some(platform) = &self.platform {
+            filter.insert("BP_SessionAnalyicsCollector_C.Platform", platform);
+        }
+
+        let mut start_time = doc! {};
+        if let Some(from) = self.from {
+            start_time.insert("$gte", from);
+        }
+        if let Some(to) = self.to {
+            start_time.insert("$lt", to);
+        }
+        if !start_time.is_empty() {
+            filter.insert("BP_SessionAnalyicsCollector_C.StartTime", start_time);
+        }
+
+        if let Some(pie) = self.pie {
+            filter.insert("BP_SessionAnalyicsCollector_C.IsPlayInEditorSession", pie);
+        }
+        if let Some(country) = &self.country {
+            filter.insert(
+                "BP_SessionAnalyicsCollector_C.CountryCode",
+                country.to_uppercase(),
+            );
+        }
+        // Same check as AnalyticsSession::is_steam_session
+        if let Some(steam) = self.steam {
+            filter.insert(
+                "BP_SessionAnalyicsCollector_C.PlayerControllerData.0.SteamAnalyticsData",
+                doc! {"$exists": steam},
+            );
+        }
+        filter
+    }
+
+    // Filter for documents in the live_sessions collection. Live sessions don't know about steam
+    // and are always happening now, so those parts of the filter are left out.
+    pub fn live_session_filter(&self) -> Document {
+        let mut filter = Document::new();
+        if let Some(build_version) = &self.build_version {
+            filter.insert("BuildVersion", build_version);
+        }
+        if let Some(platform) = &self.platform {
+            filter.insert("Platform", platform);
+        }
+        if let Some(pie) = self.pie {
+            filter.insert("IsPlayInEditorSession", pie);
+        }
+        if let Some(country) = &self.country {
+            filter.insert("CountryCode", country.to_uppercase());
+        }
+        filter
+    }
+}
+
+impl std::fmt::Display for StatsFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(build_version) = &self.build_version {
+            parts.push(format!("Build: {}", build_version));
+        }
+        if let Some(platform) = &self.platform {
+            parts.push(format!("Platform: {}", platform));
+        }
+        if let Some(from) = self.from {
+            parts.push(format!("From: {}", from.format("%Y-%m-%d %H:%M")));
+        }
+        if let Some(to) = self.to {
+            parts.push(format!("To: {}", to.format("%Y-%m-%d %H:%M")));
+        }
+        if let Some(pie) = self.pie {
+            parts.push(if pie { "PIE only" } else { "Game only" }.to_string());
+        }
+        if let Some(country) = &self.country {
+            parts.push(format!("Country: {}", country.to_uppercase()));
+        }
+        if let Some(steam) = self.steam {
+            parts.push(
+                if steam {
+                    "Steam only"
+                } else {
+                    "Non-Steam only"
+                }
+                .to_string(),
+            );
+        }
+
+        if parts.is_empty() {
+            write!(f, "All sessions")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct PlayerStats {
     pub pie_sessions: u64,
     pub game_sessions: u64,
     pub unique_players: u64, // Number of unique IPs
+    #[serde(rename = "avg_play_time_secs", serialize_with = "serialize_seconds")]
     pub avg_play_time: chrono::TimeDelta,
+    pub currently_playing: u64, // Game sessions that are still sending heartbeats
+    pub play_time: PlayTimeDistribution,
+    // Sessions only counted because the player turned telemetry off, see opt_outs. None when the
+    // filter needs something that isn't kept for them.
+    pub anonymous_sessions: Option<u64>,
+}
+
+// Percentiles of game session play time that get worked out
+pub const PLAY_TIME_PERCENTILES: [u32; 6] = [25, 50, 75, 90, 95, 99];
+// Histogram bucket edges in seconds, the last bucket holds everything longer
+pub const PLAY_TIME_BUCKETS: [i64; 10] = [0, 60, 300, 600, 900, 1800, 3600, 7200, 14400, 28800];
+// Share of the shortest and longest sessions left out of the trimmed average. Sessions left idle
+// for hours drag the plain average way up.
+pub const PLAY_TIME_TRIM: f64 = 0.05;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayTimePercentile {
+    pub percentile: u32,
+    pub secs: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayTimeBucket {
+    pub min_secs: i64,
+    // None for the last bucket
+    pub max_secs: Option<i64>,
+    pub count: u64,
+}
+
+// How long game sessions last
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PlayTimeDistribution {
+    pub sessions: u64,
+    pub mean_secs: Option<f64>,
+    // Mean without the shortest and longest PLAY_TIME_TRIM of sessions
+    pub trimmed_mean_secs: Option<f64>,
+    pub percentiles: Vec<PlayTimePercentile>,
+    pub histogram: Vec<PlayTimeBucket>,
+}
+
+impl PlayTimeDistribution {
+    pub fn percentile(&self, percentile: u32) -> Option<chrono::TimeDelta> {
+        self.percentiles
+            .iter()
+            .find(|p| p.percentile == percentile)
+            .map(|p| chrono::TimeDelta::milliseconds((p.secs * 1000.0) as i64))
+    }
+
+    pub fn trimmed_mean(&self) -> Option<chrono::TimeDelta> {
+        self.trimmed_mean_secs
+            .map(|secs| chrono::TimeDelta::milliseconds((secs * 1000.0) as i64))
+    }
+}
+
+fn serialize_seconds<S>(time_delta: &chrono::TimeDelta, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_i64(time_delta.num_seconds())
 }
 
 impl std::fmt::Display for PlayerStats {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let duration = |duration: Option<chrono::TimeDelta>| {
+            duration
+                .map(|duration| crate::utils::session_duration_to_string(&duration))
+                .unwrap_or_else(|| "-".to_string())
+        };
+
         write!(
             f,
-            "PIE Sessions: {}\nGame Sessions: {}\nUnique Players: {}\nAverage Play Time: {}",
+            "PIE Sessions: {}\nGame Sessions: {}\nUnique Players: {}\nAverage Play Time: {}\nTrimmed Average Play Time: {}\nMedian Play Time: {}\n90th Percentile Play Time: {}\nCurrently Playing: {}\nAnonymous Sessions: {}",
             self.pie_sessions,
             self.game_sessions,
             self.unique_players,
-            crate::utils::session_duration_to_string(&self.avg_play_time)
+            crate::utils::session_duration_to_string(&self.avg_play_time),
+            duration(self.play_time.trimmed_mean()),
+            duration(self.play_time.percentile(50)),
+            duration(self.play_time.percentile(90)),
+            self.currently_playing,
+            self.anonymous_sessions
+                .map(|sessions| sessions.to_string())
+                .unwrap_or_else(|| "-".to_string())
         )
     }
 }
 
-pub async fn fixup_database(database: &Database) -> mongodb::error::Result<()> {
-    let collection = database.database.collection::<Document>("sessions");
-    let mut cursor = collection.find(None, None).await?;
+// Converts a session into the document stored in the sessions collection
+pub fn session_to_document(session: &AnalyticsSession) -> mongodb::error::Result<Document> {
+    let mut document = mongodb::bson::to_document(session)?;
 
-    while cursor.advance().await? {
-        let mut document = cursor.deserialize_current()?;
-        match (
-            convert_date_time(&mut document, "StartTime"),
-            convert_date_time(&mut document, "EndTime"),
-        ) {
-            (Some(start_time), Some(end_time)) => {
-                let id = document.get_object_id("_id").unwrap();
-                let filter = doc! {"_id": id};
-                let update = doc! {"$set": doc!{"BP_SessionAnalyicsCollector_C.StartTime": start_time, "BP_SessionAnalyicsCollector_C.EndTime": end_time}};
+    convert_date_time(&mut document, "StartTime");
+    convert_date_time(&mut document, "EndTime");
 
-                collection.update_one(filter, update, None).await?;
-            }
-            _ => return Ok(()),
-        }
-    }
+    Ok(document)
+}
+
+// Error codes mongo returns when creating an index that clashes with an existing one
+const INDEX_CONFLICT_ERRORS: [i32; 2] = [85, 86];
 
-    Ok(())
+fn is_index_conflict_error(error: &mongodb::error::Error) -> bool {
+    matches!(&*error.kind, ErrorKind::Command(command_error) if INDEX_CONFLICT_ERRORS.contains(&command_error.code))
 }
 
-// Converts a document's date time from a string to a datetime
-fn convert_date_time(document: &mut Document, field_name: &str) -> Option<chrono::DateTime<Utc>> {
-    if let Ok(session_obj) = document.get_document_mut("BP_SessionAnalyicsCollector_C") {
-        if let Ok(time_str) = session_obj.get_str(field_name) {
-            if let Some(time_date) =
-                NaiveDateTime::parse_from_str(time_str, "%Y.%m.%d-%H.%M.%S").ok()
-            {
-                session_obj.insert(field_name, time_date.and_utc());
-                return Some(time_date.and_utc());
-            }
-        }
+// The name an index gets, which is the one from its options or else "Field_1_Other_-1" like mongo
+// makes up
+fn index_name(index: &IndexModel) -> String {
+    if let Some(name) = index
+        .options
+        .as_ref()
+        .and_then(|options| options.name.clone())
+    {
+        return name;
     }
 
-    None
+    index
+        .keys
+        .iter()
+        .map(|(field, direction)| match direction {
+            mongodb::bson::Bson::String(kind) => format!("{}_{}", field, kind),
+            direction => format!("{}_{}", field, direction),
+        })
+        .collect::<Vec<_>>()
+        .join("_")
 }
 
-fn get_time(document: &Document, field_name: &str) -> Option<chrono::DateTime<Utc>> {
-    if let Ok(session_obj) = document.get_document("BP_SessionAnalyicsCollector_C") {
-        if let Ok(date_time) = session_obj.get_datetime(field_name) {
-            return Some(date_time.clone().into());
-        }
+// Reads a number out of an aggregation result, which can come back as any of the numeric bson
+// types depending on how big it got
+pub fn get_number(document: &Document, key: &str) -> Option<i64> {
+    match document.get(key)? {
+        mongodb::bson::Bson::Int32(value) => Some(*value as i64),
+        mongodb::bson::Bson::Int64(value) => Some(*value),
+        mongodb::bson::Bson::Double(value) => Some(*value as i64),
+        _ => None,
     }
+}
 
-    None
+pub fn is_duplicate_key_error(error: &mongodb::error::Error) -> bool {
+    matches!(
+        &*error.kind,
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY_ERROR
+    )
 }
 
-fn get_time_delta(document: &Document) -> Option<chrono::TimeDelta> {
-    if let (Some(start_time), Some(end_time)) = (
-        get_time(document, "StartTime"),
-        get_time(document, "EndTime"),
-    ) {
-        return Some(end_time - start_time);
+// True when every failed insert of an insert_many was a duplicate key
+fn is_only_duplicate_keys_error(error: &mongodb::error::Error) -> bool {
+    match &*error.kind {
+        ErrorKind::BulkWrite(BulkWriteFailure {
+            write_errors: Some(write_errors),
+            write_concern_error: None,
+            ..
+        }) => write_errors
+            .iter()
+            .all(|write_error| write_error.code == DUPLICATE_KEY_ERROR),
+        _ => false,
+    }
+}
+
+// Converts a document's date time from a string to a datetime
+pub fn convert_date_time(
+    document: &mut Document,
+    field_name: &str,
+) -> Option<chrono::DateTime<Utc>> {
+    if let Ok(session_obj) = document.get_document_mut(SESSION_COLLECTOR) {
+        if let Ok(time_str) = session_obj.get_str(field_name) {
+            if let Ok(time_date) = NaiveDateTime::parse_from_str(time_str, UNREAL_DATE_TIME_FORMAT)
+            {
+                session_obj.insert(field_name, time_date.and_utc());
+                return Some(time_date.and_utc());
+            }
+        }
     }
 
     None
@@ -116,52 +696,1442 @@ impl Database {
         }
     }
 
-    pub async fn add_session(&self, session: &Value) -> mongodb::error::Result<InsertOneResult> {
-        let collection = self.database.collection::<Document>("sessions");
-        let mut document = mongodb::bson::to_document(&mongodb::bson::to_bson(session)?)?;
+    // Every index the server relies on, as (collection, index). Indexes without a name get the
+    // name mongo would give them.
+    pub fn declared_indexes(&self) -> Vec<(String, IndexModel)> {
+        let names = &self.collections;
+        let index = |keys: Document| IndexModel::builder().keys(keys).build();
+        let unique = |keys: Document| {
+            IndexModel::builder()
+                .keys(keys)
+                .options(IndexOptions::builder().unique(true).build())
+                .build()
+        };
+        // Mongo only allows one text index per collection
+        let search = |keys: Document| {
+            IndexModel::builder()
+                .keys(keys)
+                .options(IndexOptions::builder().name("Search".to_string()).build())
+                .build()
+        };
+
+        let mut session_text_keys = doc! {};
+        for field in SESSION_SEARCH_FIELDS {
+            session_text_keys.insert(field, "text");
+        }
+
+        let sessions = vec![
+            // Only sessions that were uploaded with a key take part in the uniqueness check
+            IndexModel::builder()
+                .keys(doc! {"IdempotencyKey": 1})
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! {"IdempotencyKey": {"$exists": true}})
+                        .build(),
+                )
+                .build(),
+            // Sessions the retention job is part way through pruning, see retention::prune_sessions
+            IndexModel::builder()
+                .keys(doc! {"PruneBatch": 1})
+                .options(IndexOptions::builder().sparse(true).build())
+                .build(),
+            // Used by StatsFilter
+            index(doc! {
+                "BP_SessionAnalyicsCollector_C.BuildVersion": 1,
+                "BP_SessionAnalyicsCollector_C.Platform": 1,
+            }),
+            index(doc! {"BP_SessionAnalyicsCollector_C.Platform": 1}),
+            index(doc! {"BP_SessionAnalyicsCollector_C.Changelist": 1}),
+            index(doc! {"BP_SessionAnalyicsCollector_C.StartTime": 1}),
+            index(doc! {
+                "BP_SessionAnalyicsCollector_C.IsPlayInEditorSession": 1,
+                "BP_SessionAnalyicsCollector_C.StartTime": 1,
+            }),
+            // Used by unique player counts
+            index(doc! {"BP_SessionAnalyicsCollector_C.ip": 1}),
+            // Used by get_player_profile
+            index(doc! {"BP_SessionAnalyicsCollector_C.PlayerControllerData.NetID": 1}),
+            search(session_text_keys),
+        ];
 
-        convert_date_time(&mut document, "StartTime");
-        convert_date_time(&mut document, "EndTime");
+        let live_sessions = vec![
+            unique(doc! {"SessionID": 1}),
+            index(doc! {"Status": 1, "LastHeartbeat": 1}),
+        ];
 
-        collection.insert_one(document, None).await
+        let crash_groups = vec![unique(doc! {"Signature": 1})];
+
+        let crashes = vec![
+            index(doc! {"Signature": 1}),
+            index(doc! {"NetID": 1}),
+            // Used by privacy::erase
+            index(doc! {"SessionID": 1}),
+        ];
+
+        let events = vec![
+            index(doc! {"Name": 1, "Timestamp": 1}),
+            index(doc! {"Build": 1, "Name": 1}),
+            index(doc! {"SessionID": 1}),
+            // Used by privacy::erase
+            index(doc! {"NetID": 1}),
+        ];
+
+        let positions = vec![
+            index(doc! {"Map": 1, "EventType": 1}),
+            index(doc! {"SessionID": 1}),
+            // Used by privacy::erase
+            index(doc! {"NetID": 1}),
+        ];
+
+        let feedback = vec![
+            unique(doc! {"SessionObjectID": 1, "CommentIndex": 1}),
+            index(doc! {"Status": 1, "SessionTime": -1}),
+            index(doc! {"Tags": 1}),
+            index(doc! {"NetID": 1}),
+            search(doc! {"Comment": "text"}),
+        ];
+
+        let report_cache = vec![unique(doc! {"Report": 1, "FilterKey": 1, "Day": 1})];
+
+        // The key rollups::add_session upserts on
+        let daily_rollups = vec![unique(doc! {
+            "Day": 1,
+            "BuildVersion": 1,
+            "Platform": 1,
+            "CountryCode": 1,
+            "IsPlayInEditorSession": 1,
+            "IsSteam": 1,
+        })];
+        // The key rollups::add_session inserts players under
+        let rollup_players = vec![unique(doc! {
+            "Day": 1,
+            "BuildVersion": 1,
+            "Platform": 1,
+            "CountryCode": 1,
+            "IsPlayInEditorSession": 1,
+            "IsSteam": 1,
+            "Player": 1,
+        })];
+
+        // Looking up whether a player's erasure was done
+        let erasure_requests = vec![index(doc! {"TargetHash": 1})];
+
+        let opt_outs = vec![unique(doc! {"NetID": 1})];
+
+        let anonymous_sessions = vec![unique(doc! {
+            "Day": 1,
+            "BuildVersion": 1,
+            "Platform": 1,
+            "IsPlayInEditorSession": 1,
+        })];
+        let anonymous_session_keys = vec![unique(doc! {"IdempotencyKey": 1})];
+
+        // The key reporting::add_player_activity upserts on, and the cohort range
+        let first_seen = vec![
+            unique(doc! {
+                "NetID": 1,
+                "BuildVersion": 1,
+                "Platform": 1,
+                "CountryCode": 1,
+                "IsPlayInEditorSession": 1,
+                "IsSteam": 1,
+            }),
+            index(doc! {"FirstSeen": 1}),
+        ];
+        // The key reporting::add_player_activity inserts on, starting with the range reports read
+        let player_days = vec![unique(doc! {
+            "Day": 1,
+            "BuildVersion": 1,
+            "Platform": 1,
+            "CountryCode": 1,
+            "IsPlayInEditorSession": 1,
+            "IsSteam": 1,
+            "NetID": 1,
+        })];
+
+        [
+            (&names.sessions, sessions),
+            (&names.live_sessions, live_sessions),
+            (&names.crash_groups, crash_groups),
+            (&names.crashes, crashes),
+            (&names.events, events),
+            (&names.positions, positions),
+            (&names.feedback, feedback),
+            (&names.report_cache, report_cache),
+            (&names.daily_rollups, daily_rollups),
+            (&names.rollup_players, rollup_players),
+            (&names.erasure_requests, erasure_requests),
+            (&names.opt_outs, opt_outs),
+            (&names.anonymous_sessions, anonymous_sessions),
+            (&names.anonymous_session_keys, anonymous_session_keys),
+            (&names.first_seen, first_seen),
+            (&names.player_days, player_days),
+        ]
+        .into_iter()
+        .flat_map(|(collection, indexes)| {
+            indexes
+                .into_iter()
+                .map(move |index| (collection.clone(), index))
+        })
+        .collect()
     }
 
-    pub async fn get_players_stats(&self) -> mongodb::error::Result<PlayerStats> {
-        let collection = self.database.collection::<Document>("sessions");
+    // Creates any declared index that's missing and reports indexes that don't match what's
+    // declared. Drift is only reported, an index someone added by hand might be there for a reason.
+    pub async fn ensure_indexes(&self) -> mongodb::error::Result<()> {
+        let mut declared: HashMap<String, Vec<String>> = HashMap::new();
 
-        let pie_filter =
-            |is_pie: bool| doc! {"BP_SessionAnalyicsCollector_C.IsPlayInEditorSession": is_pie};
-        let pie_session_cnt = collection.count_documents(pie_filter(true), None).await?;
-        let game_session_cnt = collection.count_documents(pie_filter(false), None).await?;
+        for (collection_name, index) in self.declared_indexes() {
+            let name = index_name(&index);
+            declared
+                .entry(collection_name.clone())
+                .or_default()
+                .push(name.clone());
 
-        let ips = collection
-            .distinct("BP_SessionAnalyicsCollector_C.ip", None, None)
+            let collection = self.database.collection::<Document>(&collection_name);
+            match collection.create_index(index, None).await {
+                Ok(_) => {}
+                // An index with the same name or keys exists but has different options
+                Err(e) if is_index_conflict_error(&e) => {
+                    println!(
+                        "Index drift: {}.{} doesn't match its declaration! {}",
+                        collection_name, name, e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        for (collection_name, names) in declared {
+            let actual = self
+                .database
+                .collection::<Document>(&collection_name)
+                .list_index_names()
+                .await?;
+
+            for name in actual {
+                if name != "_id_" && !names.contains(&name) {
+                    println!(
+                        "Index drift: {}.{} isn't declared by the server",
+                        collection_name, name
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the ids of the sessions stored under the given idempotency keys
+    async fn find_sessions_by_idempotency_keys(
+        &self,
+        keys: &[&str],
+    ) -> mongodb::error::Result<HashMap<String, ObjectId>> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.sessions);
+
+        let options = FindOptions::builder()
+            .projection(doc! {"_id": 1, "IdempotencyKey": 1})
+            .build();
+        let documents: Vec<Document> = collection
+            .find(doc! {"IdempotencyKey": {"$in": keys}}, options)
+            .await?
+            .try_collect()
             .await?;
 
-        let game_sessions = collection.find(pie_filter(false), None).await?;
-        let play_times = game_sessions.map(|session_res| match session_res {
-            Ok(session) => get_time_delta(&session).ok_or("".to_string()),
-            Err(e) => Err(e.to_string()),
-        });
+        Ok(documents
+            .iter()
+            .filter_map(|document| {
+                Some((
+                    document.get_str("IdempotencyKey").ok()?.to_string(),
+                    document.get_object_id("_id").ok()?,
+                ))
+            })
+            .collect())
+    }
+
+    // Stores a session. If a session with the same idempotency key was stored before then nothing
+    // is inserted and the original session's id is returned instead.
+    pub async fn add_session(
+        &self,
+        session: &AnalyticsSession,
+    ) -> mongodb::error::Result<AddSessionResult> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.sessions);
+
+        let id = ObjectId::new();
+        let mut document = session_to_document(session)?;
+        document.insert("_id", id);
+        let feedback = feedback::feedback_from_session(id, &document);
+
+        match collection.insert_one(&document, None).await {
+            Ok(_) => {
+                // The comments are still in the session, backfill_feedback picks them up next start
+                if let Err(e) = self.add_feedback(feedback).await {
+                    eprintln!("Failed to insert feedback into database! Error: {}", e);
+                }
+                // A rollup rebuild picks the session up if this fails
+                if let Err(e) = rollups::add_session(self, &document).await {
+                    eprintln!("Failed to add session to daily rollups! Error: {}", e);
+                }
+                // `rebuild-rollups` picks the session up if this fails
+                if let Err(e) = reporting::add_player_activity(self, &document).await {
+                    eprintln!("Failed to record the player's activity! Error: {}", e);
+                }
+                Ok(AddSessionResult::Inserted(id))
+            }
+            Err(e) if is_duplicate_key_error(&e) => {
+                let key = match &session.idempotency_key {
+                    Some(key) => key.as_str(),
+                    None => return Err(e),
+                };
+
+                match self
+                    .find_sessions_by_idempotency_keys(&[key])
+                    .await?
+                    .get(key)
+                {
+                    Some(original_id) => Ok(AddSessionResult::Duplicate(*original_id)),
+                    None => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Inserts a batch of sessions in one go. The returned vec has one entry per session, with
+    // either the id it was stored under or the reason it couldn't be stored.
+    pub async fn add_sessions(
+        &self,
+        sessions: &[AnalyticsSession],
+    ) -> mongodb::error::Result<Vec<Result<AddSessionResult, String>>> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.sessions);
+
+        // Pick the ids here, the driver doesn't expose which ids made it in when some inserts fail
+        let mut ids = Vec::with_capacity(sessions.len());
+        let mut documents = Vec::with_capacity(sessions.len());
+        let mut feedback = Vec::with_capacity(sessions.len());
+        for session in sessions {
+            let id = ObjectId::new();
+            let mut document = session_to_document(session)?;
+            document.insert("_id", id);
+
+            ids.push(id);
+            feedback.push(feedback::feedback_from_session(id, &document));
+            documents.push(document);
+        }
+
+        let mut results: Vec<Result<AddSessionResult, String>> = ids
+            .into_iter()
+            .map(|id| Ok(AddSessionResult::Inserted(id)))
+            .collect();
+        let mut duplicate_indices = Vec::new();
+
+        // Unordered so one bad document doesn't stop the rest of the batch from being inserted
+        let options = InsertManyOptions::builder().ordered(Some(false)).build();
+        match collection.insert_many(&documents, Some(options)).await {
+            Ok(_) => {}
+            Err(e) => match *e.kind {
+                ErrorKind::BulkWrite(BulkWriteFailure {
+                    write_errors,
+                    write_concern_error,
+                    ..
+                }) => {
+                    for write_error in write_errors.unwrap_or_default() {
+                        if write_error.code == DUPLICATE_KEY_ERROR {
+                            duplicate_indices.push(write_error.index);
+                        }
 
-        let vec = play_times.collect::<Vec<_>>().await;
+                        if let Some(result) = results.get_mut(write_error.index) {
+                            *result = Err(write_error.message);
+                        }
+                    }
 
-        let mut num_play_times: i32 = 0;
-        let mut avg_play_time = chrono::TimeDelta::new(0, 0).unwrap();
-        for play_time_res in vec {
-            if let Ok(play_time) = play_time_res {
-                avg_play_time = avg_play_time + play_time;
-                num_play_times = num_play_times + 1;
+                    // Nothing is known to be durable if the write concern wasn't satisfied
+                    if let Some(write_concern_error) = write_concern_error {
+                        for result in results.iter_mut().filter(|result| result.is_ok()) {
+                            *result = Err(write_concern_error.message.clone());
+                        }
+                    }
+                }
+                _ => return Err(e),
+            },
+        }
+
+        // Point sessions that were already uploaded at their original documents
+        let duplicate_keys: Vec<&str> = duplicate_indices
+            .iter()
+            .filter_map(|&i| sessions.get(i)?.idempotency_key.as_deref())
+            .collect();
+        if !duplicate_keys.is_empty() {
+            let original_ids = self
+                .find_sessions_by_idempotency_keys(&duplicate_keys)
+                .await?;
+
+            for i in duplicate_indices {
+                let original_id = sessions
+                    .get(i)
+                    .and_then(|session| session.idempotency_key.as_ref())
+                    .and_then(|key| original_ids.get(key));
+
+                if let (Some(result), Some(original_id)) = (results.get_mut(i), original_id) {
+                    *result = Ok(AddSessionResult::Duplicate(*original_id));
+                }
+            }
+        }
+
+        let inserted_feedback: Vec<Document> = feedback
+            .into_iter()
+            .zip(&results)
+            .filter(|(_, result)| matches!(result, Ok(AddSessionResult::Inserted(_))))
+            .flat_map(|(feedback, _)| feedback)
+            .collect();
+        if let Err(e) = self.add_feedback(inserted_feedback).await {
+            eprintln!("Failed to insert feedback into database! Error: {}", e);
+        }
+
+        for (document, _) in documents
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| matches!(result, Ok(AddSessionResult::Inserted(_))))
+        {
+            if let Err(e) = rollups::add_session(self, document).await {
+                eprintln!("Failed to add session to daily rollups! Error: {}", e);
+            }
+            // `rebuild-rollups` picks the session up if this fails
+            if let Err(e) = reporting::add_player_activity(self, document).await {
+                eprintln!("Failed to record the player's activity! Error: {}", e);
+            }
+        }
+
+        Ok(results)
+    }
+
+    // Stores feedback records made by feedback::feedback_from_session. Records that are already
+    // stored are skipped.
+    async fn add_feedback(&self, feedback: Vec<Document>) -> mongodb::error::Result<()> {
+        if feedback.is_empty() {
+            return Ok(());
+        }
+
+        let options = InsertManyOptions::builder().ordered(Some(false)).build();
+        match self
+            .database
+            .collection::<Document>(&self.collections.feedback)
+            .insert_many(feedback, Some(options))
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) if is_only_duplicate_keys_error(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Makes feedback records for comments in sessions that were stored before the feedback
+    // collection existed, or whose records failed to insert
+    pub async fn backfill_feedback(&self) -> mongodb::error::Result<()> {
+        let sessions = self
+            .database
+            .collection::<Document>(&self.collections.sessions);
+        let filter = doc! {format!("{}.FeedbackComments.0", FEEDBACK_COLLECTOR): {"$exists": true}};
+
+        let mut cursor = sessions.find(filter, None).await?;
+        let mut feedback = Vec::new();
+        while let Some(session) = cursor.try_next().await? {
+            if let Ok(id) = session.get_object_id("_id") {
+                feedback.extend(feedback::feedback_from_session(id, &session));
+            }
+        }
+
+        self.add_feedback(feedback).await
+    }
+
+    pub async fn get_feedback(
+        &self,
+        query: &FeedbackQuery,
+        limit: i64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>> {
+        let options = FindOptions::builder()
+            .sort(doc! {"SessionTime": -1, "CommentIndex": 1})
+            .limit(limit)
+            .skip(skip)
+            .build();
+
+        self.database
+            .collection::<FeedbackItem>(&self.collections.feedback)
+            .find(query.to_match_document(), options)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    // Searches comments and sessions at once, best matches first. Scores from the two collections
+    // aren't strictly comparable, but they're close enough to interleave the results.
+    pub async fn search(
+        &self,
+        query: &SearchQuery,
+        limit: u64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<SearchHit>> {
+        let limit = limit.min(MAX_SEARCH_RESULTS.saturating_sub(skip));
+        if query.text.trim().is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Either collection could have every hit on the page, so both need to fetch all of them
+        let fetch = (skip + limit) as i64;
+        let options = |projection: Document| {
+            FindOptions::builder()
+                .projection(projection)
+                .sort(doc! {"Score": {"$meta": "textScore"}})
+                .limit(fetch)
+                .build()
+        };
+
+        let feedback: Vec<Document> = self
+            .database
+            .collection::<Document>(&self.collections.feedback)
+            .find(
+                query.to_match_document("SessionTime"),
+                options(doc! {
+                    "SessionObjectID": 1,
+                    "Comment": 1,
+                    "SessionTime": 1,
+                    "Score": {"$meta": "textScore"},
+                }),
+            )
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut session_projection = doc! {
+            "BP_SessionAnalyicsCollector_C.StartTime": 1,
+            "Score": {"$meta": "textScore"},
+        };
+        for field in SESSION_SEARCH_FIELDS {
+            session_projection.insert(field, 1);
+        }
+        let sessions: Vec<Document> = self
+            .database
+            .collection::<Document>(&self.collections.sessions)
+            .find(
+                query.to_match_document("BP_SessionAnalyicsCollector_C.StartTime"),
+                options(session_projection),
+            )
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut hits: Vec<SearchHit> = feedback
+            .iter()
+            .filter_map(|comment| {
+                feedback_search_hit(comment, comment.get_f64("Score").unwrap_or(0.0))
+            })
+            .collect();
+        hits.extend(sessions.iter().filter_map(|session| {
+            session_search_hit(session, session.get_f64("Score").unwrap_or(0.0))
+        }));
+
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(hits
+            .into_iter()
+            .skip(skip as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    pub async fn get_feedback_item(
+        &self,
+        id: ObjectId,
+    ) -> mongodb::error::Result<Option<FeedbackItem>> {
+        self.database
+            .collection::<FeedbackItem>(&self.collections.feedback)
+            .find_one(doc! {"_id": id}, None)
+            .await
+    }
+
+    pub async fn get_session_feedback(
+        &self,
+        session_object_id: ObjectId,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>> {
+        let options = FindOptions::builder()
+            .sort(doc! {"CommentIndex": 1})
+            .build();
+
+        self.database
+            .collection::<FeedbackItem>(&self.collections.feedback)
+            .find(doc! {"SessionObjectID": session_object_id}, options)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    // Marks the document matching `filter` as being filed as a GitLab issue, unless it already has
+    // an issue or is being filed by someone else. Returns whether it was marked.
+    async fn claim_gitlab_issue(
+        &self,
+        collection: &str,
+        mut filter: Document,
+    ) -> mongodb::error::Result<bool> {
+        let now = Utc::now();
+        filter.insert("GitlabIssue", Bson::Null);
+        filter.insert(
+            "$or",
+            vec![
+                doc! {"GitlabIssuePending": {"$exists": false}},
+                doc! {"GitlabIssuePending": {"$lt": now - GITLAB_CLAIM_EXPIRY}},
+            ],
+        );
+
+        let result = self
+            .database
+            .collection::<Document>(collection)
+            .update_one(filter, doc! {"$set": {"GitlabIssuePending": now}}, None)
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    // Undoes claim_gitlab_issue after filing the issue failed
+    async fn release_gitlab_issue(
+        &self,
+        collection: &str,
+        filter: Document,
+    ) -> mongodb::error::Result<()> {
+        self.database
+            .collection::<Document>(collection)
+            .update_one(filter, doc! {"$unset": {"GitlabIssuePending": ""}}, None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn claim_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<bool> {
+        self.claim_gitlab_issue(&self.collections.feedback, doc! {"_id": id})
+            .await
+    }
+
+    pub async fn release_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<()> {
+        self.release_gitlab_issue(&self.collections.feedback, doc! {"_id": id})
+            .await
+    }
+
+    pub async fn set_feedback_gitlab_issue(
+        &self,
+        id: ObjectId,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()> {
+        self.database
+            .collection::<Document>(&self.collections.feedback)
+            .update_one(
+                doc! {"_id": id},
+                doc! {
+                    "$set": {
+                        "GitlabIssue": mongodb::bson::to_document(issue)?,
+                        "UpdatedTime": mongodb::bson::DateTime::now(),
+                    },
+                    "$unset": {"GitlabIssuePending": ""},
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_session_document(
+        &self,
+        id: ObjectId,
+    ) -> mongodb::error::Result<Option<Document>> {
+        self.database
+            .collection::<Document>(&self.collections.sessions)
+            .find_one(doc! {"_id": id}, None)
+            .await
+    }
+
+    // Returns the updated comment, or None if there's no comment with that id
+    pub async fn update_feedback(
+        &self,
+        id: ObjectId,
+        update: &FeedbackUpdate,
+    ) -> mongodb::error::Result<Option<FeedbackItem>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        self.database
+            .collection::<FeedbackItem>(&self.collections.feedback)
+            .find_one_and_update(doc! {"_id": id}, update.to_update_document(), options)
+            .await
+    }
+
+    // Opens a live session, or keeps it open if it already is. Heartbeats go through here too so
+    // that a session whose start event got lost still shows up.
+    pub async fn touch_live_session(
+        &self,
+        event: &LiveSessionEvent,
+        cloudflare_info: &CloudflareInfo,
+    ) -> mongodb::error::Result<()> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.live_sessions);
+        let now = mongodb::bson::DateTime::now();
+
+        // Ended sessions stay ended, a late heartbeat shouldn't reopen them
+        let filter = doc! {
+            "SessionID": &event.session_id,
+            "Status": {"$ne": LiveSessionStatus::Ended.as_str()},
+        };
+        let update = doc! {
+            "$set": {
+                "Status": LiveSessionStatus::Active.as_str(),
+                "LastHeartbeat": now,
+                "NetID": &event.net_id,
+                "IsPlayInEditorSession": event.is_play_in_editor_session,
+                "BuildVersion": &event.build_version,
+                "Platform": &event.platform,
+                "CountryCode": &cloudflare_info.country,
+                "CountryName": cloudflare_info.get_country_name(),
+            },
+            "$setOnInsert": {"StartTime": now},
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+
+        match collection.update_one(filter, update, options).await {
+            Ok(_) => Ok(()),
+            // The upsert collided with the ended session
+            Err(e) if is_duplicate_key_error(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Closes a live session. Returns false if there was no open session with that id.
+    pub async fn end_live_session(&self, session_id: &str) -> mongodb::error::Result<bool> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.live_sessions);
+        let now = mongodb::bson::DateTime::now();
+
+        let filter = doc! {
+            "SessionID": session_id,
+            "Status": {"$ne": LiveSessionStatus::Ended.as_str()},
+        };
+        let update = doc! {
+            "$set": {
+                "Status": LiveSessionStatus::Ended.as_str(),
+                "LastHeartbeat": now,
+                "EndTime": now,
+            },
+        };
+
+        let res = collection.update_one(filter, update, None).await?;
+        Ok(res.matched_count > 0)
+    }
+
+    // Marks active sessions that haven't sent a heartbeat within `timeout` as abandoned, ending
+    // them at their last heartbeat. Returns how many sessions were abandoned.
+    pub async fn sweep_abandoned_sessions(
+        &self,
+        timeout: chrono::TimeDelta,
+    ) -> mongodb::error::Result<u64> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.live_sessions);
+        let cutoff = Utc::now() - timeout;
+
+        let filter = doc! {
+            "Status": LiveSessionStatus::Active.as_str(),
+            "LastHeartbeat": {"$lt": cutoff},
+        };
+        let update = vec![doc! {
+            "$set": {
+                "Status": LiveSessionStatus::Abandoned.as_str(),
+                "EndTime": "$LastHeartbeat",
+            },
+        }];
+
+        let res = collection.update_many(filter, update, None).await?;
+        Ok(res.modified_count)
+    }
+
+    // Looks up which player a session belonged to, checking live sessions first since a crashed
+    // session usually never uploads its full session data
+    pub async fn find_net_id_for_session(
+        &self,
+        session_id: &str,
+    ) -> mongodb::error::Result<Option<String>> {
+        let live_session = self
+            .database
+            .collection::<Document>(&self.collections.live_sessions)
+            .find_one(doc! {"SessionID": session_id}, None)
+            .await?;
+        if let Some(net_id) = live_session.and_then(|s| s.get_str("NetID").ok().map(String::from)) {
+            return Ok(Some(net_id));
+        }
+
+        let session = self
+            .database
+            .collection::<Document>(&self.collections.sessions)
+            .find_one(
+                doc! {"BP_SessionAnalyicsCollector_C.SessionID": session_id},
+                None,
+            )
+            .await?;
+
+        Ok(session.and_then(|session| {
+            session
+                .get_document(SESSION_COLLECTOR)
+                .ok()?
+                .get_array("PlayerControllerData")
+                .ok()?
+                .first()?
+                .as_document()?
+                .get_str("NetID")
+                .ok()
+                .map(String::from)
+        }))
+    }
+
+    pub fn crash_files_bucket(&self) -> GridFsBucket {
+        let options = GridFsBucketOptions::builder()
+            .bucket_name(self.collections.crash_files.clone())
+            .build();
+
+        self.database.gridfs_bucket(options)
+    }
+
+    // Stores a crash and counts it towards its signature's crash group. Returns the group.
+    pub async fn add_crash(&self, crash: Document) -> mongodb::error::Result<CrashGroup> {
+        let signature = crash.get_str("Signature").unwrap_or_default().to_string();
+        let crash_id = crash.get_object_id("_id").ok();
+        let now = mongodb::bson::DateTime::now();
+
+        self.database
+            .collection::<Document>(&self.collections.crashes)
+            .insert_one(&crash, None)
+            .await?;
+
+        let mut update = doc! {
+            "$inc": {"Count": 1},
+            "$set": {"LastSeen": now, "LastCrashID": crash_id},
+            "$setOnInsert": {
+                "FirstSeen": now,
+                "CrashType": crash.get("CrashType"),
+                "ErrorMessage": crash.get("ErrorMessage"),
+                "SignatureFrames": crash.get("SignatureFrames"),
+            },
+        };
+        if let Ok(build_version) = crash.get_str("BuildVersion") {
+            update.insert("$addToSet", doc! {"BuildVersions": build_version});
+        }
+
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let group = self
+            .database
+            .collection::<CrashGroup>(&self.collections.crash_groups)
+            .find_one_and_update(doc! {"Signature": &signature}, update, options)
+            .await?;
+
+        group.ok_or_else(|| {
+            mongodb::error::Error::custom(format!("crash group {} missing after upsert", signature))
+        })
+    }
+
+    // Returns crash groups, most frequent first
+    pub async fn get_crash_groups(&self, limit: i64) -> mongodb::error::Result<Vec<CrashGroup>> {
+        let options = FindOptions::builder()
+            .sort(doc! {"Count": -1})
+            .limit(limit)
+            .build();
+
+        self.database
+            .collection::<CrashGroup>(&self.collections.crash_groups)
+            .find(None, options)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    pub async fn get_crash_group(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<Option<CrashGroup>> {
+        self.database
+            .collection::<CrashGroup>(&self.collections.crash_groups)
+            .find_one(doc! {"Signature": signature}, None)
+            .await
+    }
+
+    pub async fn claim_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<bool> {
+        self.claim_gitlab_issue(
+            &self.collections.crash_groups,
+            doc! {"Signature": signature},
+        )
+        .await
+    }
+
+    pub async fn release_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<()> {
+        self.release_gitlab_issue(
+            &self.collections.crash_groups,
+            doc! {"Signature": signature},
+        )
+        .await
+    }
+
+    pub async fn set_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()> {
+        self.database
+            .collection::<Document>(&self.collections.crash_groups)
+            .update_one(
+                doc! {"Signature": signature},
+                doc! {
+                    "$set": {"GitlabIssue": mongodb::bson::to_document(issue)?},
+                    "$unset": {"GitlabIssuePending": ""},
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn add_events(&self, events: &[GameplayEvent]) -> mongodb::error::Result<u64> {
+        let documents = events
+            .iter()
+            .map(|event| event.to_document())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let res = self
+            .database
+            .collection::<Document>(&self.collections.events)
+            .insert_many(documents, None)
+            .await?;
+
+        Ok(res.inserted_ids.len() as u64)
+    }
+
+    pub async fn count_events(&self, query: &EventQuery) -> mongodb::error::Result<u64> {
+        self.database
+            .collection::<Document>(&self.collections.events)
+            .count_documents(query.to_match_document(), None)
+            .await
+    }
+
+    // Counts the events matching `query`, grouped by name, build or a property value. Biggest
+    // groups come first.
+    pub async fn group_events(
+        &self,
+        query: &EventQuery,
+        grouping: &EventGrouping,
+    ) -> mongodb::error::Result<Vec<EventCount>> {
+        let pipeline = [
+            doc! {"$match": query.to_match_document()},
+            doc! {"$group": {"_id": grouping.field_path(), "count": {"$sum": 1}}},
+            doc! {"$sort": {"count": -1, "_id": 1}},
+        ];
+
+        let documents: Vec<Document> = self
+            .database
+            .collection::<Document>(&self.collections.events)
+            .aggregate(pipeline, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(documents
+            .into_iter()
+            .map(|mut document| EventCount {
+                key: document
+                    .remove("_id")
+                    .unwrap_or(mongodb::bson::Bson::Null)
+                    .into_relaxed_extjson(),
+                count: get_number(&document, "count").unwrap_or(0),
+            })
+            .collect())
+    }
+
+    // Players are identified by NetID, or by SessionID for events that were sent without one
+    pub async fn get_funnel(
+        &self,
+        query: &FunnelQuery,
+    ) -> mongodb::error::Result<Vec<FunnelStepResult>> {
+        let names: Vec<&str> = query.steps.iter().map(|step| step.name.as_str()).collect();
+
+        let mut filter = query.events.to_match_document();
+        filter.insert("Name", doc! {"$in": names});
+
+        // Sorted by player so each player is counted as soon as their last event comes in, and only
+        // one player's events are held at a time
+        let pipeline = [
+            doc! {"$match": filter},
+            doc! {"$project": {
+                "_id": 0,
+                "Player": {"$ifNull": ["$NetID", "$SessionID"]},
+                "Name": 1,
+                "Timestamp": 1,
+                "Properties": 1,
+            }},
+            doc! {"$sort": {"Player": 1, "Timestamp": 1}},
+        ];
+        let options = AggregateOptions::builder().allow_disk_use(true).build();
+
+        let mut counts = query.counts();
+        let mut current_player = None;
+        let mut events = Vec::new();
+
+        let mut cursor = self
+            .database
+            .collection::<Document>(&self.collections.events)
+            .aggregate(pipeline, options)
+            .await?;
+        while let Some(document) = cursor.try_next().await? {
+            let player = document.get_str("Player").unwrap_or("").to_string();
+            if current_player.as_ref() != Some(&player) {
+                if !events.is_empty() {
+                    counts.add_player(&events);
+                    events.clear();
+                }
+                current_player = Some(player);
+            }
+
+            if let (Ok(name), Ok(timestamp)) =
+                (document.get_str("Name"), document.get_datetime("Timestamp"))
+            {
+                events.push(FunnelEvent {
+                    name: name.to_string(),
+                    timestamp: timestamp.to_chrono(),
+                    properties: document
+                        .get_document("Properties")
+                        .cloned()
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        if !events.is_empty() {
+            counts.add_player(&events);
+        }
+
+        Ok(counts.results())
+    }
+
+    pub async fn add_positions(&self, samples: &[PositionSample]) -> mongodb::error::Result<u64> {
+        let documents = samples.iter().map(PositionSample::to_document);
+
+        let res = self
+            .database
+            .collection::<Document>(&self.collections.positions)
+            .insert_many(documents, None)
+            .await?;
+
+        Ok(res.inserted_ids.len() as u64)
+    }
+
+    pub fn position_filter(map: &str, event_type: Option<&str>) -> Document {
+        let mut filter = doc! {"Map": map};
+        if let Some(event_type) = event_type {
+            filter.insert("EventType", event_type);
+        }
+
+        filter
+    }
+
+    // Bounds of every position recorded on a map, for maps without configured bounds
+    pub async fn get_position_bounds(
+        &self,
+        map: &str,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<Option<MapBounds>> {
+        let pipeline = [
+            doc! {"$match": Self::position_filter(map, event_type)},
+            doc! {"$group": {
+                "_id": null,
+                "min_x": {"$min": "$X"},
+                "max_x": {"$max": "$X"},
+                "min_y": {"$min": "$Y"},
+                "max_y": {"$max": "$Y"},
+            }},
+        ];
+
+        let mut cursor = self
+            .database
+            .collection::<Document>(&self.collections.positions)
+            .aggregate(pipeline, None)
+            .await?;
+
+        Ok(cursor.try_next().await?.and_then(|document| {
+            Some(MapBounds {
+                min_x: document.get_f64("min_x").ok()?,
+                max_x: document.get_f64("max_x").ok()?,
+                min_y: document.get_f64("min_y").ok()?,
+                max_y: document.get_f64("max_y").ok()?,
+            })
+        }))
+    }
+
+    // Counts the positions that fall into each cell of `grid`. The binning happens in mongo so
+    // only the non-empty cells come back.
+    pub async fn bin_positions(
+        &self,
+        grid: &mut HeatmapGrid,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<()> {
+        let bounds = grid.bounds;
+        let (cell_width, cell_height) = grid.cell_size();
+
+        let mut filter = Self::position_filter(&grid.map, event_type);
+        filter.insert("X", doc! {"$gte": bounds.min_x, "$lte": bounds.max_x});
+        filter.insert("Y", doc! {"$gte": bounds.min_y, "$lte": bounds.max_y});
+
+        let pipeline = [
+            doc! {"$match": filter},
+            doc! {"$group": {
+                "_id": {
+                    "x": {"$floor": {"$divide": [{"$subtract": ["$X", bounds.min_x]}, cell_width]}},
+                    "y": {"$floor": {"$divide": [{"$subtract": ["$Y", bounds.min_y]}, cell_height]}},
+                },
+                "count": {"$sum": 1},
+            }},
+        ];
+
+        let mut cursor = self
+            .database
+            .collection::<Document>(&self.collections.positions)
+            .aggregate(pipeline, None)
+            .await?;
+
+        while let Some(document) = cursor.try_next().await? {
+            let cell = match document.get_document("_id") {
+                Ok(cell) => cell,
+                Err(_) => continue,
+            };
+
+            if let (Some(x), Some(y), Some(count)) = (
+                get_number(cell, "x"),
+                get_number(cell, "y"),
+                get_number(&document, "count"),
+            ) {
+                grid.add(x, y, count.max(0) as u64);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Frame time percentiles, hitches and memory per build and hardware class for game sessions
+    // that sent performance data. Histograms are merged in mongo, only the percentiles are
+    // worked out here.
+    pub async fn get_performance_stats(
+        &self,
+        stats_filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<PerformanceStats>> {
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.sessions);
+
+        // Game sessions only, unless the filter asks for PIE
+        let mut filter = doc! {"BP_SessionAnalyicsCollector_C.IsPlayInEditorSession": false};
+        filter.extend(stats_filter.session_filter());
+        filter.insert("BP_PerformanceAnalyticsCollector_C", doc! {"$exists": true});
+
+        let group_key = doc! {
+            "build": "$BP_SessionAnalyicsCollector_C.BuildVersion",
+            "class": "$BP_PerformanceAnalyticsCollector_C.HardwareClass",
+        };
+
+        let summary_pipeline = [
+            doc! {"$match": filter.clone()},
+            doc! {"$group": {
+                "_id": group_key.clone(),
+                "sessions": {"$sum": 1},
+                "hitches": {"$sum": "$BP_PerformanceAnalyticsCollector_C.HitchCount"},
+                "avg_peak_memory": {"$avg": "$BP_PerformanceAnalyticsCollector_C.PeakMemoryMB"},
+                "max_peak_memory": {"$max": "$BP_PerformanceAnalyticsCollector_C.PeakMemoryMB"},
+            }},
+        ];
+
+        let mut histogram_key = group_key;
+        histogram_key.insert(
+            "bound",
+            "$BP_PerformanceAnalyticsCollector_C.FrameTimeHistogram.UpperBoundMs",
+        );
+        let histogram_pipeline = [
+            doc! {"$match": filter},
+            doc! {"$unwind": "$BP_PerformanceAnalyticsCollector_C.FrameTimeHistogram"},
+            doc! {"$group": {
+                "_id": histogram_key,
+                "count": {"$sum": "$BP_PerformanceAnalyticsCollector_C.FrameTimeHistogram.Count"},
+            }},
+        ];
+
+        let group_id = |id: &Document| {
+            (
+                id.get_str("build").ok().map(String::from),
+                id.get_str("class").ok().map(String::from),
+            )
+        };
+
+        let mut histograms: HashMap<PerformanceGroup, Vec<(f64, i64)>> = HashMap::new();
+        let mut cursor = collection.aggregate(histogram_pipeline, None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            if let (Ok(id), Some(count)) =
+                (document.get_document("_id"), get_number(&document, "count"))
+            {
+                if let Ok(bound) = id.get_f64("bound") {
+                    histograms
+                        .entry(group_id(id))
+                        .or_default()
+                        .push((bound, count));
+                }
             }
         }
 
-        avg_play_time = avg_play_time / num_play_times;
+        let mut stats = Vec::new();
+        let mut cursor = collection.aggregate(summary_pipeline, None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            let id = match document.get_document("_id") {
+                Ok(id) => group_id(id),
+                Err(_) => continue,
+            };
+
+            let mut histogram = histograms.remove(&id).unwrap_or_default();
+            histogram.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let sessions = get_number(&document, "sessions").unwrap_or(0);
+            let hitches = get_number(&document, "hitches").unwrap_or(0);
+
+            stats.push(PerformanceStats {
+                build_version: id.0,
+                hardware_class: id.1,
+                sessions,
+                frames: histogram.iter().map(|(_, count)| count).sum(),
+                p50_frame_time_ms: histogram_percentile(&histogram, 0.50),
+                p95_frame_time_ms: histogram_percentile(&histogram, 0.95),
+                p99_frame_time_ms: histogram_percentile(&histogram, 0.99),
+                hitches_per_session: hitches as f64 / sessions.max(1) as f64,
+                avg_peak_memory_mb: document.get_f64("avg_peak_memory").ok(),
+                max_peak_memory_mb: document.get_f64("max_peak_memory").ok(),
+            });
+        }
+
+        // Builds in descending order so recent versions usually come first, then hardware classes
+        stats.sort_by(|a, b| {
+            (&b.build_version, &a.hardware_class).cmp(&(&a.build_version, &b.hardware_class))
+        });
+
+        Ok(stats)
+    }
+
+    // Looks up every session a player was in. Returns None for players that have never uploaded
+    // a session or have opted out.
+    pub async fn get_player_profile(
+        &self,
+        net_id: &str,
+    ) -> mongodb::error::Result<Option<PlayerProfile>> {
+        if crate::opt_outs::is_opted_out(self, net_id).await? {
+            return Ok(None);
+        }
+
+        let collection = self
+            .database
+            .collection::<Document>(&self.collections.sessions);
+
+        let is_pie = "$BP_SessionAnalyicsCollector_C.IsPlayInEditorSession";
+        let start_time = "$BP_SessionAnalyicsCollector_C.StartTime";
+        let end_time = "$BP_SessionAnalyicsCollector_C.EndTime";
+        let has_dates = doc! {"$and": [
+            {"$eq": [{"$type": start_time}, "date"]},
+            {"$eq": [{"$type": end_time}, "date"]},
+        ]};
+        let play_time_ms =
+            doc! {"$cond": [has_dates.clone(), {"$subtract": [end_time, start_time]}, null]};
+
+        let pipeline = [
+            doc! {"$match": {"BP_SessionAnalyicsCollector_C.PlayerControllerData.NetID": net_id}},
+            doc! {"$facet": {
+                "profile": [
+                    {"$group": {
+                        "_id": null,
+                        "first_seen": {"$min": {"$cond": [has_dates.clone(), start_time, null]}},
+                        "last_seen": {"$max": {"$cond": [has_dates, end_time, null]}},
+                        "sessions": {"$sum": 1},
+                        "pie_sessions": {"$sum": {"$cond": [{"$eq": [is_pie, true]}, 1, 0]}},
+                        "play_time_ms": {"$sum": {"$cond": [
+                            {"$eq": [is_pie, true]},
+                            0,
+                            {"$ifNull": [play_time_ms.clone(), 0]},
+                        ]}},
+                        "steam_sessions": {"$sum": {"$cond": [
+                            {"$gt": [
+                                {"$size": {"$ifNull": [
+                                    "$BP_SessionAnalyicsCollector_C.PlayerControllerData.SteamAnalyticsData",
+                                    [],
+                                ]}},
+                                0,
+                            ]},
+                            1,
+                            0,
+                        ]}},
+                        "countries": {"$addToSet": "$BP_SessionAnalyicsCollector_C.CountryName"},
+                        "builds": {"$addToSet": "$BP_SessionAnalyicsCollector_C.BuildVersion"},
+                        "platforms": {"$addToSet": "$BP_SessionAnalyicsCollector_C.Platform"},
+                    }},
+                ],
+                "feedback": [
+                    {"$match": {"BP_CactusGameFeedbackCollector_C.FeedbackComments.0": {"$exists": true}}},
+                    {"$sort": {"BP_SessionAnalyicsCollector_C.StartTime": 1}},
+                    {"$project": {
+                        "session_id": "$BP_SessionAnalyicsCollector_C.SessionID",
+                        "time": start_time,
+                        "comments": "$BP_CactusGameFeedbackCollector_C.FeedbackComments",
+                    }},
+                ],
+                "recent_sessions": [
+                    {"$sort": {"BP_SessionAnalyicsCollector_C.StartTime": -1}},
+                    {"$limit": PLAYER_RECENT_SESSIONS},
+                    {"$project": {
+                        "session_id": "$BP_SessionAnalyicsCollector_C.SessionID",
+                        "start_time": start_time,
+                        "play_time_ms": play_time_ms,
+                        "build_version": "$BP_SessionAnalyicsCollector_C.BuildVersion",
+                        "platform": "$BP_SessionAnalyicsCollector_C.Platform",
+                        "country_code": "$BP_SessionAnalyicsCollector_C.CountryCode",
+                        "is_pie": is_pie,
+                    }},
+                ],
+            }},
+        ];
+
+        let result = collection
+            .aggregate(pipeline, None)
+            .await?
+            .try_next()
+            .await?
+            .unwrap_or_default();
+
+        let facet = |name: &str| -> Vec<Document> {
+            result
+                .get_array(name)
+                .map(|results| {
+                    results
+                        .iter()
+                        .filter_map(|result| result.as_document().cloned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let profile = match facet("profile").into_iter().next() {
+            Some(profile) => profile,
+            None => return Ok(None),
+        };
+
+        let feedback = facet("feedback")
+            .iter()
+            .map(|document| PlayerFeedback {
+                session_id: document.get_str("session_id").ok().map(String::from),
+                time: get_chrono(document, "time"),
+                comments: document
+                    .get_array("comments")
+                    .map(|comments| {
+                        comments
+                            .iter()
+                            .filter_map(|comment| comment.as_str().map(String::from))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        let recent_sessions = facet("recent_sessions")
+            .iter()
+            .map(|document| PlayerSessionSummary {
+                id: document
+                    .get_object_id("_id")
+                    .map(|id| id.to_hex())
+                    .unwrap_or_default(),
+                session_id: document.get_str("session_id").ok().map(String::from),
+                start_time: get_chrono(document, "start_time"),
+                play_time_secs: get_number(document, "play_time_ms").map(|ms| ms / 1000),
+                build_version: document.get_str("build_version").ok().map(String::from),
+                platform: document.get_str("platform").ok().map(String::from),
+                country_code: document.get_str("country_code").ok().map(String::from),
+                is_play_in_editor_session: document.get_bool("is_pie").unwrap_or(false),
+            })
+            .collect();
+
+        Ok(Some(PlayerProfile {
+            net_id: net_id.to_string(),
+            is_steam_player: get_number(&profile, "steam_sessions").unwrap_or(0) > 0,
+            first_seen: get_chrono(&profile, "first_seen"),
+            last_seen: get_chrono(&profile, "last_seen"),
+            sessions: get_number(&profile, "sessions").unwrap_or(0) as u64,
+            pie_sessions: get_number(&profile, "pie_sessions").unwrap_or(0) as u64,
+            total_play_time_secs: get_number(&profile, "play_time_ms").unwrap_or(0) / 1000,
+            countries: get_strings(&profile, "countries"),
+            builds: get_strings(&profile, "builds"),
+            platforms: get_strings(&profile, "platforms"),
+            feedback,
+            recent_sessions,
+        }))
+    }
+
+    // Play time percentiles, trimmed mean and histogram for game sessions. Worked out from the
+    // daily rollups like get_players_stats, so the two always agree.
+    pub async fn get_play_time_distribution(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<PlayTimeDistribution> {
+        let totals = rollups::get_totals(self, filter).await?;
+        Ok(rollups::play_time_distribution(&totals))
+    }
+
+    // Worked out from the daily rollups, so dates are widened to whole days and sessions the
+    // retention job has pruned are still counted. See rollups::play_time_distribution for how
+    // exact the play time is.
+    pub async fn get_players_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<PlayerStats> {
+        let totals = rollups::get_totals(self, filter).await?;
+
+        let avg_play_time = if totals.timed_sessions > 0 {
+            chrono::TimeDelta::milliseconds(totals.play_time_ms / totals.timed_sessions as i64)
+        } else {
+            chrono::TimeDelta::zero()
+        };
+
+        let mut live_filter = doc! {"IsPlayInEditorSession": false};
+        live_filter.extend(filter.live_session_filter());
+        live_filter.insert("Status", LiveSessionStatus::Active.as_str());
+        let currently_playing = self
+            .database
+            .collection::<Document>(&self.collections.live_sessions)
+            .count_documents(live_filter, None)
+            .await?;
 
         Ok(PlayerStats {
-            pie_sessions: pie_session_cnt,
-            game_sessions: game_session_cnt,
-            unique_players: ips.len() as u64,
-            avg_play_time: avg_play_time,
+            pie_sessions: totals.pie_sessions,
+            game_sessions: totals.game_sessions,
+            unique_players: totals.unique_players,
+            avg_play_time,
+            currently_playing,
+            play_time: rollups::play_time_distribution(&totals),
+            anonymous_sessions: crate::opt_outs::get_anonymous_sessions(self, filter).await?,
         })
     }
 }
@@ -169,10 +2139,39 @@ impl Database {
 pub fn connect_to_db(config: &config::Config) -> Database {
     let client_options = ClientOptions::parse(&config.mongodb_connection_string).unwrap();
     let client = Client::with_options(client_options).unwrap();
-    let db = client.database("local");
+
+    // Older deployments wrote everything to "local", which mongo keeps for replication data
+    if config.database.name == "local" {
+        println!("Warning: using the reserved \"local\" database, it isn't replicated!");
+    }
+    let db = client.database(&config.database.name);
 
     Database {
         client,
         database: db,
+        collections: config.database.collections.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_percentile_picks_the_bucket_it_lands_in() {
+        let buckets = [(8.0, 50), (16.0, 40), (33.0, 0), (50.0, 9), (100.0, 1)];
+        assert_eq!(histogram_percentile(&buckets, 0.0), Some(8.0));
+        assert_eq!(histogram_percentile(&buckets, 0.50), Some(8.0));
+        assert_eq!(histogram_percentile(&buckets, 0.51), Some(16.0));
+        // Empty buckets are skipped over
+        assert_eq!(histogram_percentile(&buckets, 0.95), Some(50.0));
+        assert_eq!(histogram_percentile(&buckets, 0.99), Some(50.0));
+        assert_eq!(histogram_percentile(&buckets, 1.0), Some(100.0));
+    }
+
+    #[test]
+    fn histogram_percentile_of_nothing_is_unknown() {
+        assert_eq!(histogram_percentile(&[], 0.5), None);
+        assert_eq!(histogram_percentile(&[(8.0, 0), (16.0, 0)], 0.5), None);
     }
 }
@@ -1,16 +1,17 @@
-use std::env;
-
 use serenity::async_trait;
 use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage};
-use serenity::model::application::{Command, Interaction};
+use serenity::model::application::Interaction;
 use serenity::model::gateway::Ready;
 use serenity::model::id::GuildId;
 use serenity::prelude::*;
 use std::sync::Arc;
 
-struct Handler;
+struct Handler {
+    state: Arc<ServerState>,
+}
 
 use crate::commands;
+use crate::ServerState;
 
 #[async_trait]
 impl EventHandler for Handler {
@@ -21,11 +22,66 @@ impl EventHandler for Handler {
             let content = match command.data.name.as_str() {
                 "ping" => Some(commands::ping::run(&command.data.options())),
                 "test_command" => {
-                    commands::test_command::run(&ctx, &command).await.unwrap();
+                    commands::test_command::run(&ctx, &command, &self.state)
+                        .await
+                        .unwrap();
                     None
                 }
                 "print_config" => {
-                    commands::print_config::run(&ctx, &command).await.unwrap();
+                    commands::print_config::run(&ctx, &command, &self.state)
+                        .await
+                        .unwrap();
+                    None
+                }
+                "performance" => {
+                    if let Err(why) = commands::performance::run(&ctx, &command, &self.state).await
+                    {
+                        eprintln!("Failed to run performance command: {why}");
+                    }
+                    None
+                }
+                "funnel" => {
+                    if let Err(why) = commands::funnel::run(&ctx, &command, &self.state).await {
+                        eprintln!("Failed to run funnel command: {why}");
+                    }
+                    None
+                }
+                "feedback" => {
+                    if let Err(why) = commands::feedback::run(&ctx, &command, &self.state).await {
+                        eprintln!("Failed to run feedback command: {why}");
+                    }
+                    None
+                }
+                "feedback_status" => {
+                    if let Err(why) =
+                        commands::feedback_status::run(&ctx, &command, &self.state).await
+                    {
+                        eprintln!("Failed to run feedback_status command: {why}");
+                    }
+                    None
+                }
+                "player" => {
+                    if let Err(why) = commands::player::run(&ctx, &command, &self.state).await {
+                        eprintln!("Failed to run player command: {why}");
+                    }
+                    None
+                }
+                "search" => {
+                    if let Err(why) = commands::search::run(&ctx, &command, &self.state).await {
+                        eprintln!("Failed to run search command: {why}");
+                    }
+                    None
+                }
+                "retention" => {
+                    if let Err(why) = commands::retention::run(&ctx, &command, &self.state).await {
+                        eprintln!("Failed to run retention command: {why}");
+                    }
+                    None
+                }
+                "erase" => {
+                    if let Err(why) = commands::erase::run(&ctx, &command, &self.state).await {
+                        eprintln!("Failed to run erase command: {why}");
+                    }
                     None
                 }
                 _ => Some("not implemented :(".to_string()),
@@ -38,6 +94,16 @@ impl EventHandler for Handler {
                     println!("Cannot respond to slash command: {why}");
                 }
             }
+        } else if let Interaction::Component(component) = interaction {
+            if component
+                .data
+                .custom_id
+                .starts_with(commands::gitlab_issue::BUTTON_PREFIX)
+            {
+                if let Err(why) = commands::gitlab_issue::run(&ctx, &component, &self.state).await {
+                    eprintln!("Failed to create GitLab issues: {why}");
+                }
+            }
         }
     }
 
@@ -54,22 +120,31 @@ impl EventHandler for Handler {
                         commands::ping::register(),
                         commands::test_command::register(),
                         commands::print_config::register(),
+                        commands::performance::register(),
+                        commands::retention::register(),
+                        commands::funnel::register(),
+                        commands::player::register(),
+                        commands::feedback::register(),
+                        commands::feedback_status::register(),
+                        commands::search::register(),
+                        commands::erase::register(),
                     ],
                 )
                 .await;
 
-            // println!("I now have the following guild slash commands: {commands:#?}");
+            if let Err(why) = commands {
+                eprintln!("Failed to register guild slash commands: {why}");
+            }
         }
     }
 }
 
-pub fn initialize() {
-    let state = crate::get_server_state();
+pub fn initialize(state: Arc<ServerState>) {
     let token = state.secrets.keys.discord_token.clone();
 
     tokio::task::spawn(async move {
         let mut client = Client::builder(token, GatewayIntents::empty())
-            .event_handler(Handler)
+            .event_handler(Handler { state })
             .await
             .expect("Error creating client");
 
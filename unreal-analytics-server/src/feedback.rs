@@ -0,0 +1,213 @@
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::gitlab::GitlabIssue;
+use crate::session::{FEEDBACK_COLLECTOR, SESSION_COLLECTOR};
+
+// Where a feedback comment is in triage
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackStatus {
+    New,
+    Acknowledged,
+    Fixed,
+    WontFix,
+}
+
+impl FeedbackStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeedbackStatus::New => "new",
+            FeedbackStatus::Acknowledged => "acknowledged",
+            FeedbackStatus::Fixed => "fixed",
+            FeedbackStatus::WontFix => "wont_fix",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "new" => Some(FeedbackStatus::New),
+            "acknowledged" => Some(FeedbackStatus::Acknowledged),
+            "fixed" => Some(FeedbackStatus::Fixed),
+            "wont_fix" => Some(FeedbackStatus::WontFix),
+            _ => None,
+        }
+    }
+}
+
+// A single feedback comment from the feedback collection
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FeedbackItem {
+    #[serde(
+        rename = "_id",
+        serialize_with = "mongodb::bson::serde_helpers::serialize_object_id_as_hex_string"
+    )]
+    pub id: ObjectId,
+    // _id of the session document the comment came from
+    #[serde(
+        rename = "SessionObjectID",
+        serialize_with = "mongodb::bson::serde_helpers::serialize_object_id_as_hex_string"
+    )]
+    pub session_object_id: ObjectId,
+    #[serde(rename = "SessionID", default)]
+    pub session_id: Option<String>,
+    #[serde(rename = "NetID", default)]
+    pub net_id: Option<String>,
+    #[serde(rename = "BuildVersion", default)]
+    pub build_version: Option<String>,
+    #[serde(rename = "Comment")]
+    pub comment: String,
+    #[serde(rename = "Status")]
+    pub status: FeedbackStatus,
+    #[serde(rename = "Tags", default)]
+    pub tags: Vec<String>,
+    #[serde(rename = "Assignee", default)]
+    pub assignee: Option<String>,
+    #[serde(
+        rename = "GitlabIssue",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gitlab_issue: Option<GitlabIssue>,
+    // When the session the comment was left in started
+    #[serde(
+        rename = "SessionTime",
+        deserialize_with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime::deserialize"
+    )]
+    pub session_time: chrono::DateTime<chrono::Utc>,
+    #[serde(
+        rename = "UpdatedTime",
+        deserialize_with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime::deserialize"
+    )]
+    pub updated_time: chrono::DateTime<chrono::Utc>,
+}
+
+// Narrows down which feedback gets listed
+#[derive(Debug, Clone, Default)]
+pub struct FeedbackQuery {
+    pub status: Option<FeedbackStatus>,
+    pub tag: Option<String>,
+    pub assignee: Option<String>,
+    // Case insensitive substring of the comment
+    pub search: Option<String>,
+}
+
+impl FeedbackQuery {
+    pub fn to_match_document(&self) -> Document {
+        let mut filter = doc! {};
+
+        if let Some(status) = self.status {
+            filter.insert("Status", status.as_str());
+        }
+        if let Some(tag) = &self.tag {
+            filter.insert("Tags", tag);
+        }
+        if let Some(assignee) = &self.assignee {
+            filter.insert("Assignee", assignee);
+        }
+        if let Some(search) = &self.search {
+            filter.insert(
+                "Comment",
+                doc! {"$regex": crate::utils::escape_regex(search), "$options": "i"},
+            );
+        }
+
+        filter
+    }
+}
+
+// Changes to a feedback comment's triage state, fields that are None are left alone
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct FeedbackUpdate {
+    pub status: Option<FeedbackStatus>,
+    pub tags: Option<Vec<String>>,
+    // An empty string unassigns the comment
+    pub assignee: Option<String>,
+}
+
+impl FeedbackUpdate {
+    pub fn to_update_document(&self) -> Document {
+        let mut set = doc! {"UpdatedTime": mongodb::bson::DateTime::now()};
+
+        if let Some(status) = self.status {
+            set.insert("Status", status.as_str());
+        }
+        if let Some(tags) = &self.tags {
+            let mut tags: Vec<String> = tags
+                .iter()
+                .map(|tag| tag.trim().to_lowercase())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            tags.sort();
+            tags.dedup();
+            set.insert("Tags", tags);
+        }
+        if let Some(assignee) = &self.assignee {
+            let assignee = assignee.trim();
+            set.insert(
+                "Assignee",
+                if assignee.is_empty() {
+                    Bson::Null
+                } else {
+                    Bson::String(assignee.to_string())
+                },
+            );
+        }
+
+        doc! {"$set": set}
+    }
+}
+
+// Makes a feedback record for every comment in a stored session document. The records are keyed
+// by session and comment index, so making them again for the same session gives the same keys.
+pub fn feedback_from_session(session_object_id: ObjectId, session: &Document) -> Vec<Document> {
+    let comments = match session
+        .get_document(FEEDBACK_COLLECTOR)
+        .and_then(|feedback| feedback.get_array("FeedbackComments"))
+    {
+        Ok(comments) => comments,
+        Err(_) => return Vec::new(),
+    };
+
+    let collector = session.get_document(SESSION_COLLECTOR).ok();
+    let collector_str = |key: &str| {
+        collector
+            .and_then(|collector| collector.get_str(key).ok())
+            .map(String::from)
+    };
+    let net_id = collector
+        .and_then(|collector| collector.get_array("PlayerControllerData").ok())
+        .and_then(|player_controllers| player_controllers.first())
+        .and_then(|player_controller| player_controller.as_document())
+        .and_then(|player_controller| player_controller.get_str("NetID").ok())
+        .map(String::from);
+
+    let now = mongodb::bson::DateTime::now();
+    let session_time = collector
+        .and_then(|collector| collector.get_datetime("StartTime").ok())
+        .copied()
+        .unwrap_or(now);
+
+    comments
+        .iter()
+        .enumerate()
+        .filter_map(|(i, comment)| Some((i, comment.as_str()?.trim())))
+        .filter(|(_, comment)| !comment.is_empty())
+        .map(|(i, comment)| {
+            doc! {
+                "SessionObjectID": session_object_id,
+                "CommentIndex": i as i32,
+                "SessionID": collector_str("SessionID"),
+                "NetID": &net_id,
+                "BuildVersion": collector_str("BuildVersion"),
+                "Comment": comment,
+                "Status": FeedbackStatus::New.as_str(),
+                "Tags": Vec::<String>::new(),
+                "Assignee": Bson::Null,
+                "SessionTime": session_time,
+                "CreatedTime": now,
+                "UpdatedTime": now,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,333 @@
+use mongodb::bson::{Bson, Document};
+use serde::Serialize;
+
+use crate::database::EventQuery;
+use crate::gameplay_event::is_valid_property_name;
+
+// Most steps a funnel can have
+const MAX_STEPS: usize = 10;
+
+// One step of a funnel, an event name and optionally a property value the event has to have
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FunnelStep {
+    pub name: String,
+    pub property: Option<(String, String)>,
+}
+
+impl FunnelStep {
+    // Parses "Name" or "Name:Property=Value"
+    pub fn parse(step: &str) -> Result<Self, String> {
+        let step = step.trim();
+        let (name, property) = match step.split_once(':') {
+            Some((name, property)) => {
+                let (property, value) = property
+                    .split_once('=')
+                    .ok_or(format!("Step `{}` is missing a property value", step))?;
+                let property = property.trim();
+                if !is_valid_property_name(property) {
+                    return Err(format!("Step `{}` has an invalid property name", step));
+                }
+                (
+                    name.trim(),
+                    Some((property.to_string(), value.trim().to_string())),
+                )
+            }
+            None => (step, None),
+        };
+
+        if name.is_empty() || name.len() > 128 {
+            return Err(format!("Step `{}` needs an event name", step));
+        }
+
+        Ok(FunnelStep {
+            name: name.to_string(),
+            property,
+        })
+    }
+
+    // Parses a comma separated list of steps
+    pub fn parse_list(steps: &str) -> Result<Vec<Self>, String> {
+        let steps = steps
+            .split(',')
+            .filter(|step| !step.trim().is_empty())
+            .map(FunnelStep::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if steps.is_empty() || steps.len() > MAX_STEPS {
+            return Err(format!("A funnel needs between 1 and {} steps", MAX_STEPS));
+        }
+
+        Ok(steps)
+    }
+
+    fn matches(&self, event: &FunnelEvent) -> bool {
+        if event.name != self.name {
+            return false;
+        }
+
+        match &self.property {
+            Some((property, value)) => event
+                .properties
+                .get(property)
+                .is_some_and(|actual| property_to_string(actual) == *value),
+            None => true,
+        }
+    }
+}
+
+impl std::fmt::Display for FunnelStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.property {
+            Some((property, value)) => write!(f, "{}:{}={}", self.name, property, value),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+// Property values are compared as text so that "2" in a step matches both 2 and "2" in an event
+fn property_to_string(value: &Bson) -> String {
+    match value {
+        Bson::String(value) => value.clone(),
+        Bson::Double(value) if value.fract() == 0.0 => format!("{}", *value as i64),
+        value => value.to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunnelQuery {
+    pub steps: Vec<FunnelStep>,
+    // Build and date range of the events, the name is ignored
+    pub events: EventQuery,
+    // Every step has to happen within this long of the first one
+    pub window: Option<chrono::Duration>,
+}
+
+// An event as seen by the funnel, one player's events are sorted by timestamp
+#[derive(Debug, Clone)]
+pub struct FunnelEvent {
+    pub name: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub properties: Document,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FunnelStepResult {
+    pub step: String,
+    // Players that got at least this far
+    pub players: u64,
+    // Share of the players from the first step that got this far
+    pub conversion: f64,
+    // Share of the players from the previous step that got this far
+    pub step_conversion: f64,
+    // Players from the previous step that didn't get this far
+    pub drop_off: u64,
+}
+
+impl FunnelQuery {
+    // How many steps in a row a player completed. Every occurrence of the first step is tried as
+    // a starting point so that an early attempt that ran out of time doesn't hide a later one.
+    fn steps_completed(&self, events: &[FunnelEvent]) -> usize {
+        let mut best = 0;
+
+        for (start, first) in events.iter().enumerate() {
+            if !self.steps[0].matches(first) {
+                continue;
+            }
+
+            let mut completed = 1;
+            for event in &events[start + 1..] {
+                if completed == self.steps.len() {
+                    break;
+                }
+                if let Some(window) = self.window {
+                    if event.timestamp - first.timestamp > window {
+                        break;
+                    }
+                }
+                if self.steps[completed].matches(event) {
+                    completed += 1;
+                }
+            }
+
+            best = best.max(completed);
+            if best == self.steps.len() {
+                break;
+            }
+        }
+
+        best
+    }
+
+    // Starts counting a funnel. Players are added one at a time so they never all have to be held.
+    pub fn counts(&self) -> FunnelCounts<'_> {
+        FunnelCounts {
+            query: self,
+            reached: vec![0u64; self.steps.len()],
+        }
+    }
+}
+
+// How many players reached each step of a funnel so far
+pub struct FunnelCounts<'a> {
+    query: &'a FunnelQuery,
+    reached: Vec<u64>,
+}
+
+impl FunnelCounts<'_> {
+    // Counts one player's events, sorted by time
+    pub fn add_player(&mut self, events: &[FunnelEvent]) {
+        let completed = self.query.steps_completed(events);
+        for count in self.reached.iter_mut().take(completed) {
+            *count += 1;
+        }
+    }
+
+    // Works out the funnel from every player added
+    pub fn results(self) -> Vec<FunnelStepResult> {
+        let reached = self.reached;
+        let first = reached.first().copied().unwrap_or(0);
+        let ratio = |count: u64, total: u64| {
+            if total > 0 {
+                count as f64 / total as f64
+            } else {
+                0.0
+            }
+        };
+
+        self.query
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let previous = if i > 0 { reached[i - 1] } else { reached[i] };
+                FunnelStepResult {
+                    step: step.to_string(),
+                    players: reached[i],
+                    conversion: ratio(reached[i], first),
+                    step_conversion: ratio(reached[i], previous),
+                    drop_off: previous - reached[i],
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn query(steps: &str, window_secs: Option<i64>) -> FunnelQuery {
+        FunnelQuery {
+            steps: FunnelStep::parse_list(steps).unwrap(),
+            events: EventQuery::default(),
+            window: window_secs.map(chrono::Duration::seconds),
+        }
+    }
+
+    // Events a second apart, in the order given
+    fn events(names: &[&str]) -> Vec<FunnelEvent> {
+        let start = chrono::DateTime::UNIX_EPOCH;
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| FunnelEvent {
+                name: name.to_string(),
+                timestamp: start + chrono::Duration::seconds(i as i64),
+                properties: Document::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_steps_with_properties() {
+        let steps = FunnelStep::parse_list(" Tutorial , Level:Index=2,,Boss").unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                FunnelStep {
+                    name: "Tutorial".to_string(),
+                    property: None,
+                },
+                FunnelStep {
+                    name: "Level".to_string(),
+                    property: Some(("Index".to_string(), "2".to_string())),
+                },
+                FunnelStep {
+                    name: "Boss".to_string(),
+                    property: None,
+                },
+            ]
+        );
+
+        assert!(FunnelStep::parse("Level:Index").is_err());
+        assert!(FunnelStep::parse(":Index=2").is_err());
+        assert!(FunnelStep::parse_list(" , ").is_err());
+        assert!(FunnelStep::parse_list(&["A"; MAX_STEPS + 1].join(",")).is_err());
+    }
+
+    #[test]
+    fn steps_have_to_happen_in_order() {
+        let query = query("A,B,C", None);
+        assert_eq!(query.steps_completed(&events(&["A", "B", "C"])), 3);
+        assert_eq!(
+            query.steps_completed(&events(&["A", "X", "B", "X", "C"])),
+            3
+        );
+        assert_eq!(query.steps_completed(&events(&["B", "C", "A"])), 1);
+        assert_eq!(query.steps_completed(&events(&["C", "A", "B"])), 2);
+        assert_eq!(query.steps_completed(&events(&["B", "C"])), 0);
+        assert_eq!(query.steps_completed(&[]), 0);
+    }
+
+    #[test]
+    fn window_is_measured_from_the_first_step() {
+        let query = query("A,B,C", Some(2));
+        assert_eq!(query.steps_completed(&events(&["A", "B", "C"])), 3);
+        assert_eq!(query.steps_completed(&events(&["A", "B", "X", "C"])), 2);
+        // The first attempt runs out of time, the second one doesn't
+        assert_eq!(
+            query.steps_completed(&events(&["A", "X", "X", "A", "B", "C"])),
+            3
+        );
+    }
+
+    #[test]
+    fn properties_match_as_text() {
+        let query = query("Level:Index=2", None);
+        let mut events = events(&["Level", "Level", "Level"]);
+        events[0].properties = doc! {"Index": 1};
+        assert_eq!(query.steps_completed(&events[..1]), 0);
+        events[1].properties = doc! {"Index": 2.0};
+        assert_eq!(query.steps_completed(&events[..2]), 1);
+        events[2].properties = doc! {"Index": "2"};
+        assert_eq!(query.steps_completed(&events[2..]), 1);
+    }
+
+    #[test]
+    fn counts_conversion_and_drop_off() {
+        let query = query("A,B,C", None);
+        let mut counts = query.counts();
+        counts.add_player(&events(&["A", "B", "C"]));
+        counts.add_player(&events(&["A", "B"]));
+        counts.add_player(&events(&["A"]));
+        counts.add_player(&events(&["A", "C"]));
+        let results = counts.results();
+
+        let players: Vec<u64> = results.iter().map(|result| result.players).collect();
+        assert_eq!(players, vec![4, 2, 1]);
+        let drop_off: Vec<u64> = results.iter().map(|result| result.drop_off).collect();
+        assert_eq!(drop_off, vec![0, 2, 1]);
+        assert_eq!(results[0].conversion, 1.0);
+        assert_eq!(results[2].conversion, 0.25);
+        assert_eq!(results[2].step_conversion, 0.5);
+    }
+
+    #[test]
+    fn empty_funnel_has_no_conversion() {
+        let results = query("A,B", None).counts().results();
+        assert!(results
+            .iter()
+            .all(|result| result.players == 0 && result.conversion == 0.0));
+    }
+}
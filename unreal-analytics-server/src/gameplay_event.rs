@@ -0,0 +1,104 @@
+use chrono::NaiveDateTime;
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+
+use rocket::serde::json::{serde_json::Map, Value};
+
+use crate::session::{unreal_date_time, FieldError};
+
+// A designer defined event sent from Blueprint, like a level being completed or a dialogue choice.
+// The server doesn't know anything about specific events, everything event specific goes in
+// `properties`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GameplayEvent {
+    #[serde(rename = "Name")]
+    pub name: String,
+
+    #[serde(rename = "Timestamp", with = "unreal_date_time")]
+    pub timestamp: NaiveDateTime,
+
+    #[serde(rename = "SessionID")]
+    pub session_id: String,
+
+    #[serde(rename = "NetID", default)]
+    pub net_id: Option<String>,
+
+    #[serde(rename = "Build", default)]
+    pub build: Option<String>,
+
+    #[serde(rename = "Properties", default)]
+    pub properties: Map<String, Value>,
+
+    // False when the player turned telemetry off, older builds don't send it
+    #[serde(
+        rename = "TelemetryConsent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub telemetry_consent: Option<bool>,
+}
+
+// Property names end up in mongo field paths, so keep them to plain identifiers
+pub fn is_valid_property_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl GameplayEvent {
+    // Deserializes and validates an event. `field_prefix` is put in front of the field names in
+    // errors so that errors can be matched up with the event they came from.
+    pub fn from_value(value: Value, field_prefix: &str) -> Result<Self, Vec<FieldError>> {
+        let event: GameplayEvent = serde_path_to_error::deserialize(value).map_err(|err| {
+            let field = match err.path().to_string().as_str() {
+                "." => field_prefix.to_string(),
+                path => format!("{}.{}", field_prefix, path),
+            };
+
+            vec![FieldError::new(field, err.into_inner().to_string())]
+        })?;
+
+        let mut errors = Vec::new();
+
+        if event.name.trim().is_empty() || event.name.len() > 128 {
+            errors.push(FieldError::new(
+                format!("{}.Name", field_prefix),
+                "Name must be between 1 and 128 characters",
+            ));
+        }
+
+        if event.session_id.trim().is_empty() || event.session_id.len() > 128 {
+            errors.push(FieldError::new(
+                format!("{}.SessionID", field_prefix),
+                "SessionID must be between 1 and 128 characters",
+            ));
+        }
+
+        for property in event.properties.keys() {
+            if !is_valid_property_name(property) {
+                errors.push(FieldError::new(
+                    format!("{}.Properties.{}", field_prefix, property),
+                    "property names may only contain letters, numbers and underscores",
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(event)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn to_document(&self) -> mongodb::bson::ser::Result<Document> {
+        Ok(doc! {
+            "Name": &self.name,
+            "Timestamp": self.timestamp.and_utc(),
+            "SessionID": &self.session_id,
+            "NetID": &self.net_id,
+            "Build": &self.build,
+            "Properties": mongodb::bson::to_document(&self.properties)?,
+            "ReceivedTime": mongodb::bson::DateTime::now(),
+        })
+    }
+}
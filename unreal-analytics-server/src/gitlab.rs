@@ -0,0 +1,464 @@
+use mongodb::bson::{oid::ObjectId, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::config::GitlabConfig;
+use crate::database::CrashGroup;
+use crate::feedback::FeedbackItem;
+use crate::session::SESSION_COLLECTOR;
+use crate::ServerState;
+
+// An issue made by this server, stored on the feedback comment or crash group it was made from
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitlabIssue {
+    #[serde(rename = "IID", alias = "iid")]
+    pub iid: u64,
+    #[serde(rename = "WebURL", alias = "web_url")]
+    pub web_url: String,
+}
+
+#[derive(Debug)]
+pub enum GitlabError {
+    // There's no gitlab_token in the secrets
+    NotConfigured,
+    Database(mongodb::error::Error),
+    Http(reqwest::Error),
+    // GitLab answered with something other than 201 Created
+    Api(reqwest::StatusCode, String),
+    // Someone else is filing the same issue right now
+    InProgress,
+}
+
+impl std::fmt::Display for GitlabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GitlabError::NotConfigured => write!(f, "GitLab token is not configured"),
+            GitlabError::Database(e) => write!(f, "Database error: {}", e),
+            GitlabError::Http(e) => write!(f, "GitLab request failed: {}", e),
+            GitlabError::Api(status, body) => write!(f, "GitLab returned {}: {}", status, body),
+            GitlabError::InProgress => write!(f, "The issue is already being created"),
+        }
+    }
+}
+
+impl std::error::Error for GitlabError {}
+
+impl From<mongodb::error::Error> for GitlabError {
+    fn from(e: mongodb::error::Error) -> Self {
+        GitlabError::Database(e)
+    }
+}
+
+impl From<reqwest::Error> for GitlabError {
+    fn from(e: reqwest::Error) -> Self {
+        GitlabError::Http(e)
+    }
+}
+
+// Title and markdown description of an issue that's about to be created
+#[derive(Debug, Clone)]
+pub struct NewIssue {
+    pub title: String,
+    pub description: String,
+    pub labels: Vec<String>,
+}
+
+// Keeps issue titles to one short line
+fn title(prefix: &str, text: &str) -> String {
+    let line = text.lines().next().unwrap_or("").trim();
+    let mut title: String = line.chars().take(80).collect();
+    if title.len() < line.len() {
+        title += "...";
+    }
+    format!("{}: {}", prefix, title)
+}
+
+// Session metadata as a markdown table, so whoever picks up the issue knows where it came from
+fn session_table(session: &Document) -> String {
+    let collector = match session.get_document(SESSION_COLLECTOR) {
+        Ok(collector) => collector,
+        Err(_) => return String::new(),
+    };
+
+    let mut rows = Vec::new();
+    if let Ok(id) = session.get_object_id("_id") {
+        rows.push(("Session", id.to_hex()));
+    }
+    for field in [
+        "SessionID",
+        "BuildVersion",
+        "Changelist",
+        "Platform",
+        "BuildConfiguration",
+        "CountryName",
+    ] {
+        match collector.get(field) {
+            Some(Bson::String(value)) => rows.push((field, value.clone())),
+            Some(Bson::Null) | None => {}
+            Some(value) => rows.push((field, value.to_string())),
+        }
+    }
+    if let (Ok(start_time), Ok(end_time)) = (
+        collector.get_datetime("StartTime"),
+        collector.get_datetime("EndTime"),
+    ) {
+        let duration = end_time.to_chrono() - start_time.to_chrono();
+        rows.push(("StartTime", start_time.to_chrono().to_rfc3339()));
+        rows.push((
+            "Duration",
+            crate::utils::session_duration_to_string(&duration),
+        ));
+    }
+    if let Ok(is_pie) = collector.get_bool("IsPlayInEditorSession") {
+        rows.push(("IsPlayInEditorSession", is_pie.to_string()));
+    }
+
+    let mut table = "| | |\n|---|---|\n".to_string();
+    for (name, value) in rows {
+        table += &format!("| {} | `{}` |\n", name, value.replace('|', "\\|"));
+    }
+    table
+}
+
+pub fn feedback_issue(
+    config: &GitlabConfig,
+    feedback: &FeedbackItem,
+    session: Option<&Document>,
+) -> NewIssue {
+    // No NetID, erasing a player's data can't reach issues that were already made
+    let mut description = format!("> {}\n\n", feedback.comment.replace('\n', "\n> "));
+    if let Some(session) = session {
+        description += &session_table(session);
+    }
+    description += &format!("\nFeedback id: `{}`\n", feedback.id.to_hex());
+
+    let mut labels = config.feedback_labels.clone();
+    labels.extend(feedback.tags.iter().cloned());
+
+    NewIssue {
+        title: title("Feedback", &feedback.comment),
+        description,
+        labels,
+    }
+}
+
+pub fn crash_issue(config: &GitlabConfig, group: &CrashGroup) -> NewIssue {
+    let message = group
+        .error_message
+        .as_deref()
+        .or(group.crash_type.as_deref())
+        .unwrap_or("Unknown crash");
+
+    let mut description = format!(
+        "**{}**\n\nSeen {} times between {} and {}\n\nBuilds: {}\n\n",
+        message,
+        group.count,
+        group.first_seen.to_rfc3339(),
+        group.last_seen.to_rfc3339(),
+        group.build_versions.join(", ")
+    );
+    if !group.signature_frames.is_empty() {
+        description += &format!("```\n{}\n```\n\n", group.signature_frames.join("\n"));
+    }
+    description += &format!(
+        "Signature: `{}`\nLast crash: `{}`\n",
+        group.signature,
+        group.last_crash_id.to_hex()
+    );
+
+    NewIssue {
+        title: title("Crash", message),
+        description,
+        labels: config.crash_labels.clone(),
+    }
+}
+
+// Creates an issue in the configured project
+pub async fn create_issue(
+    config: &GitlabConfig,
+    token: &str,
+    issue: &NewIssue,
+) -> Result<GitlabIssue, GitlabError> {
+    if token.is_empty() {
+        return Err(GitlabError::NotConfigured);
+    }
+
+    // Project paths like "group/project" have to be url encoded
+    let project = config.project_id.replace('/', "%2F");
+    let url = format!(
+        "{}/api/v4/projects/{}/issues",
+        config.base_url.trim_end_matches('/'),
+        project
+    );
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .header("PRIVATE-TOKEN", token)
+        .form(&[
+            ("title", issue.title.as_str()),
+            ("description", issue.description.as_str()),
+            ("labels", &issue.labels.join(",")),
+        ])
+        .send()
+        .await?;
+
+    let status = response.status();
+    if status != reqwest::StatusCode::CREATED {
+        let body = response.text().await.unwrap_or_default();
+        return Err(GitlabError::Api(status, body));
+    }
+
+    Ok(response.json::<GitlabIssue>().await?)
+}
+
+fn read_config(state: &ServerState) -> (GitlabConfig, String) {
+    let config = state
+        .read_config()
+        .unwrap_or_else(|| state.default_config.clone())
+        .gitlab;
+
+    (config, state.secrets.keys.gitlab_token.clone())
+}
+
+// Files a feedback comment as an issue, or returns the issue it was already filed as. Returns None
+// if there's no comment with that id, and InProgress while someone else is filing it.
+pub async fn file_feedback(
+    state: &ServerState,
+    id: ObjectId,
+) -> Result<Option<GitlabIssue>, GitlabError> {
+    let storage = state.storage.as_ref();
+    let feedback = match storage.get_feedback_item(id).await? {
+        Some(feedback) => feedback,
+        None => return Ok(None),
+    };
+    if let Some(issue) = feedback.gitlab_issue {
+        return Ok(Some(issue));
+    }
+
+    // Two clicks at once both get this far, only the one that claims the comment files it
+    if !storage.claim_feedback_gitlab_issue(id).await? {
+        return match storage.get_feedback_item(id).await? {
+            Some(FeedbackItem {
+                gitlab_issue: Some(issue),
+                ..
+            }) => Ok(Some(issue)),
+            _ => Err(GitlabError::InProgress),
+        };
+    }
+
+    let issue = async {
+        let session = storage
+            .get_session_document(feedback.session_object_id)
+            .await?;
+        let (config, token) = read_config(state);
+        create_issue(
+            &config,
+            &token,
+            &feedback_issue(&config, &feedback, session.as_ref()),
+        )
+        .await
+    }
+    .await;
+
+    match issue {
+        Ok(issue) => {
+            storage.set_feedback_gitlab_issue(id, &issue).await?;
+            Ok(Some(issue))
+        }
+        Err(e) => {
+            if let Err(e) = storage.release_feedback_gitlab_issue(id).await {
+                eprintln!("Failed to release GitLab issue claim! Error: {}", e);
+            }
+            Err(e)
+        }
+    }
+}
+
+// Files a crash group as an issue, or returns the issue it was already filed as. Returns None if
+// there's no crash group with that signature.
+pub async fn file_crash_group(
+    state: &ServerState,
+    signature: &str,
+) -> Result<Option<GitlabIssue>, GitlabError> {
+    let storage = state.storage.as_ref();
+    let group = match storage.get_crash_group(signature).await? {
+        Some(group) => group,
+        None => return Ok(None),
+    };
+    if let Some(issue) = group.gitlab_issue {
+        return Ok(Some(issue));
+    }
+
+    if !storage.claim_crash_group_gitlab_issue(signature).await? {
+        return match storage.get_crash_group(signature).await? {
+            Some(CrashGroup {
+                gitlab_issue: Some(issue),
+                ..
+            }) => Ok(Some(issue)),
+            _ => Err(GitlabError::InProgress),
+        };
+    }
+
+    let (config, token) = read_config(state);
+    match create_issue(&config, &token, &crash_issue(&config, &group)).await {
+        Ok(issue) => {
+            storage
+                .set_crash_group_gitlab_issue(signature, &issue)
+                .await?;
+            Ok(Some(issue))
+        }
+        Err(e) => {
+            if let Err(e) = storage.release_crash_group_gitlab_issue(signature).await {
+                eprintln!("Failed to release GitLab issue claim! Error: {}", e);
+            }
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // Answers one request with `status` and `body`, and returns the request line, headers and body
+    // it got
+    fn mock_gitlab(status: &str, body: &str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                request += &line;
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request += &String::from_utf8(body).unwrap();
+
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            request
+        });
+
+        (base_url, handle)
+    }
+
+    fn config(base_url: String) -> GitlabConfig {
+        GitlabConfig {
+            base_url,
+            project_id: "group/project".to_string(),
+            feedback_labels: vec!["feedback".to_string()],
+            crash_labels: vec!["crash".to_string()],
+            session_message_button: false,
+        }
+    }
+
+    fn new_issue() -> NewIssue {
+        NewIssue {
+            title: "Feedback: stuck in the elevator".to_string(),
+            description: "> stuck in the elevator".to_string(),
+            labels: vec!["feedback".to_string(), "level2".to_string()],
+        }
+    }
+
+    #[rocket::async_test]
+    async fn creates_issues() {
+        let (base_url, handle) = mock_gitlab(
+            "201 Created",
+            r#"{"id": 1, "iid": 42, "web_url": "https://gitlab.example.com/group/project/-/issues/42"}"#,
+        );
+
+        let issue = create_issue(&config(base_url + "/"), "token", &new_issue())
+            .await
+            .unwrap();
+        assert_eq!(issue.iid, 42);
+        assert_eq!(
+            issue.web_url,
+            "https://gitlab.example.com/group/project/-/issues/42"
+        );
+
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST /api/v4/projects/group%2Fproject/issues HTTP/1.1\r\n"));
+        assert!(request.to_lowercase().contains("private-token: token\r\n"));
+        assert!(request.ends_with(
+            "title=Feedback%3A+stuck+in+the+elevator&description=%3E+stuck+in+the+elevator&labels=feedback%2Clevel2"
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn reports_gitlab_errors() {
+        let (base_url, handle) = mock_gitlab("403 Forbidden", r#"{"message": "403 Forbidden"}"#);
+
+        match create_issue(&config(base_url), "token", &new_issue()).await {
+            Err(GitlabError::Api(status, body)) => {
+                assert_eq!(status, reqwest::StatusCode::FORBIDDEN);
+                assert_eq!(body, r#"{"message": "403 Forbidden"}"#);
+            }
+            result => panic!("expected an API error, got {:?}", result),
+        }
+        handle.join().unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn reports_bad_responses() {
+        let (base_url, handle) = mock_gitlab("201 Created", "<html>not json</html>");
+
+        let result = create_issue(&config(base_url), "token", &new_issue()).await;
+        assert!(matches!(result, Err(GitlabError::Http(_))));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn feedback_issues_leave_out_the_player() {
+        let feedback = FeedbackItem {
+            id: ObjectId::new(),
+            session_object_id: ObjectId::new(),
+            session_id: Some("session".to_string()),
+            net_id: Some("76561197960287930".to_string()),
+            build_version: None,
+            comment: "stuck in the elevator\nplease help".to_string(),
+            status: crate::feedback::FeedbackStatus::New,
+            tags: vec!["level2".to_string()],
+            assignee: None,
+            gitlab_issue: None,
+            session_time: chrono::Utc::now(),
+            updated_time: chrono::Utc::now(),
+        };
+
+        let issue = feedback_issue(&config(String::new()), &feedback, None);
+        assert_eq!(issue.title, "Feedback: stuck in the elevator");
+        assert!(issue
+            .description
+            .starts_with("> stuck in the elevator\n> please help\n\n"));
+        assert!(!issue.description.contains("76561197960287930"));
+        assert_eq!(issue.labels, vec!["feedback", "level2"]);
+    }
+
+    #[rocket::async_test]
+    async fn needs_a_token() {
+        // Nothing listens here, the request must not be made at all
+        let result =
+            create_issue(&config("http://127.0.0.1:1".to_string()), "", &new_issue()).await;
+        assert!(matches!(result, Err(GitlabError::NotConfigured)));
+    }
+}
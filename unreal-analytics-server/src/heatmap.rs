@@ -0,0 +1,215 @@
+use chrono::NaiveDateTime;
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::config::MapBounds;
+use crate::session::{unreal_date_time, FieldError};
+
+// A world position recorded by the game, like where a player died or got stuck
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PositionSample {
+    #[serde(rename = "SessionID")]
+    pub session_id: String,
+
+    #[serde(rename = "NetID", default)]
+    pub net_id: Option<String>,
+
+    #[serde(rename = "Map")]
+    pub map: String,
+
+    #[serde(rename = "EventType")]
+    pub event_type: String,
+
+    #[serde(rename = "X")]
+    pub x: f64,
+
+    #[serde(rename = "Y")]
+    pub y: f64,
+
+    #[serde(rename = "Z", default)]
+    pub z: f64,
+
+    // Time the sample was taken, the server's receive time is used when it's missing
+    #[serde(
+        rename = "Timestamp",
+        with = "unreal_date_time::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp: Option<NaiveDateTime>,
+
+    // False when the player turned telemetry off, older builds don't send it
+    #[serde(
+        rename = "TelemetryConsent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub telemetry_consent: Option<bool>,
+}
+
+impl PositionSample {
+    pub fn validate(&self, field_prefix: &str) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        let required = [
+            ("SessionID", &self.session_id),
+            ("Map", &self.map),
+            ("EventType", &self.event_type),
+        ];
+        for (field, value) in required {
+            if value.trim().is_empty() || value.len() > 128 {
+                errors.push(FieldError::new(
+                    format!("{}.{}", field_prefix, field),
+                    format!("{} must be between 1 and 128 characters", field),
+                ));
+            }
+        }
+
+        let coordinates = [("X", self.x), ("Y", self.y), ("Z", self.z)];
+        for (field, value) in coordinates {
+            if !value.is_finite() {
+                errors.push(FieldError::new(
+                    format!("{}.{}", field_prefix, field),
+                    "coordinate must be a finite number",
+                ));
+            }
+        }
+
+        errors
+    }
+
+    pub fn to_document(&self) -> Document {
+        let now = mongodb::bson::DateTime::now();
+
+        doc! {
+            "SessionID": &self.session_id,
+            "NetID": &self.net_id,
+            "Map": &self.map,
+            "EventType": &self.event_type,
+            "X": self.x,
+            "Y": self.y,
+            "Z": self.z,
+            "Timestamp": self.timestamp.map(|timestamp| mongodb::bson::DateTime::from_chrono(timestamp.and_utc())).unwrap_or(now),
+            "ReceivedTime": now,
+        }
+    }
+}
+
+// Positions binned into a 2D grid over a map. X runs along the columns and Y along the rows, with
+// the first row at min_y.
+#[derive(Serialize, Debug, Clone)]
+pub struct HeatmapGrid {
+    pub map: String,
+    pub event_type: Option<String>,
+    pub bounds: MapBounds,
+    pub width: u32,
+    pub height: u32,
+    pub total: u64,
+    pub max: u64,
+    // Row major, width * height cells
+    pub cells: Vec<u64>,
+}
+
+impl HeatmapGrid {
+    // Makes an empty grid with `resolution` cells along the longest side of the bounds
+    pub fn new(
+        map: String,
+        event_type: Option<String>,
+        bounds: MapBounds,
+        resolution: u32,
+    ) -> Self {
+        let resolution = resolution.clamp(1, 1024);
+        let size_x = (bounds.max_x - bounds.min_x).max(f64::EPSILON);
+        let size_y = (bounds.max_y - bounds.min_y).max(f64::EPSILON);
+
+        let (width, height) = if size_x >= size_y {
+            let height = (resolution as f64 * size_y / size_x).ceil() as u32;
+            (resolution, height.max(1))
+        } else {
+            let width = (resolution as f64 * size_x / size_y).ceil() as u32;
+            (width.max(1), resolution)
+        };
+
+        HeatmapGrid {
+            map,
+            event_type,
+            bounds,
+            width,
+            height,
+            total: 0,
+            max: 0,
+            cells: vec![0; (width * height) as usize],
+        }
+    }
+
+    // World space size of a single cell
+    pub fn cell_size(&self) -> (f64, f64) {
+        (
+            (self.bounds.max_x - self.bounds.min_x).max(f64::EPSILON) / self.width as f64,
+            (self.bounds.max_y - self.bounds.min_y).max(f64::EPSILON) / self.height as f64,
+        )
+    }
+
+    pub fn add(&mut self, cell_x: i64, cell_y: i64, count: u64) {
+        // Positions exactly on the max edge land one past the last cell
+        let cell_x = cell_x.clamp(0, self.width as i64 - 1) as u32;
+        let cell_y = cell_y.clamp(0, self.height as i64 - 1) as u32;
+
+        let cell = &mut self.cells[(cell_y * self.width + cell_x) as usize];
+        *cell += count;
+
+        self.total += count;
+        self.max = self.max.max(*cell);
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut pixels = Vec::with_capacity(self.cells.len() * 4);
+        for &count in &self.cells {
+            pixels.extend_from_slice(&heat_color(count, self.max));
+        }
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&pixels)?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+// Maps a cell count to a colour going from blue through green and yellow to red. Uses a log scale
+// so that a few very hot cells don't wash out everything else. Empty cells are transparent.
+fn heat_color(count: u64, max: u64) -> [u8; 4] {
+    if count == 0 || max == 0 {
+        return [0, 0, 0, 0];
+    }
+
+    let t = ((count as f64).ln_1p() / (max as f64).ln_1p()).clamp(0.0, 1.0);
+    let stops: [(f64, [f64; 3]); 4] = [
+        (0.0, [0.0, 0.0, 255.0]),
+        (0.33, [0.0, 255.0, 0.0]),
+        (0.66, [255.0, 255.0, 0.0]),
+        (1.0, [255.0, 0.0, 0.0]),
+    ];
+
+    let mut color = stops[0].1;
+    for pair in stops.windows(2) {
+        let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+        if t >= t0 && t <= t1 {
+            let f = (t - t0) / (t1 - t0);
+            color = [
+                c0[0] + (c1[0] - c0[0]) * f,
+                c0[1] + (c1[1] - c0[1]) * f,
+                c0[2] + (c1[2] - c0[2]) * f,
+            ];
+            break;
+        }
+    }
+
+    [color[0] as u8, color[1] as u8, color[2] as u8, 200]
+}
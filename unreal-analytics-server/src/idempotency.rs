@@ -0,0 +1,32 @@
+use rocket::http::Status;
+
+use rocket::request::Outcome;
+use rocket::request::{self, FromRequest, Request};
+
+// Longest key we'll accept, anything longer is almost certainly not a GUID
+const MAX_KEY_LENGTH: usize = 128;
+
+// The value of the optional Idempotency-Key header. Retries of the same upload should send the same
+// key so the server can recognize them and return the original result instead of storing it again.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey(pub Option<String>);
+
+#[derive(Debug)]
+pub enum IdempotencyKeyError {
+    Invalid,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = IdempotencyKeyError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("Idempotency-Key").map(str::trim) {
+            Some(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => {
+                Outcome::Success(IdempotencyKey(Some(key.to_string())))
+            }
+            Some(_) => Outcome::Error((Status::BadRequest, IdempotencyKeyError::Invalid)),
+            None => Outcome::Success(IdempotencyKey(None)),
+        }
+    }
+}
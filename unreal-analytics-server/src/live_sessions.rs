@@ -0,0 +1,25 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ServerState;
+
+// Spawns the task that marks live sessions as abandoned once they stop sending heartbeats
+pub fn initialize(state: Arc<ServerState>) {
+    tokio::task::spawn(async move {
+        loop {
+            let config = match state.read_config() {
+                Some(config) => config.live_sessions,
+                None => state.default_config.live_sessions.clone(),
+            };
+
+            let timeout = chrono::TimeDelta::seconds(config.heartbeat_timeout_secs as i64);
+            match state.storage.sweep_abandoned_sessions(timeout).await {
+                Ok(0) => {}
+                Ok(abandoned) => println!("Live sessions: marked {} as abandoned", abandoned),
+                Err(e) => eprintln!("Live sessions: failed to sweep abandoned sessions: {}", e),
+            }
+
+            tokio::time::sleep(Duration::from_secs(config.sweep_interval_secs.max(1))).await;
+        }
+    });
+}
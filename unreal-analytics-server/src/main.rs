@@ -1,27 +1,43 @@
 pub mod auth;
+pub mod build_info;
 pub mod cloudflare;
 pub mod commands;
 pub mod config;
+pub mod crash;
 pub mod database;
 pub mod discord_bot;
+pub mod feedback;
+pub mod funnel;
+pub mod gameplay_event;
+pub mod gitlab;
+pub mod heatmap;
+pub mod idempotency;
+pub mod live_sessions;
+pub mod migrations;
+pub mod opt_outs;
+pub mod privacy;
+pub mod reporting;
+pub mod retention;
+pub mod rollups;
 pub mod routes;
+pub mod scrubbing;
+pub mod session;
+pub mod storage;
 pub mod utils;
 
-use once_cell::sync::OnceCell;
 use std::sync::Arc;
 use std::sync::RwLock;
 
-#[macro_use]
-extern crate rocket;
-
 use rocket::routes;
 
 #[derive(Debug)]
 pub struct ServerState {
-    pub db: database::Database,
+    // Everything the server records, in mongo unless the config picks another backend
+    pub storage: Arc<dyn storage::Storage>,
     pub default_config: config::Config,
     pub config: RwLock<config::Config>, // Config can be changed later
     pub secrets: config::Secrets,
+    pub scrubber: scrubbing::Scrubber,
 }
 
 impl ServerState {
@@ -31,41 +47,96 @@ impl ServerState {
 
         Some(lock.clone())
     }
-}
 
-pub static SERVER_STATE: OnceCell<Arc<ServerState>> = OnceCell::new();
-pub fn get_server_state() -> Arc<ServerState> {
-    SERVER_STATE.get().unwrap().clone()
+    // How IPs are stored with the current config
+    pub fn ip_policy(&self) -> privacy::IpPolicy {
+        let config = self.read_config().unwrap_or(self.default_config.clone());
+        privacy::IpPolicy::new(&config, &self.secrets)
+    }
 }
 
-async fn initialize() {
+async fn initialize() -> Arc<ServerState> {
     let config = config::read_config();
     let keys = config::read_secrets();
+    privacy::check_config(&config, &keys);
+    let scrubber = scrubbing::Scrubber::new(&config.scrubbing).unwrap_or_else(|e| {
+        eprintln!("Invalid scrubbing config! {}", e);
+        std::process::exit(1);
+    });
 
-    let db = database::connect_to_db(&config);
-    database::fixup_database(&db).await.unwrap();
+    let storage = storage::create_storage(&config);
+    if let Err(e) = storage.migrate().await {
+        eprintln!("Failed to prepare the database! Error: {}", e);
+        std::process::exit(1);
+    }
 
-    let state = Arc::new(ServerState {
-        db,
+    Arc::new(ServerState {
+        storage,
         default_config: config.clone(),
         config: RwLock::new(config),
         secrets: keys,
-    });
-
-    SERVER_STATE.set(state.clone()).unwrap();
+        scrubber,
+    })
 }
 
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
-    initialize().await;
+async fn main() -> Result<(), Box<rocket::Error>> {
+    // `migrate [--dry-run]` applies pending migrations and `rebuild-rollups` recomputes the daily
+    // rollups and first seen days, both exit without starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("migrate") => {
+            migrations::run_cli(&args[1..]).await;
+            return Ok(());
+        }
+        Some("rebuild-rollups") => {
+            rollups::run_cli().await;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let state = initialize().await;
 
-    discord_bot::initialize();
+    discord_bot::initialize(state.clone());
+    live_sessions::initialize(state.clone());
+    retention::initialize(state.clone());
 
     println!("Running http server...");
     let _rocket = rocket::build()
+        .manage(state)
         .mount(
             "/",
-            routes![routes::index::index, routes::session_upload::upload_session],
+            routes![
+                routes::index::index,
+                routes::session_upload::upload_session,
+                routes::batch_upload::upload_session_batch,
+                routes::live_session::start_session,
+                routes::live_session::session_heartbeat,
+                routes::live_session::end_session,
+                routes::crash_upload::upload_crash,
+                routes::crash_upload::get_crash_groups,
+                routes::crash_upload::create_crash_gitlab_issue,
+                routes::events::upload_events,
+                routes::events::get_event_counts,
+                routes::events::get_funnel,
+                routes::heatmap::upload_positions,
+                routes::heatmap::get_heatmap,
+                routes::stats::get_player_stats,
+                routes::stats::get_play_time_stats,
+                routes::stats::get_performance_stats,
+                routes::stats::get_daily_stats,
+                routes::feedback::get_feedback,
+                routes::feedback::update_feedback,
+                routes::feedback::create_feedback_gitlab_issue,
+                routes::players::get_player_profile,
+                routes::players::erase_player,
+                routes::players::set_opt_out,
+                routes::search::search,
+                routes::search::get_session,
+                routes::reports::get_active_players,
+                routes::reports::get_retention
+            ],
         )
         .ignite()
         .await?
@@ -0,0 +1,369 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+
+use crate::config::StorageBackend;
+use crate::database::{self, Database};
+use crate::rollups::ROLLUP_KEY;
+use crate::session::SESSION_COLLECTOR;
+
+// How often a running migration refreshes HeartbeatAt on its claim
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// A claim that hasn't had a heartbeat for this long belongs to a server that died
+const CLAIM_EXPIRY: chrono::TimeDelta = chrono::TimeDelta::seconds(120);
+
+// A one off change to stored documents. Returns how many documents it changed, or would change
+// when it's a dry run.
+pub struct Migration {
+    pub id: i32,
+    pub name: &'static str,
+    run: for<'a> fn(&'a Database, bool) -> BoxFuture<'a, mongodb::error::Result<u64>>,
+}
+
+// In the order they're applied. Ids must only ever go up, and a migration that has shipped must
+// never change, add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        id: 1,
+        name: "Convert session dates from strings",
+        run: |db, dry_run| Box::pin(convert_session_dates(db, dry_run)),
+    },
+    Migration {
+        id: 2,
+        name: "Build daily rollups",
+        run: |db, dry_run| Box::pin(build_rollups(db, dry_run)),
+    },
+    Migration {
+        id: 3,
+        name: "Record when players were first seen",
+        run: |db, dry_run| Box::pin(build_first_seen(db, dry_run)),
+    },
+    Migration {
+        id: 4,
+        name: "Move rollup players to their own collection",
+        run: |db, dry_run| Box::pin(move_rollup_players(db, dry_run)),
+    },
+    Migration {
+        id: 5,
+        name: "Record the days players played on",
+        run: |db, dry_run| Box::pin(build_player_days(db, dry_run)),
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    pub id: i32,
+    pub name: &'static str,
+    pub documents: u64,
+}
+
+impl std::fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "#{} {}: {} documents",
+            self.id, self.name, self.documents
+        )
+    }
+}
+
+// Sessions uploaded before dates were converted on insert have StartTime and EndTime as unreal
+// date strings
+async fn convert_session_dates(db: &Database, dry_run: bool) -> mongodb::error::Result<u64> {
+    let collection = db.database.collection::<Document>(&db.collections.sessions);
+    let filter = doc! {"$or": [
+        {format!("{}.StartTime", SESSION_COLLECTOR): {"$type": "string"}},
+        {format!("{}.EndTime", SESSION_COLLECTOR): {"$type": "string"}},
+    ]};
+
+    if dry_run {
+        return collection.count_documents(filter, None).await;
+    }
+
+    let mut converted = 0;
+    let mut cursor = collection.find(filter, None).await?;
+    while let Some(mut document) = cursor.try_next().await? {
+        let id = match document.get_object_id("_id") {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+
+        let mut set = doc! {};
+        for field in ["StartTime", "EndTime"] {
+            if let Some(date) = database::convert_date_time(&mut document, field) {
+                set.insert(format!("{}.{}", SESSION_COLLECTOR, field), date);
+            }
+        }
+        if set.is_empty() {
+            eprintln!("Migration: session {} has dates that can't be parsed", id);
+            continue;
+        }
+
+        collection
+            .update_one(doc! {"_id": id}, doc! {"$set": set}, None)
+            .await?;
+        converted += 1;
+    }
+
+    Ok(converted)
+}
+
+// Sessions stored before rollups existed aren't counted in them
+async fn build_rollups(db: &Database, dry_run: bool) -> mongodb::error::Result<u64> {
+    let sessions = db.database.collection::<Document>(&db.collections.sessions);
+    if dry_run {
+        return sessions.count_documents(None, None).await;
+    }
+
+    crate::rollups::rebuild(db).await?;
+    db.database
+        .collection::<Document>(&db.collections.daily_rollups)
+        .count_documents(None, None)
+        .await
+}
+
+// Sessions stored before first seen days were recorded aren't in them
+async fn build_first_seen(db: &Database, dry_run: bool) -> mongodb::error::Result<u64> {
+    let sessions = db.database.collection::<Document>(&db.collections.sessions);
+    if dry_run {
+        return sessions.count_documents(None, None).await;
+    }
+
+    crate::reporting::rebuild_first_seen(db).await?;
+    db.database
+        .collection::<Document>(&db.collections.first_seen)
+        .count_documents(None, None)
+        .await
+}
+
+// Sessions stored before played days were recorded aren't in them
+async fn build_player_days(db: &Database, dry_run: bool) -> mongodb::error::Result<u64> {
+    let sessions = db.database.collection::<Document>(&db.collections.sessions);
+    if dry_run {
+        return sessions.count_documents(None, None).await;
+    }
+
+    crate::reporting::rebuild_player_days(db).await?;
+    db.database
+        .collection::<Document>(&db.collections.player_days)
+        .count_documents(None, None)
+        .await
+}
+
+// Rollups used to keep their players in a Players array, which had no limit on how big it got
+async fn move_rollup_players(db: &Database, dry_run: bool) -> mongodb::error::Result<u64> {
+    let rollups = db
+        .database
+        .collection::<Document>(&db.collections.daily_rollups);
+    let filter = doc! {"Players": {"$exists": true}};
+    if dry_run {
+        return rollups.count_documents(filter, None).await;
+    }
+
+    let mut project: Document = ROLLUP_KEY
+        .iter()
+        .map(|field| (field.to_string(), 1.into()))
+        .collect();
+    project.insert("_id", 0);
+    project.insert("Player", "$Players");
+    let mut on = ROLLUP_KEY.to_vec();
+    on.push("Player");
+    let pipeline = [
+        doc! {"$match": filter.clone()},
+        doc! {"$unwind": "$Players"},
+        doc! {"$project": project},
+        doc! {"$merge": {
+            "into": &db.collections.rollup_players,
+            "on": on,
+            "whenMatched": "keepExisting",
+        }},
+    ];
+    rollups.aggregate(pipeline, None).await?;
+
+    Ok(rollups
+        .update_many(filter, doc! {"$unset": {"Players": ""}}, None)
+        .await?
+        .modified_count)
+}
+
+// Waits for another server to finish applying a migration. Fails when it stopped part way, since
+// the data has to be checked by hand before the migration can be tried again.
+async fn wait_for_claim(
+    collection: &Collection<Document>,
+    migration: &Migration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!(
+        "Migration #{} is being applied by another server, waiting for it",
+        migration.id
+    );
+
+    loop {
+        let claim = match collection
+            .find_one(doc! {"_id": migration.id}, None)
+            .await?
+        {
+            Some(claim) => claim,
+            None => {
+                return Err(format!("migration #{} failed on another server", migration.id).into())
+            }
+        };
+        if claim.get_str("Status") == Ok("applied") {
+            return Ok(());
+        }
+
+        // Claims from before there were heartbeats only have StartedAt
+        let heartbeat = claim
+            .get_datetime("HeartbeatAt")
+            .or_else(|_| claim.get_datetime("StartedAt"))
+            .map(|date| date.to_chrono())
+            .unwrap_or_default();
+        if chrono::Utc::now() - heartbeat > CLAIM_EXPIRY {
+            return Err(format!(
+                "migration #{} was claimed by a server that stopped part way through it (last \
+                 heartbeat {}). Check the data, then delete its record from the migrations \
+                 collection to run it again.",
+                migration.id, heartbeat
+            )
+            .into());
+        }
+
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+    }
+}
+
+// Applies every migration that hasn't been applied yet, in order. With dry_run nothing is changed
+// and the report says how many documents each pending migration would touch.
+pub async fn run_migrations(
+    db: &Database,
+    dry_run: bool,
+) -> Result<Vec<MigrationReport>, Box<dyn std::error::Error + Send + Sync>> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.migrations);
+
+    let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+    let applied: Vec<i32> = collection
+        .find(doc! {"Status": "applied"}, options)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .iter()
+        .filter_map(|document| document.get_i32("_id").ok())
+        .collect();
+
+    let mut reports = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.id))
+    {
+        if dry_run {
+            reports.push(MigrationReport {
+                id: migration.id,
+                name: migration.name,
+                documents: (migration.run)(db, true).await?,
+            });
+            continue;
+        }
+
+        // Claim the migration first so that two servers starting at once don't both run it
+        let now = mongodb::bson::DateTime::now();
+        let claim = doc! {
+            "_id": migration.id,
+            "Name": migration.name,
+            "Status": "running",
+            "StartedAt": now,
+            "HeartbeatAt": now,
+        };
+        match collection.insert_one(claim, None).await {
+            Ok(_) => {}
+            Err(e) if database::is_duplicate_key_error(&e) => {
+                wait_for_claim(&collection, migration).await?;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Keeps the claim alive for as long as the migration runs
+        let heartbeat = {
+            let collection = collection.clone();
+            let id = migration.id;
+            tokio::task::spawn(async move {
+                loop {
+                    tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+                    let heartbeat = doc! {"$set": {"HeartbeatAt": mongodb::bson::DateTime::now()}};
+                    if let Err(e) = collection
+                        .update_one(doc! {"_id": id, "Status": "running"}, heartbeat, None)
+                        .await
+                    {
+                        eprintln!("Migration #{}: failed to refresh its claim! {}", id, e);
+                    }
+                }
+            })
+        };
+
+        println!("Applying migration #{} {}", migration.id, migration.name);
+        let result = (migration.run)(db, false).await;
+        heartbeat.abort();
+
+        match result {
+            Ok(documents) => {
+                collection
+                    .update_one(
+                        doc! {"_id": migration.id},
+                        doc! {"$set": {
+                            "Status": "applied",
+                            "AppliedAt": mongodb::bson::DateTime::now(),
+                            "Documents": documents as i64,
+                        }},
+                        None,
+                    )
+                    .await?;
+
+                reports.push(MigrationReport {
+                    id: migration.id,
+                    name: migration.name,
+                    documents,
+                });
+            }
+            Err(e) => {
+                // Let the next start try again, later migrations may depend on this one
+                collection
+                    .delete_one(doc! {"_id": migration.id}, None)
+                    .await?;
+                return Err(e.into());
+            }
+        }
+    }
+
+    Ok(reports)
+}
+
+// Entry point for `unreal-analytics-server migrate [--dry-run]`
+pub async fn run_cli(args: &[String]) {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+
+    let config = crate::config::read_config();
+    if config.storage.backend != StorageBackend::Mongo {
+        println!("Nothing to migrate, the storage backend isn't mongo");
+        return;
+    }
+    let db = database::connect_to_db(&config);
+
+    match run_migrations(&db, dry_run).await {
+        Ok(reports) if reports.is_empty() => println!("No pending migrations"),
+        Ok(reports) => {
+            let verb = if dry_run { "Would apply" } else { "Applied" };
+            for report in reports {
+                println!("{} {}", verb, report);
+            }
+        }
+        Err(e) => {
+            eprintln!("Migration failed! Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
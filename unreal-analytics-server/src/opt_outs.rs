@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+
+use crate::database::{Database, StatsFilter};
+use crate::session::AnalyticsSession;
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+
+// Builds that don't ask for consent count as consenting
+pub fn has_consent(telemetry_consent: Option<bool>) -> bool {
+    telemetry_consent != Some(false)
+}
+
+// Whether anything about a player can be stored, the same rule sessions are counted by.
+// `opted_out` is what opted_out returned for the NetIDs being checked.
+pub fn may_store(
+    telemetry_consent: Option<bool>,
+    net_id: Option<&str>,
+    opted_out: &HashSet<String>,
+) -> bool {
+    has_consent(telemetry_consent) && net_id.is_none_or(|net_id| !opted_out.contains(net_id))
+}
+
+pub async fn is_opted_out(db: &Database, net_id: &str) -> mongodb::error::Result<bool> {
+    let count = db
+        .database
+        .collection::<Document>(&db.collections.opt_outs)
+        .count_documents(doc! {"NetID": net_id}, None)
+        .await?;
+
+    Ok(count > 0)
+}
+
+// Which of the NetIDs are opted out
+pub async fn opted_out(db: &Database, net_ids: &[&str]) -> mongodb::error::Result<HashSet<String>> {
+    if net_ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let options = FindOptions::builder().projection(doc! {"NetID": 1}).build();
+    let opt_outs: Vec<Document> = db
+        .database
+        .collection::<Document>(&db.collections.opt_outs)
+        .find(doc! {"NetID": {"$in": net_ids}}, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(opt_outs
+        .iter()
+        .filter_map(|opt_out| opt_out.get_str("NetID").ok())
+        .map(String::from)
+        .collect())
+}
+
+// Sessions without consent, or from an opted out player, are only counted
+async fn is_anonymous(db: &Database, session: &AnalyticsSession) -> mongodb::error::Result<bool> {
+    if !session.has_consent() {
+        return Ok(true);
+    }
+
+    let net_ids: Vec<&str> = session
+        .collector
+        .player_controller_data
+        .iter()
+        .map(|player_controller| player_controller.net_id.as_str())
+        .collect();
+    Ok(!opted_out(db, &net_ids).await?.is_empty())
+}
+
+// What's kept of a session that was only counted
+#[derive(Debug, Clone)]
+pub struct AnonymousSession {
+    pub start_time: chrono::NaiveDateTime,
+    pub build_version: Option<String>,
+    pub platform: Option<String>,
+    pub is_play_in_editor_session: bool,
+}
+
+impl AnonymousSession {
+    pub fn new(session: &AnalyticsSession) -> Self {
+        let collector = &session.collector;
+        AnonymousSession {
+            start_time: collector.start_time,
+            build_version: collector.build_version.clone(),
+            platform: collector.platform.clone(),
+            is_play_in_editor_session: collector.is_play_in_editor_session,
+        }
+    }
+}
+
+// Anonymous counts only know the day, build, platform and PIE flag of a session, so None when the
+// filter needs anything else
+pub fn anonymous_filter(filter: &StatsFilter) -> Option<Document> {
+    if filter.country.is_some() || filter.steam.is_some() {
+        return None;
+    }
+
+    let mut match_filter = doc! {};
+    let day = crate::rollups::day_filter(filter);
+    if !day.is_empty() {
+        match_filter.insert("Day", day);
+    }
+    if let Some(build_version) = &filter.build_version {
+        match_filter.insert("BuildVersion", build_version);
+    }
+    if let Some(platform) = &filter.platform {
+        match_filter.insert("Platform", platform);
+    }
+    if let Some(pie) = filter.pie {
+        match_filter.insert("IsPlayInEditorSession", pie);
+    }
+
+    Some(match_filter)
+}
+
+async fn count_anonymous(db: &Database, session: &AnonymousSession) -> mongodb::error::Result<()> {
+    let options = UpdateOptions::builder().upsert(true).build();
+    db.database
+        .collection::<Document>(&db.collections.anonymous_sessions)
+        .update_one(
+            doc! {
+                "Day": session.start_time.format(DAY_FORMAT).to_string(),
+                "BuildVersion": &session.build_version,
+                "Platform": &session.platform,
+                "IsPlayInEditorSession": session.is_play_in_editor_session,
+            },
+            doc! {"$inc": {"Sessions": 1_i64}},
+            options,
+        )
+        .await?;
+
+    Ok(())
+}
+
+// Remembers the idempotency key of a session that was only counted. Returns false if it was
+// counted before.
+async fn add_anonymous_key(db: &Database, key: &str) -> mongodb::error::Result<bool> {
+    let result = db
+        .database
+        .collection::<Document>(&db.collections.anonymous_session_keys)
+        .insert_one(
+            doc! {"IdempotencyKey": key, "CountedAt": mongodb::bson::DateTime::now()},
+            None,
+        )
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if crate::database::is_duplicate_key_error(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Counts the session instead of it being stored when the player turned telemetry off. Nothing
+// about the player is kept. Returns whether it was counted, or already had been under the same
+// idempotency key.
+pub async fn count_if_anonymous(
+    db: &Database,
+    session: &AnalyticsSession,
+) -> mongodb::error::Result<bool> {
+    if !is_anonymous(db, session).await? {
+        return Ok(false);
+    }
+
+    // A retry of an upload that was already counted
+    if let Some(key) = &session.idempotency_key {
+        if !add_anonymous_key(db, key).await? {
+            return Ok(true);
+        }
+    }
+
+    count_anonymous(db, &AnonymousSession::new(session)).await?;
+    Ok(true)
+}
+
+// How many sessions were only counted, or None when the filter needs something that isn't kept
+// for them
+pub async fn get_anonymous_sessions(
+    db: &Database,
+    filter: &StatsFilter,
+) -> mongodb::error::Result<Option<u64>> {
+    let match_filter = match anonymous_filter(filter) {
+        Some(match_filter) => match_filter,
+        None => return Ok(None),
+    };
+
+    let pipeline = [
+        doc! {"$match": match_filter},
+        doc! {"$group": {"_id": null, "sessions": {"$sum": "$Sessions"}}},
+    ];
+    let result = db
+        .database
+        .collection::<Document>(&db.collections.anonymous_sessions)
+        .aggregate(pipeline, None)
+        .await?
+        .try_next()
+        .await?
+        .unwrap_or_default();
+
+    Ok(Some(match result.get("sessions") {
+        Some(Bson::Int32(sessions)) => *sessions as u64,
+        Some(Bson::Int64(sessions)) => *sessions as u64,
+        _ => 0,
+    }))
+}
+
+// Opts a player out, nothing new about them is stored from then on. What's already stored is
+// kept, erasing it is a separate request (see privacy::erase). Returns false if they already
+// were opted out.
+pub async fn register(db: &Database, net_id: &str) -> mongodb::error::Result<bool> {
+    let options = UpdateOptions::builder().upsert(true).build();
+    let result = db
+        .database
+        .collection::<Document>(&db.collections.opt_outs)
+        .update_one(
+            doc! {"NetID": net_id},
+            doc! {"$setOnInsert": {"OptedOutAt": mongodb::bson::DateTime::now()}},
+            options,
+        )
+        .await?;
+
+    Ok(result.upserted_id.is_some())
+}
+
+// Opts a player back in, only sessions from then on are stored. Returns false if they weren't
+// opted out.
+pub async fn withdraw(db: &Database, net_id: &str) -> mongodb::error::Result<bool> {
+    let result = db
+        .database
+        .collection::<Document>(&db.collections.opt_outs)
+        .delete_one(doc! {"NetID": net_id}, None)
+        .await?;
+
+    Ok(result.deleted_count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_consenting_players_that_didnt_opt_out_are_stored() {
+        let opted_out = HashSet::from(["opted-out".to_string()]);
+
+        assert!(may_store(None, None, &opted_out));
+        assert!(may_store(Some(true), Some("player"), &opted_out));
+        assert!(!may_store(Some(false), Some("player"), &opted_out));
+        assert!(!may_store(Some(false), None, &opted_out));
+        assert!(!may_store(None, Some("opted-out"), &opted_out));
+    }
+}
@@ -0,0 +1,525 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::{Config, IpMode, Secrets};
+use crate::database::Database;
+use crate::retention::ArchiveEdit;
+use crate::session::{FEEDBACK_COLLECTOR, SESSION_COLLECTOR};
+
+// Keyed so that a hashed IPv4 address can't be found by hashing all of them
+pub fn keyed_hash(salt: &str, value: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(value.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Keeps the /24 of IPv4 addresses and the /48 of IPv6 ones
+pub fn truncate_ip(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Ipv4Addr::new(a, b, c, 0).to_string()
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0).to_string()
+        }
+    }
+}
+
+pub fn anonymize_ip(ip: &IpAddr, mode: IpMode, salt: &str) -> String {
+    match mode {
+        IpMode::Raw => ip.to_string(),
+        IpMode::Hash => keyed_hash(salt, &ip.to_string()),
+        IpMode::Truncate => truncate_ip(ip),
+    }
+}
+
+// How IPs are stored in sessions, taken from the config and secrets
+#[derive(Debug, Clone)]
+pub struct IpPolicy {
+    pub mode: IpMode,
+    pub salt: String,
+}
+
+impl IpPolicy {
+    pub fn new(config: &Config, secrets: &Secrets) -> Self {
+        IpPolicy {
+            mode: config.privacy.ip_mode,
+            salt: secrets.keys.ip_hash_salt.clone(),
+        }
+    }
+
+    // The IP as it should be stored in a session
+    pub fn stored_ip(&self, ip: &IpAddr) -> String {
+        anonymize_ip(ip, self.mode, &self.salt)
+    }
+}
+
+// Warns about settings that make hashed IPs easy to reverse
+pub fn check_config(config: &Config, secrets: &Secrets) {
+    if config.privacy.ip_mode == IpMode::Hash && secrets.keys.ip_hash_salt.is_empty() {
+        eprintln!("Warning: ip_mode is \"hash\" but there's no ip_hash_salt in the secrets");
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErasureMode {
+    // Removes the player's records
+    Delete,
+    // Keeps the records but replaces the player's NetID with a random id, drops their IP and
+    // Steam details and blanks their feedback comments
+    Anonymize,
+}
+
+impl ErasureMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErasureMode::Delete => "delete",
+            ErasureMode::Anonymize => "anonymize",
+        }
+    }
+
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "delete" => Some(ErasureMode::Delete),
+            "anonymize" => Some(ErasureMode::Anonymize),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ErasureTarget {
+    NetId(String),
+    // Only the sessions uploaded from this IP and the records that belong to them
+    Ip(IpAddr),
+}
+
+impl ErasureTarget {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ErasureTarget::NetId(_) => "net_id",
+            ErasureTarget::Ip(_) => "ip",
+        }
+    }
+
+    pub fn value(&self) -> String {
+        match self {
+            ErasureTarget::NetId(net_id) => net_id.clone(),
+            ErasureTarget::Ip(ip) => ip.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErasureRequest {
+    pub target: ErasureTarget,
+    pub mode: ErasureMode,
+    // Who asked for it, for the audit record
+    pub requested_by: String,
+}
+
+// How many records an erasure changed in each collection
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ErasureReport {
+    pub sessions: u64,
+    pub feedback: u64,
+    pub crashes: u64,
+    pub events: u64,
+    pub positions: u64,
+    pub live_sessions: u64,
+    pub archived_sessions: u64,
+}
+
+impl std::fmt::Display for ErasureReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} sessions, {} feedback comments, {} crashes, {} events, {} positions, {} live sessions and {} archived sessions",
+            self.sessions,
+            self.feedback,
+            self.crashes,
+            self.events,
+            self.positions,
+            self.live_sessions,
+            self.archived_sessions
+        )
+    }
+}
+
+// Stands in for the feedback comments of an anonymized player
+pub const ERASED_COMMENT: &str = "[erased]";
+
+fn field(name: &str) -> String {
+    format!("{}.{}", SESSION_COLLECTOR, name)
+}
+
+pub fn session_net_ids(session: &Document) -> Vec<String> {
+    session
+        .get_document(SESSION_COLLECTOR)
+        .and_then(|collector| collector.get_array("PlayerControllerData"))
+        .into_iter()
+        .flatten()
+        .filter_map(|player_controller| player_controller.as_document()?.get_str("NetID").ok())
+        .map(String::from)
+        .collect()
+}
+
+// Swaps identifiers in the rollup players. Rollup counters aren't personal data and are left
+// alone.
+async fn replace_players(
+    db: &Database,
+    players: &[String],
+    replacement: Option<&str>,
+) -> mongodb::error::Result<()> {
+    if players.is_empty() {
+        return Ok(());
+    }
+
+    let names = &db.collections;
+    let collection = db.database.collection::<Document>(&names.rollup_players);
+    let filter = doc! {"Player": {"$in": players}};
+    if let Some(replacement) = replacement {
+        // Merged on the unique key, since several of the identifiers can share a rollup
+        let mut project: Document = crate::rollups::ROLLUP_KEY
+            .iter()
+            .map(|field| (field.to_string(), 1.into()))
+            .collect();
+        project.insert("_id", 0);
+        project.insert("Player", doc! {"$literal": replacement});
+        let mut on = crate::rollups::ROLLUP_KEY.to_vec();
+        on.push("Player");
+        let pipeline = [
+            doc! {"$match": filter.clone()},
+            doc! {"$project": project},
+            doc! {"$merge": {
+                "into": &names.rollup_players,
+                "on": on,
+                "whenMatched": "keepExisting",
+            }},
+        ];
+        collection.aggregate(pipeline, None).await?;
+    }
+    collection.delete_many(filter, None).await?;
+
+    Ok(())
+}
+
+// Deletes or anonymizes everything stored about a player
+async fn erase_records(
+    db: &Database,
+    config: &Config,
+    salt: &str,
+    request: &ErasureRequest,
+) -> Result<ErasureReport, Box<dyn std::error::Error + Send + Sync>> {
+    let mut report = ErasureReport::default();
+    let sessions = db.database.collection::<Document>(&db.collections.sessions);
+
+    // An IP can be stored in any of the forms ip_mode allows. Truncated ones are shared with other
+    // players so they're never matched.
+    let (session_filter, net_ids, mut ips) = match &request.target {
+        ErasureTarget::NetId(net_id) => (
+            doc! {field("PlayerControllerData.NetID"): net_id},
+            vec![net_id.clone()],
+            Vec::new(),
+        ),
+        ErasureTarget::Ip(ip) => {
+            let ips = vec![ip.to_string(), keyed_hash(salt, &ip.to_string())];
+            (doc! {field("ip"): {"$in": &ips}}, Vec::new(), ips)
+        }
+    };
+
+    let options = FindOptions::builder()
+        .projection(doc! {
+            "_id": 1,
+            field("SessionID"): 1,
+            field("ip"): 1,
+            field("PlayerControllerData.NetID"): 1,
+        })
+        .build();
+    let matched: Vec<Document> = sessions
+        .find(session_filter, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    let session_object_ids: Vec<ObjectId> = matched
+        .iter()
+        .filter_map(|session| session.get_object_id("_id").ok())
+        .collect();
+    let mut session_ids: Vec<String> = Vec::new();
+    // Every NetID in the matched sessions, for an IP it's whoever played from it
+    let mut session_players = net_ids.clone();
+    for session in &matched {
+        let collector = session.get_document(SESSION_COLLECTOR).ok();
+        let collector_str = |key: &str| collector.and_then(|collector| collector.get_str(key).ok());
+        if let Some(session_id) = collector_str("SessionID") {
+            session_ids.push(session_id.to_string());
+        }
+        if let Some(ip) = collector_str("ip") {
+            if !ips.iter().any(|known| known == ip) {
+                ips.push(ip.to_string());
+            }
+        }
+        for net_id in session_net_ids(session) {
+            if !session_players.contains(&net_id) {
+                session_players.push(net_id);
+            }
+        }
+    }
+    // Only the target's own NetIDs are replaced in sessions shared with other players
+    let erased_net_ids = match &request.target {
+        ErasureTarget::NetId(_) => net_ids.clone(),
+        ErasureTarget::Ip(_) => session_players.clone(),
+    };
+
+    let related = doc! {"$or": [
+        {"NetID": {"$in": &net_ids}},
+        {"SessionID": {"$in": &session_ids}},
+    ]};
+    let feedback_filter = doc! {"$or": [
+        {"NetID": {"$in": &net_ids}},
+        {"SessionObjectID": {"$in": &session_object_ids}},
+    ]};
+
+    let collection = |name: &str| db.database.collection::<Document>(name);
+    let names = &db.collections;
+
+    let crashes: Vec<Document> = collection(&names.crashes)
+        .find(related.clone(), None)
+        .await?
+        .try_collect()
+        .await?;
+    // Logs and minidumps can't be scrubbed, so they go in both modes
+    for crash in &crashes {
+        crate::crash::delete_attachments(db, crash).await?;
+    }
+
+    // Random, so the records of the player stay together without pointing back at them
+    let pseudonym = format!("erased-{}", ObjectId::new().to_hex());
+    let replacement = match request.mode {
+        ErasureMode::Delete => None,
+        ErasureMode::Anonymize => Some(pseudonym.as_str()),
+    };
+
+    match request.mode {
+        ErasureMode::Delete => {
+            report.sessions = sessions
+                .delete_many(doc! {"_id": {"$in": &session_object_ids}}, None)
+                .await?
+                .deleted_count;
+            report.feedback = collection(&names.feedback)
+                .delete_many(feedback_filter, None)
+                .await?
+                .deleted_count;
+            report.crashes = collection(&names.crashes)
+                .delete_many(related.clone(), None)
+                .await?
+                .deleted_count;
+            report.events = collection(&names.events)
+                .delete_many(related.clone(), None)
+                .await?
+                .deleted_count;
+            report.positions = collection(&names.positions)
+                .delete_many(related.clone(), None)
+                .await?
+                .deleted_count;
+            report.live_sessions = collection(&names.live_sessions)
+                .delete_many(related, None)
+                .await?
+                .deleted_count;
+        }
+        ErasureMode::Anonymize => {
+            // The Steam data has the player's Steam ID and name. It's emptied rather than removed
+            // so the session still counts as a Steam one.
+            let options = UpdateOptions::builder()
+                .array_filters(vec![
+                    doc! {"player.NetID": {"$in": &erased_net_ids}},
+                    doc! {
+                        "steam.NetID": {"$in": &erased_net_ids},
+                        "steam.SteamAnalyticsData": {"$exists": true},
+                    },
+                ])
+                .build();
+            report.sessions = sessions
+                .update_many(
+                    doc! {"_id": {"$in": &session_object_ids}},
+                    doc! {
+                        "$set": {
+                            field("PlayerControllerData.$[player].NetID"): &pseudonym,
+                            field("PlayerControllerData.$[steam].SteamAnalyticsData"): {},
+                        },
+                        "$unset": {field("ip"): ""},
+                    },
+                    options,
+                )
+                .await?
+                .modified_count;
+            // The comments are free text and often identify whoever wrote them, only the count is kept
+            let comments = format!("{}.FeedbackComments", FEEDBACK_COLLECTOR);
+            sessions
+                .update_many(
+                    doc! {
+                        "_id": {"$in": &session_object_ids},
+                        format!("{}.0", comments): {"$exists": true},
+                    },
+                    doc! {"$set": {format!("{}.$[]", comments): ERASED_COMMENT}},
+                    None,
+                )
+                .await?;
+
+            let set_net_id = doc! {"$set": {"NetID": &pseudonym}};
+            report.feedback = collection(&names.feedback)
+                .update_many(
+                    feedback_filter,
+                    doc! {"$set": {"NetID": &pseudonym, "Comment": ERASED_COMMENT}},
+                    None,
+                )
+                .await?
+                .modified_count;
+            report.crashes = collection(&names.crashes)
+                .update_many(
+                    related.clone(),
+                    doc! {"$set": {"NetID": &pseudonym, "Attachments": []}},
+                    None,
+                )
+                .await?
+                .modified_count;
+            report.events = collection(&names.events)
+                .update_many(related.clone(), set_net_id.clone(), None)
+                .await?
+                .modified_count;
+            report.positions = collection(&names.positions)
+                .update_many(related.clone(), set_net_id.clone(), None)
+                .await?
+                .modified_count;
+            report.live_sessions = collection(&names.live_sessions)
+                .update_many(related, set_net_id, None)
+                .await?
+                .modified_count;
+        }
+    }
+
+    replace_players(db, &ips, replacement).await?;
+    // Only a NetID points at the player, the days they played on aren't tied to an IP
+    crate::reporting::replace_player_activity(db, &net_ids, replacement).await?;
+
+    // Sessions pruned by the retention job only exist in the archives
+    let mode = request.mode;
+    let target = request.target.clone();
+    let archive_ips = ips.clone();
+    let edit = move |session: &Document| {
+        let collector = session.get_document(SESSION_COLLECTOR).ok();
+        let matches = match &target {
+            ErasureTarget::NetId(net_id) => session_net_ids(session).contains(net_id),
+            ErasureTarget::Ip(_) => collector
+                .and_then(|collector| collector.get_str("ip").ok())
+                .is_some_and(|ip| archive_ips.iter().any(|known| known == ip)),
+        };
+        if !matches {
+            return ArchiveEdit::Keep;
+        }
+        if mode == ErasureMode::Delete {
+            return ArchiveEdit::Remove;
+        }
+
+        let mut session = session.clone();
+        if let Ok(collector) = session.get_document_mut(SESSION_COLLECTOR) {
+            collector.remove("ip");
+            let player_controllers = collector.get_array_mut("PlayerControllerData");
+            for player_controller in player_controllers.into_iter().flatten() {
+                if let Bson::Document(player_controller) = player_controller {
+                    let erased = match (&target, player_controller.get_str("NetID")) {
+                        (ErasureTarget::NetId(net_id), Ok(id)) => id == net_id,
+                        (ErasureTarget::Ip(_), Ok(_)) => true,
+                        (_, Err(_)) => false,
+                    };
+                    if erased {
+                        player_controller.insert("NetID", &pseudonym);
+                        if player_controller.contains_key("SteamAnalyticsData") {
+                            player_controller.insert("SteamAnalyticsData", Document::new());
+                        }
+                    }
+                }
+            }
+        }
+        if let Ok(feedback) = session.get_document_mut(FEEDBACK_COLLECTOR) {
+            if let Ok(comments) = feedback.get_array_mut("FeedbackComments") {
+                comments.fill(Bson::String(ERASED_COMMENT.to_string()));
+            }
+        }
+        ArchiveEdit::Replace(session)
+    };
+    report.archived_sessions =
+        crate::retention::rewrite_archives(&config.retention.archive_dir, Box::new(edit)).await?;
+
+    Ok(report)
+}
+
+// Deletes or anonymizes every session, feedback comment, crash, event and position of a player,
+// and keeps an audit record of the request. The record only has a keyed hash of the NetID or IP,
+// enough to check whether a given player's request was handled.
+pub async fn erase(
+    db: &Database,
+    config: &Config,
+    secrets: &Secrets,
+    request: &ErasureRequest,
+) -> Result<ErasureReport, Box<dyn std::error::Error + Send + Sync>> {
+    let salt = &secrets.keys.ip_hash_salt;
+    let audit = db
+        .database
+        .collection::<Document>(&db.collections.erasure_requests);
+
+    let id = ObjectId::new();
+    audit
+        .insert_one(
+            doc! {
+                "_id": id,
+                "Target": request.target.kind(),
+                "TargetHash": keyed_hash(salt, &request.target.value()),
+                "Mode": request.mode.as_str(),
+                "RequestedBy": &request.requested_by,
+                "RequestedAt": mongodb::bson::DateTime::now(),
+                "Status": "running",
+            },
+            None,
+        )
+        .await?;
+
+    match erase_records(db, config, salt, request).await {
+        Ok(report) => {
+            audit
+                .update_one(
+                    doc! {"_id": id},
+                    doc! {"$set": {
+                        "Status": "completed",
+                        "CompletedAt": mongodb::bson::DateTime::now(),
+                        "Report": mongodb::bson::to_document(&report)?,
+                    }},
+                    None,
+                )
+                .await?;
+            Ok(report)
+        }
+        Err(e) => {
+            // Erasing again is safe, the record just shows it has to be done
+            audit
+                .update_one(
+                    doc! {"_id": id},
+                    doc! {"$set": {"Status": "failed", "Error": e.to_string()}},
+                    None,
+                )
+                .await?;
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,735 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{Duration, NaiveDate, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{is_duplicate_key_error, Database, StatsFilter};
+use crate::session::SESSION_COLLECTOR;
+
+// Longest range a report can cover, in days
+const MAX_REPORT_DAYS: i64 = 366;
+// Range used when the filter doesn't have one
+const DEFAULT_REPORT_DAYS: i64 = 30;
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+const START_TIME_FIELD: &str = "BP_SessionAnalyicsCollector_C.StartTime";
+
+// Unique players (by NetID) active on a day, in the 7 days up to it and in the 30 days up to it
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ActivePlayers {
+    pub date: NaiveDate,
+    pub dau: u64,
+    pub wau: u64,
+    pub mau: u64,
+}
+
+// Players whose first session was on `date`, and the fraction of them that played again exactly
+// 1, 7 and 30 days later. The rates are missing until enough time has passed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RetentionCohort {
+    pub date: NaiveDate,
+    pub players: u64,
+    pub d1: Option<f64>,
+    pub d7: Option<f64>,
+    pub d30: Option<f64>,
+}
+
+// First day and last day (exclusive) a report covers
+fn report_days(filter: &StatsFilter) -> (NaiveDate, NaiveDate) {
+    let today = Utc::now().date_naive();
+    let end = filter
+        .to
+        .map(|to| to.date_naive())
+        .unwrap_or(today + Duration::days(1));
+    let start = filter
+        .from
+        .map(|from| from.date_naive())
+        .unwrap_or(end - Duration::days(DEFAULT_REPORT_DAYS))
+        .max(end - Duration::days(MAX_REPORT_DAYS));
+
+    (start, end)
+}
+
+fn days(start: NaiveDate, end: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+    start.iter_days().take_while(move |day| *day < end)
+}
+
+// Everything in the filter except the date range, which is handled per day
+fn cache_key(filter: &StatsFilter) -> String {
+    format!(
+        "build={:?};platform={:?};pie={:?};country={:?};steam={:?}",
+        filter.build_version,
+        filter.platform,
+        filter.pie,
+        filter
+            .country
+            .as_ref()
+            .map(|country| country.to_uppercase()),
+        filter.steam
+    )
+}
+
+// Reads the cached report rows for the given days that are still fresh
+async fn read_cache<T: for<'de> Deserialize<'de>>(
+    db: &Database,
+    report: &str,
+    key: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+    ttl_secs: u64,
+) -> mongodb::error::Result<BTreeMap<NaiveDate, T>> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.report_cache);
+    let fresh_after = Utc::now() - Duration::seconds(ttl_secs as i64);
+
+    let filter = doc! {
+        "Report": report,
+        "FilterKey": key,
+        "Day": {
+            "$gte": start.format(DAY_FORMAT).to_string(),
+            "$lt": end.format(DAY_FORMAT).to_string(),
+        },
+        "ComputedAt": {"$gte": fresh_after},
+    };
+
+    let mut rows = BTreeMap::new();
+    let mut cursor = collection.find(filter, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let day = document
+            .get_str("Day")
+            .ok()
+            .and_then(|day| NaiveDate::parse_from_str(day, DAY_FORMAT).ok());
+        let row = document
+            .get_document("Row")
+            .ok()
+            .and_then(|row| mongodb::bson::from_document::<T>(row.clone()).ok());
+        if let (Some(day), Some(row)) = (day, row) {
+            rows.insert(day, row);
+        }
+    }
+
+    Ok(rows)
+}
+
+// Days in [start, end) that aren't in the cache, as a range covering all of them
+fn missing_days<T>(
+    cached: &BTreeMap<NaiveDate, T>,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Option<(NaiveDate, NaiveDate)> {
+    let mut missing = days(start, end).filter(|day| !cached.contains_key(day));
+    let first = missing.next()?;
+    let last = missing.last().unwrap_or(first);
+    Some((first, last + Duration::days(1)))
+}
+
+// Caches rows for days that won't change any more
+async fn write_cache<T: Serialize>(
+    db: &Database,
+    report: &str,
+    key: &str,
+    rows: &[(NaiveDate, &T)],
+) -> mongodb::error::Result<()> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.report_cache);
+    let options = UpdateOptions::builder().upsert(true).build();
+
+    for (day, row) in rows {
+        let day = day.format(DAY_FORMAT).to_string();
+        let filter = doc! {"Report": report, "FilterKey": key, "Day": &day};
+        let update = doc! {
+            "$set": {
+                "Row": mongodb::bson::to_document(row)?,
+                "ComputedAt": mongodb::bson::DateTime::now(),
+            },
+        };
+        collection
+            .update_one(filter, update, options.clone())
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Returns the days each player was active on, keyed by NetID. Read from player_days rather than
+// the sessions, which the retention job prunes.
+async fn player_days(
+    db: &Database,
+    filter: &StatsFilter,
+) -> mongodb::error::Result<HashMap<String, HashSet<NaiveDate>>> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.player_days);
+
+    // Developers playing in the editor aren't players, unless the filter asks for them
+    let mut key_filter = crate::rollups::rollup_filter(filter);
+    if !key_filter.contains_key("IsPlayInEditorSession") {
+        key_filter.insert("IsPlayInEditorSession", false);
+    }
+
+    let options = FindOptions::builder()
+        .projection(doc! {"NetID": 1, "Day": 1})
+        .build();
+    let mut players: HashMap<String, HashSet<NaiveDate>> = HashMap::new();
+    let mut cursor = collection.find(key_filter, options).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let day = document
+            .get_str("Day")
+            .ok()
+            .and_then(|day| NaiveDate::parse_from_str(day, DAY_FORMAT).ok());
+        if let (Ok(net_id), Some(day)) = (document.get_str("NetID"), day) {
+            players.entry(net_id.to_string()).or_default().insert(day);
+        }
+    }
+
+    Ok(players)
+}
+
+// The first seen record a stored session counts towards and the day it started on, or None for
+// sessions without a NetID or a date
+fn first_seen_key(session: &Document) -> Option<(Document, String)> {
+    let collector = session.get_document(SESSION_COLLECTOR).ok()?;
+    let day = collector
+        .get_datetime("StartTime")
+        .ok()?
+        .to_chrono()
+        .format(DAY_FORMAT)
+        .to_string();
+    // A session's player is its first player controller
+    let net_id = collector
+        .get_array("PlayerControllerData")
+        .ok()?
+        .first()?
+        .as_document()?
+        .get_str("NetID")
+        .ok()
+        .filter(|net_id| !net_id.is_empty())?;
+
+    let mut key = doc! {"NetID": net_id};
+    key.extend(crate::rollups::session_dimensions(collector));
+    Some((key, day))
+}
+
+async fn upsert_first_seen(db: &Database, key: Document, day: &str) -> mongodb::error::Result<()> {
+    let options = UpdateOptions::builder().upsert(true).build();
+    db.database
+        .collection::<Document>(&db.collections.first_seen)
+        .update_one(key, doc! {"$min": {"FirstSeen": day}}, options)
+        .await?;
+
+    Ok(())
+}
+
+async fn insert_player_day(db: &Database, key: Document, day: &str) -> mongodb::error::Result<()> {
+    let mut player_day = key;
+    player_day.insert("Day", day);
+    match db
+        .database
+        .collection::<Document>(&db.collections.player_days)
+        .insert_one(player_day, None)
+        .await
+    {
+        Ok(_) => Ok(()),
+        // They already played that day
+        Err(e) if is_duplicate_key_error(&e) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Records the day of a newly stored session as one its player played on, and as their first
+// unless they were seen earlier
+pub async fn add_player_activity(db: &Database, session: &Document) -> mongodb::error::Result<()> {
+    let (key, day) = match first_seen_key(session) {
+        Some(first_seen) => first_seen,
+        None => return Ok(()),
+    };
+
+    upsert_first_seen(db, key.clone(), &day).await?;
+    insert_player_day(db, key, &day).await
+}
+
+// Records the first seen days of every stored session. Days of pruned sessions are only ever moved
+// earlier, never removed, since their sessions can't be looked at any more.
+pub async fn rebuild_first_seen(db: &Database) -> mongodb::error::Result<()> {
+    let options = FindOptions::builder()
+        .projection(doc! {SESSION_COLLECTOR: 1})
+        .build();
+    let mut cursor = db
+        .database
+        .collection::<Document>(&db.collections.sessions)
+        .find(doc! {START_TIME_FIELD: {"$type": "date"}}, options)
+        .await?;
+
+    // Keyed by the key as a string, since documents can't be map keys
+    let mut first_seen: HashMap<String, (Document, String)> = HashMap::new();
+    while let Some(session) = cursor.try_next().await? {
+        let (key, day) = match first_seen_key(&session) {
+            Some(first_seen) => first_seen,
+            None => continue,
+        };
+
+        let (_, first_day) = first_seen
+            .entry(key.to_string())
+            .or_insert_with(|| (key, day.clone()));
+        if day < *first_day {
+            *first_day = day;
+        }
+    }
+
+    for (key, day) in first_seen.into_values() {
+        upsert_first_seen(db, key, &day).await?;
+    }
+
+    Ok(())
+}
+
+// Records the days every stored session's player played on. Days of pruned sessions are kept,
+// since their sessions can't be looked at any more.
+pub async fn rebuild_player_days(db: &Database) -> mongodb::error::Result<()> {
+    let options = FindOptions::builder()
+        .projection(doc! {SESSION_COLLECTOR: 1})
+        .build();
+    let mut cursor = db
+        .database
+        .collection::<Document>(&db.collections.sessions)
+        .find(doc! {START_TIME_FIELD: {"$type": "date"}}, options)
+        .await?;
+
+    // Keyed by the key and day as a string, since documents can't be map keys
+    let mut player_days: HashMap<String, (Document, String)> = HashMap::new();
+    while let Some(session) = cursor.try_next().await? {
+        if let Some((key, day)) = first_seen_key(&session) {
+            player_days
+                .entry(format!("{}{}", key, day))
+                .or_insert((key, day));
+        }
+    }
+
+    for (key, day) in player_days.into_values() {
+        insert_player_day(db, key, &day).await?;
+    }
+
+    Ok(())
+}
+
+// Moves the days erased players played on over to `replacement`, or deletes them without one
+async fn replace_player_days(
+    db: &Database,
+    net_ids: &[String],
+    replacement: Option<&str>,
+) -> mongodb::error::Result<()> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.player_days);
+    let filter = doc! {"NetID": {"$in": net_ids}};
+    if let Some(replacement) = replacement {
+        // Merged on the unique key, since several of the NetIDs can share a day
+        let mut on = crate::rollups::ROLLUP_KEY.to_vec();
+        on.push("NetID");
+        let mut project: Document = on
+            .iter()
+            .map(|field| (field.to_string(), 1.into()))
+            .collect();
+        project.insert("_id", 0);
+        project.insert("NetID", doc! {"$literal": replacement});
+        let pipeline = [
+            doc! {"$match": filter.clone()},
+            doc! {"$project": project},
+            doc! {"$merge": {
+                "into": &db.collections.player_days,
+                "on": on,
+                "whenMatched": "keepExisting",
+            }},
+        ];
+        collection.aggregate(pipeline, None).await?;
+    }
+    collection.delete_many(filter, None).await?;
+
+    Ok(())
+}
+
+// Moves the first seen and played days of erased players over to `replacement`, or deletes them
+// without one
+pub async fn replace_player_activity(
+    db: &Database,
+    net_ids: &[String],
+    replacement: Option<&str>,
+) -> mongodb::error::Result<()> {
+    if net_ids.is_empty() {
+        return Ok(());
+    }
+
+    replace_first_seen(db, net_ids, replacement).await?;
+    replace_player_days(db, net_ids, replacement).await
+}
+
+async fn replace_first_seen(
+    db: &Database,
+    net_ids: &[String],
+    replacement: Option<&str>,
+) -> mongodb::error::Result<()> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.first_seen);
+    let filter = doc! {"NetID": {"$in": net_ids}};
+    let records: Vec<Document> = collection
+        .find(filter.clone(), None)
+        .await?
+        .try_collect()
+        .await?;
+    collection.delete_many(filter, None).await?;
+
+    if let Some(replacement) = replacement {
+        for mut key in records {
+            key.remove("_id");
+            let day = match key.remove("FirstSeen") {
+                Some(Bson::String(day)) => day,
+                _ => continue,
+            };
+            key.insert("NetID", replacement);
+            upsert_first_seen(db, key, &day).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// The day each player matching the filter was first seen on, for players first seen in
+// [start, end)
+async fn first_seen_days(
+    db: &Database,
+    filter: &StatsFilter,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> mongodb::error::Result<HashMap<String, NaiveDate>> {
+    let collection = db
+        .database
+        .collection::<Document>(&db.collections.first_seen);
+
+    // Same as player_days
+    let mut key_filter = crate::rollups::rollup_filter(&StatsFilter {
+        from: None,
+        to: None,
+        ..filter.clone()
+    });
+    if !key_filter.contains_key("IsPlayInEditorSession") {
+        key_filter.insert("IsPlayInEditorSession", false);
+    }
+
+    let options = FindOptions::builder()
+        .projection(doc! {"NetID": 1, "FirstSeen": 1})
+        .build();
+    let mut in_range = key_filter.clone();
+    in_range.insert(
+        "FirstSeen",
+        doc! {
+            "$gte": start.format(DAY_FORMAT).to_string(),
+            "$lt": end.format(DAY_FORMAT).to_string(),
+        },
+    );
+
+    // A player has a record per build, platform and country they played on
+    let mut players: HashMap<String, NaiveDate> = HashMap::new();
+    let mut cursor = collection.find(in_range, options.clone()).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let day = document
+            .get_str("FirstSeen")
+            .ok()
+            .and_then(|day| NaiveDate::parse_from_str(day, DAY_FORMAT).ok());
+        if let (Ok(net_id), Some(day)) = (document.get_str("NetID"), day) {
+            let first_day = players.entry(net_id.to_string()).or_insert(day);
+            *first_day = (*first_day).min(day);
+        }
+    }
+    if players.is_empty() {
+        return Ok(players);
+    }
+
+    // Players that were seen before the range on another build, platform or country
+    let net_ids: Vec<&String> = players.keys().collect();
+    let mut earlier = key_filter;
+    earlier.insert("NetID", doc! {"$in": net_ids});
+    earlier.insert(
+        "FirstSeen",
+        doc! {"$lt": start.format(DAY_FORMAT).to_string()},
+    );
+    let earlier: Vec<String> = collection
+        .find(earlier, options)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .into_iter()
+        .filter_map(|document| document.get_str("NetID").ok().map(String::from))
+        .collect();
+    for net_id in earlier {
+        players.remove(&net_id);
+    }
+
+    Ok(players)
+}
+
+// The days player_days has to cover for active players in [start, end). MAU on the first day needs
+// the 29 days before it.
+fn active_players_filter(filter: &StatsFilter, start: NaiveDate, end: NaiveDate) -> StatsFilter {
+    StatsFilter {
+        from: Some(
+            (start - Duration::days(29))
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc(),
+        ),
+        to: Some(end.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+        ..filter.clone()
+    }
+}
+
+// Daily, weekly and monthly active players for every day in [start, end), from the days each player
+// was active on
+fn active_players_rows(
+    player_days: HashMap<String, HashSet<NaiveDate>>,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Vec<ActivePlayers> {
+    let mut day_players: HashMap<NaiveDate, HashSet<String>> = HashMap::new();
+    for (net_id, active_days) in player_days {
+        for day in active_days {
+            day_players.entry(day).or_default().insert(net_id.clone());
+        }
+    }
+
+    let unique_players = |last_day: NaiveDate, window: i64| {
+        let mut players = HashSet::new();
+        for day in days(
+            last_day - Duration::days(window - 1),
+            last_day + Duration::days(1),
+        ) {
+            if let Some(day_players) = day_players.get(&day) {
+                players.extend(day_players.iter());
+            }
+        }
+        players.len() as u64
+    };
+
+    days(start, end)
+        .map(|day| ActivePlayers {
+            date: day,
+            dau: unique_players(day, 1),
+            wau: unique_players(day, 7),
+            mau: unique_players(day, 30),
+        })
+        .collect()
+}
+
+// The days player_days has to cover for cohorts in [start, end), up to D30 of the last one
+fn retention_filter(filter: &StatsFilter, start: NaiveDate, end: NaiveDate) -> StatsFilter {
+    StatsFilter {
+        from: Some(start.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+        to: Some(
+            (end + Duration::days(30))
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc(),
+        ),
+        ..filter.clone()
+    }
+}
+
+// Retention cohorts for every day in [start, end), from the day each player was first seen on and
+// the days they were active on
+fn retention_rows(
+    first_seen: HashMap<String, NaiveDate>,
+    mut active_days: HashMap<String, HashSet<NaiveDate>>,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Vec<RetentionCohort> {
+    let today = Utc::now().date_naive();
+    let mut cohorts: BTreeMap<NaiveDate, Vec<HashSet<NaiveDate>>> =
+        days(start, end).map(|day| (day, Vec::new())).collect();
+    for (net_id, first_day) in first_seen {
+        if let Some(cohort) = cohorts.get_mut(&first_day) {
+            cohort.push(active_days.remove(&net_id).unwrap_or_default());
+        }
+    }
+
+    cohorts
+        .into_iter()
+        .map(|(day, players)| {
+            let rate = |offset: i64| {
+                let target = day + Duration::days(offset);
+                // The day hasn't finished yet
+                if target >= today || players.is_empty() {
+                    return None;
+                }
+
+                let retained = players
+                    .iter()
+                    .filter(|active_days| active_days.contains(&target))
+                    .count();
+                Some(retained as f64 / players.len() as f64)
+            };
+
+            RetentionCohort {
+                date: day,
+                players: players.len() as u64,
+                d1: rate(1),
+                d7: rate(7),
+                d30: rate(30),
+            }
+        })
+        .collect()
+}
+
+// Daily, weekly and monthly active players for every day in the filter's date range (the last 30
+// days by default). Finished days are cached for `ttl_secs`.
+pub async fn active_players(
+    db: &Database,
+    filter: &StatsFilter,
+    ttl_secs: u64,
+) -> mongodb::error::Result<Vec<ActivePlayers>> {
+    let (start, end) = report_days(filter);
+    let key = cache_key(filter);
+
+    let mut rows = read_cache(db, "active_players", &key, start, end, ttl_secs).await?;
+    let (start, end) = match missing_days(&rows, start, end) {
+        Some(missing) => missing,
+        None => return Ok(rows.into_values().collect()),
+    };
+
+    let player_days = player_days(db, &active_players_filter(filter, start, end)).await?;
+    let computed = active_players_rows(player_days, start, end);
+
+    // Today can still change
+    let today = Utc::now().date_naive();
+    let finished: Vec<_> = computed
+        .iter()
+        .filter(|row| row.date < today)
+        .map(|row| (row.date, row))
+        .collect();
+    write_cache(db, "active_players", &key, &finished).await?;
+
+    rows.extend(computed.into_iter().map(|row| (row.date, row)));
+    Ok(rows.into_values().collect())
+}
+
+// Retention cohorts for every day in the filter's date range (the last 30 days by default).
+// Cohorts whose D30 is known are cached for `ttl_secs`.
+pub async fn retention_cohorts(
+    db: &Database,
+    filter: &StatsFilter,
+    ttl_secs: u64,
+) -> mongodb::error::Result<Vec<RetentionCohort>> {
+    let (start, end) = report_days(filter);
+    let key = cache_key(filter);
+
+    let mut rows = read_cache(db, "retention", &key, start, end, ttl_secs).await?;
+    let (start, end) = match missing_days(&rows, start, end) {
+        Some(missing) => missing,
+        None => return Ok(rows.into_values().collect()),
+    };
+
+    // A player's cohort is the first day they ever played, which is kept in first_seen, so only
+    // the days played from the range up to D30 of its last day are needed
+    let first_seen = first_seen_days(db, filter, start, end).await?;
+    let active_days = player_days(db, &retention_filter(filter, start, end)).await?;
+    let computed = retention_rows(first_seen, active_days, start, end);
+
+    // Only cohorts whose D30 is known won't change any more
+    let today = Utc::now().date_naive();
+    let finished: Vec<_> = computed
+        .iter()
+        .filter(|row| row.date + Duration::days(30) < today)
+        .map(|row| (row.date, row))
+        .collect();
+    write_cache(db, "retention", &key, &finished).await?;
+
+    rows.extend(computed.into_iter().map(|row| (row.date, row)));
+    Ok(rows.into_values().collect())
+}
+
+// The first seen key and day of each of `sessions`, the records add_player_activity keeps for them
+fn session_activity<'a>(
+    sessions: impl IntoIterator<Item = &'a Document>,
+) -> Vec<(Document, NaiveDate)> {
+    sessions
+        .into_iter()
+        .filter_map(first_seen_key)
+        .filter_map(|(key, day)| Some((key, NaiveDate::parse_from_str(&day, DAY_FORMAT).ok()?)))
+        .collect()
+}
+
+// Same as player_days, for activity records
+fn activity_days(
+    activity: &[(Document, NaiveDate)],
+    filter: &StatsFilter,
+) -> HashMap<String, HashSet<NaiveDate>> {
+    let filter = StatsFilter {
+        pie: Some(filter.pie.unwrap_or(false)),
+        ..filter.clone()
+    };
+
+    let mut players: HashMap<String, HashSet<NaiveDate>> = HashMap::new();
+    for (key, day) in activity {
+        let mut player_day = key.clone();
+        player_day.insert("Day", day.format(DAY_FORMAT).to_string());
+        if let (true, Ok(net_id)) = (
+            crate::rollups::rollup_matches(&filter, &player_day),
+            key.get_str("NetID"),
+        ) {
+            players.entry(net_id.to_string()).or_default().insert(*day);
+        }
+    }
+    players
+}
+
+// Same as first_seen_days, for activity records
+fn activity_first_seen(
+    activity: &[(Document, NaiveDate)],
+    filter: &StatsFilter,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> HashMap<String, NaiveDate> {
+    let filter = StatsFilter {
+        from: None,
+        to: None,
+        ..filter.clone()
+    };
+
+    let mut first_seen: HashMap<String, NaiveDate> = HashMap::new();
+    for (net_id, days) in activity_days(activity, &filter) {
+        if let Some(first_day) = days.into_iter().min() {
+            first_seen.insert(net_id, first_day);
+        }
+    }
+    first_seen.retain(|_, day| *day >= start && *day < end);
+    first_seen
+}
+
+// active_players worked out straight from stored session documents, without a cache
+pub fn session_active_players<'a>(
+    filter: &StatsFilter,
+    sessions: impl IntoIterator<Item = &'a Document>,
+) -> Vec<ActivePlayers> {
+    let (start, end) = report_days(filter);
+    let activity = session_activity(sessions);
+
+    let player_days = activity_days(&activity, &active_players_filter(filter, start, end));
+    active_players_rows(player_days, start, end)
+}
+
+// retention_cohorts worked out straight from stored session documents, without a cache
+pub fn session_retention_cohorts<'a>(
+    filter: &StatsFilter,
+    sessions: impl IntoIterator<Item = &'a Document>,
+) -> Vec<RetentionCohort> {
+    let (start, end) = report_days(filter);
+    let activity = session_activity(sessions);
+
+    let first_seen = activity_first_seen(&activity, filter, start, end);
+    let active_days = activity_days(&activity, &retention_filter(filter, start, end));
+    retention_rows(first_seen, active_days, start, end)
+}
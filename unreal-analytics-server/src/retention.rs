@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+use rocket::serde::json::{serde_json, Value};
+use rocket::tokio::io::AsyncWriteExt;
+use serde::Serialize;
+
+use crate::config::RetentionConfig;
+use crate::database::Database;
+use crate::session::SESSION_COLLECTOR;
+use crate::ServerState;
+
+// Sessions archived and deleted per round trip, so a big backlog doesn't have to fit in memory
+const BATCH_SIZE: i64 = 1000;
+const DAY_FORMAT: &str = "%Y-%m-%d";
+
+// Held while archives are written, so a rewrite doesn't drop an archive written while it runs
+static ARCHIVE_LOCK: rocket::tokio::sync::Mutex<()> = rocket::tokio::sync::Mutex::const_new(());
+
+// What one run of the retention job did
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RetentionReport {
+    pub pie_sessions: u64,
+    pub game_sessions: u64,
+    // Days that had sessions pruned, as YYYY-MM-DD. Rebuilding the rollups leaves these days alone.
+    pub days: Vec<String>,
+    pub archives: Vec<String>,
+}
+
+impl std::fmt::Display for RetentionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "pruned {} PIE and {} game sessions from {} days",
+            self.pie_sessions,
+            self.game_sessions,
+            self.days.len()
+        )
+    }
+}
+
+fn field(name: &str) -> String {
+    format!("{}.{}", SESSION_COLLECTOR, name)
+}
+
+// Writes the sessions to an archive as gzipped JSON lines. Writing the same archive again replaces
+// it, so a batch that's archived twice still only ends up in it once.
+async fn write_archive(path: &Path, sessions: &[Document]) -> std::io::Result<()> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    for session in sessions {
+        let json = Bson::Document(session.clone()).into_relaxed_extjson();
+        writeln!(encoder, "{}", json)?;
+    }
+    let bytes = encoder.finish()?;
+
+    let _lock = ARCHIVE_LOCK.lock().await;
+    // Written next to the archive first so a failure part way through can't leave half of it
+    let temp_path = path.with_extension("tmp");
+    let mut file = rocket::tokio::fs::File::create(&temp_path).await?;
+    file.write_all(&bytes).await?;
+    // The sessions get deleted next, so the archive has to actually be on disk
+    file.sync_all().await?;
+    rocket::tokio::fs::rename(&temp_path, path).await
+}
+
+// What to do with an archived session
+pub enum ArchiveEdit {
+    Keep,
+    Replace(Document),
+    Remove,
+}
+
+// Rewrites one archive with `edit` applied to every session. Returns how many were changed.
+fn rewrite_archive(path: &Path, edit: &dyn Fn(&Document) -> ArchiveEdit) -> std::io::Result<u64> {
+    let reader = std::io::BufReader::new(flate2::read::MultiGzDecoder::new(std::fs::File::open(
+        path,
+    )?));
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+    let mut changed = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let session = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|json| Bson::try_from(json).ok());
+        let edited = match &session {
+            Some(Bson::Document(session)) => edit(session),
+            // Lines that can't be read are kept as they are
+            _ => ArchiveEdit::Keep,
+        };
+
+        match edited {
+            ArchiveEdit::Keep => writeln!(encoder, "{}", line)?,
+            ArchiveEdit::Replace(session) => {
+                writeln!(
+                    encoder,
+                    "{}",
+                    Bson::Document(session).into_relaxed_extjson()
+                )?;
+                changed += 1;
+            }
+            ArchiveEdit::Remove => changed += 1,
+        }
+    }
+
+    if changed > 0 {
+        // Written next to the archive first so a failure part way through can't lose it
+        let temp_path = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(&encoder.finish()?)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+    }
+
+    Ok(changed)
+}
+
+// Applies `edit` to every archived session, for when stored sessions have to change after they've
+// been archived. Returns how many sessions were changed.
+pub async fn rewrite_archives(
+    archive_dir: &str,
+    edit: Box<dyn Fn(&Document) -> ArchiveEdit + Send>,
+) -> std::io::Result<u64> {
+    let _lock = ARCHIVE_LOCK.lock().await;
+
+    let archive_dir = PathBuf::from(archive_dir);
+    rocket::tokio::task::spawn_blocking(move || {
+        let entries = match std::fs::read_dir(&archive_dir) {
+            Ok(entries) => entries,
+            // Nothing has been archived yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut changed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(".jsonl.gz") {
+                changed += rewrite_archive(&path, edit.as_ref())?;
+            }
+        }
+        Ok(changed)
+    })
+    .await?
+}
+
+// Archives and deletes every session of one kind that started before `cutoff`. Each batch is
+// marked with a PruneBatch id before it's archived, so a run that died part way through is finished
+// by the next one, which rewrites the batch's archives instead of adding the sessions twice.
+async fn prune_sessions(
+    db: &Database,
+    config: &RetentionConfig,
+    pie: bool,
+    cutoff: chrono::DateTime<chrono::Utc>,
+    report: &mut RetentionReport,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let collection = db.database.collection::<Document>(&db.collections.sessions);
+
+    // Sessions with string dates are left alone until migration #1 has converted them
+    let filter = doc! {
+        field("IsPlayInEditorSession"): pie,
+        field("StartTime"): {"$lt": cutoff, "$type": "date"},
+    };
+    let options = FindOptions::builder()
+        .sort(doc! {field("StartTime"): 1})
+        .limit(BATCH_SIZE)
+        .projection(doc! {"_id": 1})
+        .build();
+
+    let kind = if pie { "pie" } else { "game" };
+    let mut pruned = 0;
+    loop {
+        let mut unfinished = filter.clone();
+        unfinished.insert("PruneBatch", doc! {"$exists": true});
+        let batch = match collection.find_one(unfinished, None).await? {
+            Some(session) => session.get_object_id("PruneBatch")?,
+            None => {
+                let mut unmarked = filter.clone();
+                unmarked.insert("PruneBatch", doc! {"$exists": false});
+                let ids: Vec<Bson> = collection
+                    .find(unmarked, options.clone())
+                    .await?
+                    .try_collect::<Vec<Document>>()
+                    .await?
+                    .into_iter()
+                    .filter_map(|session| session.get("_id").cloned())
+                    .collect();
+                if ids.is_empty() {
+                    break;
+                }
+
+                let batch = ObjectId::new();
+                collection
+                    .update_many(
+                        doc! {"_id": {"$in": ids}},
+                        doc! {"$set": {"PruneBatch": batch}},
+                        None,
+                    )
+                    .await?;
+                batch
+            }
+        };
+
+        let sessions: Vec<Document> = collection
+            .find(doc! {"PruneBatch": batch}, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        // Every session in the batch matched the filter, so they all have a StartTime
+        let mut days: BTreeMap<String, Vec<Document>> = BTreeMap::new();
+        for mut session in sessions {
+            let day = match session
+                .get_document(SESSION_COLLECTOR)
+                .and_then(|collector| collector.get_datetime("StartTime"))
+            {
+                Ok(start_time) => start_time.to_chrono().format(DAY_FORMAT).to_string(),
+                Err(_) => continue,
+            };
+            session.remove("PruneBatch");
+            days.entry(day).or_default().push(session);
+        }
+
+        for (day, sessions) in &days {
+            let path = PathBuf::from(&config.archive_dir).join(format!(
+                "sessions-{}-{}-{}.jsonl.gz",
+                day,
+                kind,
+                batch.to_hex()
+            ));
+            write_archive(&path, sessions).await?;
+
+            let path = path.to_string_lossy().to_string();
+            if !report.archives.contains(&path) {
+                report.archives.push(path);
+            }
+            if !report.days.contains(day) {
+                report.days.push(day.clone());
+            }
+        }
+
+        let deleted = collection
+            .delete_many(doc! {"PruneBatch": batch}, None)
+            .await?;
+        pruned += deleted.deleted_count;
+    }
+
+    Ok(pruned)
+}
+
+// Prunes every session past its retention period and records what was pruned
+pub async fn run(
+    db: &Database,
+    config: &RetentionConfig,
+) -> Result<RetentionReport, Box<dyn std::error::Error + Send + Sync>> {
+    rocket::tokio::fs::create_dir_all(&config.archive_dir).await?;
+
+    let now = chrono::Utc::now();
+    let pie_cutoff = now - chrono::Duration::days(config.pie_session_days as i64);
+    let game_cutoff = now - chrono::Duration::days(config.game_session_days as i64);
+
+    let mut report = RetentionReport::default();
+    let result = async {
+        report.pie_sessions = prune_sessions(db, config, true, pie_cutoff, &mut report).await?;
+        report.game_sessions = prune_sessions(db, config, false, game_cutoff, &mut report).await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    }
+    .await;
+    report.days.sort();
+
+    if report.pie_sessions > 0 || report.game_sessions > 0 {
+        let mut document = mongodb::bson::to_document(&report)?;
+        document.insert("RunAt", now);
+        db.database
+            .collection::<Document>(&db.collections.retention_reports)
+            .insert_one(document, None)
+            .await?;
+    }
+
+    // The report is saved even when pruning failed part way, since its days are gone from the
+    // sessions collection either way
+    result?;
+    Ok(report)
+}
+
+// Spawns the task that prunes old sessions
+pub fn initialize(state: Arc<ServerState>) {
+    tokio::task::spawn(async move {
+        loop {
+            let config = match state.read_config() {
+                Some(config) => config.retention,
+                None => state.default_config.retention.clone(),
+            };
+
+            if config.enabled {
+                match state.storage.prune_sessions(&config).await {
+                    Ok(report) if report.pie_sessions == 0 && report.game_sessions == 0 => {}
+                    Ok(report) => println!("Retention: {}", report),
+                    Err(e) => eprintln!("Retention: failed to prune sessions: {}", e),
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(config.interval_secs.max(60))).await;
+        }
+    });
+}
@@ -0,0 +1,1060 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::Timelike;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use serde::Serialize;
+
+use crate::database::{
+    is_duplicate_key_error, Database, PlayTimeBucket, PlayTimeDistribution, PlayTimePercentile,
+    StatsFilter, PLAY_TIME_BUCKETS, PLAY_TIME_PERCENTILES, PLAY_TIME_TRIM,
+};
+use crate::session::{FEEDBACK_COLLECTOR, SESSION_COLLECTOR};
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+// Stands in for a missing build, platform or country, since rollup keys can't be null
+const UNKNOWN: &str = "";
+// The fields a rollup is keyed on, see session_rollup
+pub const ROLLUP_KEY: [&str; 6] = [
+    "Day",
+    "BuildVersion",
+    "Platform",
+    "CountryCode",
+    "IsPlayInEditorSession",
+    "IsSteam",
+];
+// Id of the document in the migrations collection that's there while `rebuild` runs
+const REBUILD_LOCK: &str = "rebuild-rollups";
+// How often a running rebuild refreshes HeartbeatAt on its lock
+const REBUILD_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// A lock that hasn't had a heartbeat for this long belongs to a rebuild that died
+const REBUILD_LOCK_EXPIRY: chrono::TimeDelta = chrono::TimeDelta::seconds(120);
+
+// What a session adds to its rollup, by $inc path
+type Counters = BTreeMap<String, i64>;
+
+// Counters for one day of sessions with the same build, platform, country, PIE and steam flags
+#[derive(Serialize, Debug, Clone)]
+pub struct DailyRollup {
+    pub day: String,
+    pub pie_sessions: u64,
+    pub game_sessions: u64,
+    // Unique IPs, same as PlayerStats::unique_players
+    pub unique_players: u64,
+    pub play_time_secs: f64,
+    pub feedback_comments: u64,
+}
+
+// Game sessions that lasted between PLAY_TIME_BUCKETS[i] and the next edge
+#[derive(Debug, Clone, Default)]
+pub struct PlayTimeBucketTotals {
+    pub min_secs: i64,
+    pub sessions: u64,
+    pub play_time_ms: i64,
+}
+
+// Session counts that PlayerStats is made from
+#[derive(Debug, Clone, Default)]
+pub struct SessionTotals {
+    pub pie_sessions: u64,
+    pub game_sessions: u64,
+    pub unique_players: u64,
+    // Game sessions with both dates, the ones play time is known for
+    pub timed_sessions: u64,
+    pub play_time_ms: i64,
+    // One per PLAY_TIME_BUCKETS edge
+    pub play_time_buckets: Vec<PlayTimeBucketTotals>,
+}
+
+// Filter on the Day of rollups and anonymous counts. They only know the day a session started on,
+// so dates are widened to whole days: `from` counts from the start of its day and `to` up to the
+// end of its day unless it's at midnight.
+pub fn day_filter(filter: &StatsFilter) -> Document {
+    let is_midnight = |date: &chrono::DateTime<chrono::Utc>| {
+        date.num_seconds_from_midnight() == 0 && date.nanosecond() == 0
+    };
+
+    let mut day = doc! {};
+    if let Some(from) = filter.from {
+        day.insert("$gte", from.format(DAY_FORMAT).to_string());
+    }
+    if let Some(to) = filter.to {
+        let op = if is_midnight(&to) { "$lt" } else { "$lte" };
+        day.insert(op, to.format(DAY_FORMAT).to_string());
+    }
+    day
+}
+
+// Whether a rollup's Day passes day_filter, for checking days outside of mongo
+pub fn day_matches(filter: &StatsFilter, day: &str) -> bool {
+    day_filter(filter).iter().all(|(op, bound)| {
+        let bound = bound.as_str().unwrap_or_default();
+        match op.as_str() {
+            "$gte" => day >= bound,
+            "$lt" => day < bound,
+            "$lte" => day <= bound,
+            _ => false,
+        }
+    })
+}
+
+pub fn rollup_filter(filter: &StatsFilter) -> Document {
+    let mut match_filter = doc! {};
+    let day = day_filter(filter);
+    if !day.is_empty() {
+        match_filter.insert("Day", day);
+    }
+
+    if let Some(build_version) = &filter.build_version {
+        match_filter.insert("BuildVersion", build_version);
+    }
+    if let Some(platform) = &filter.platform {
+        match_filter.insert("Platform", platform);
+    }
+    if let Some(country) = &filter.country {
+        match_filter.insert("CountryCode", country.to_uppercase());
+    }
+    if let Some(pie) = filter.pie {
+        match_filter.insert("IsPlayInEditorSession", pie);
+    }
+    if let Some(steam) = filter.steam {
+        match_filter.insert("IsSteam", steam);
+    }
+
+    match_filter
+}
+
+// Whether a rollup key passes rollup_filter, for checking keys outside of mongo
+pub fn rollup_matches(filter: &StatsFilter, key: &Document) -> bool {
+    let matches_str = |field: &str, value: Option<&str>| {
+        value.is_none_or(|value| key.get_str(field).ok() == Some(value))
+    };
+    let matches_bool = |field: &str, value: Option<bool>| {
+        value.is_none_or(|value| key.get_bool(field).ok() == Some(value))
+    };
+
+    day_matches(filter, key.get_str("Day").unwrap_or(UNKNOWN))
+        && matches_str("BuildVersion", filter.build_version.as_deref())
+        && matches_str("Platform", filter.platform.as_deref())
+        && matches_str(
+            "CountryCode",
+            filter.country.as_ref().map(|c| c.to_uppercase()).as_deref(),
+        )
+        && matches_bool("IsPlayInEditorSession", filter.pie)
+        && matches_bool("IsSteam", filter.steam)
+}
+
+// The PLAY_TIME_BUCKETS edge a play time falls under
+fn play_time_bucket(play_time_ms: i64) -> i64 {
+    PLAY_TIME_BUCKETS
+        .iter()
+        .rev()
+        .find(|min_secs| play_time_ms >= *min_secs * 1000)
+        .copied()
+        .unwrap_or(0)
+}
+
+// Play time histogram totals of the given game session play times, the same as their rollups would
+// add up to
+pub fn play_time_buckets(
+    play_times_ms: impl IntoIterator<Item = i64>,
+) -> Vec<PlayTimeBucketTotals> {
+    let mut buckets: Vec<PlayTimeBucketTotals> = PLAY_TIME_BUCKETS
+        .iter()
+        .map(|min_secs| PlayTimeBucketTotals {
+            min_secs: *min_secs,
+            ..Default::default()
+        })
+        .collect();
+    for play_time_ms in play_times_ms.into_iter().filter(|ms| *ms >= 0) {
+        let min_secs = play_time_bucket(play_time_ms);
+        if let Some(bucket) = buckets
+            .iter_mut()
+            .find(|bucket| bucket.min_secs == min_secs)
+        {
+            bucket.sessions += 1;
+            bucket.play_time_ms += play_time_ms;
+        }
+    }
+    buckets
+}
+
+// The build, platform, country, PIE and steam flags of a session's collector, as rollup fields
+pub fn session_dimensions(collector: &Document) -> Document {
+    let get_str = |key: &str| collector.get_str(key).unwrap_or(UNKNOWN);
+    // Same check as StatsFilter::session_filter
+    let is_steam = collector
+        .get_array("PlayerControllerData")
+        .ok()
+        .and_then(|player_controllers| player_controllers.first())
+        .and_then(|player_controller| player_controller.as_document())
+        .is_some_and(|player_controller| player_controller.contains_key("SteamAnalyticsData"));
+
+    doc! {
+        "BuildVersion": get_str("BuildVersion"),
+        "Platform": get_str("Platform"),
+        "CountryCode": get_str("CountryCode"),
+        "IsPlayInEditorSession": collector.get_bool("IsPlayInEditorSession").unwrap_or(false),
+        "IsSteam": is_steam,
+    }
+}
+
+// The rollup a stored session counts towards and what it adds to it, or None for sessions that
+// can't be put on a day. Counter names are $inc paths.
+fn session_rollup(session: &Document) -> Option<(Document, Counters, Option<String>)> {
+    let collector = session.get_document(SESSION_COLLECTOR).ok()?;
+    let start_time = collector.get_datetime("StartTime").ok()?.to_chrono();
+
+    let mut key = doc! {"Day": start_time.format(DAY_FORMAT).to_string()};
+    key.extend(session_dimensions(collector));
+
+    let feedback_comments = session
+        .get_document(FEEDBACK_COLLECTOR)
+        .and_then(|feedback| feedback.get_array("FeedbackComments"))
+        .map(|comments| comments.len() as i64)
+        .unwrap_or(0);
+    let mut counters = Counters::from([
+        ("Sessions".to_string(), 1),
+        ("FeedbackComments".to_string(), feedback_comments),
+    ]);
+    if let Ok(end_time) = collector.get_datetime("EndTime") {
+        let play_time_ms = (end_time.to_chrono() - start_time).num_milliseconds();
+        counters.insert("TimedSessions".to_string(), 1);
+        counters.insert("PlayTimeMs".to_string(), play_time_ms);
+
+        // Same as the distribution pipeline, which leaves out sessions that end before they start
+        if play_time_ms >= 0 {
+            let bucket = format!("PlayTimeHistogram.{}", play_time_bucket(play_time_ms));
+            counters.insert(format!("{}.Sessions", bucket), 1);
+            counters.insert(format!("{}.PlayTimeMs", bucket), play_time_ms);
+        }
+    }
+
+    Some((
+        key,
+        counters,
+        collector.get_str("ip").ok().map(String::from),
+    ))
+}
+
+// Turns $inc paths into the nested documents $inc would have made
+fn nest_counters(counters: &Counters) -> Document {
+    let mut document = Document::new();
+    'counters: for (name, value) in counters {
+        let mut parts: Vec<&str> = name.split('.').collect();
+        let last = parts.pop().unwrap_or_default();
+
+        let mut target = &mut document;
+        for part in parts {
+            let nested = target
+                .entry(part.to_string())
+                .or_insert_with(|| Document::new().into());
+            target = match nested {
+                Bson::Document(nested) => nested,
+                // session_rollup never gives a path that goes through a counter
+                _ => continue 'counters,
+            };
+        }
+        target.insert(last, *value);
+    }
+    document
+}
+
+// Counts a stored session towards the rollups and rollup players in the given collections
+async fn count_session(
+    db: &Database,
+    rollups: &str,
+    players: &str,
+    session: &Document,
+) -> mongodb::error::Result<()> {
+    let (key, counters, ip) = match session_rollup(session) {
+        Some(rollup) => rollup,
+        None => return Ok(()),
+    };
+
+    let counters: Document = counters
+        .into_iter()
+        .map(|(name, value)| (name, Bson::Int64(value)))
+        .collect();
+    let options = UpdateOptions::builder().upsert(true).build();
+    db.database
+        .collection::<Document>(rollups)
+        .update_one(key.clone(), doc! {"$inc": counters}, options)
+        .await?;
+
+    if let Some(ip) = ip {
+        let mut player = key;
+        player.insert("Player", ip);
+        match db
+            .database
+            .collection::<Document>(players)
+            .insert_one(player, None)
+            .await
+        {
+            Ok(_) => {}
+            // They already played that day
+            Err(e) if is_duplicate_key_error(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+fn rebuild_lock_expired_before() -> mongodb::bson::DateTime {
+    mongodb::bson::DateTime::from_chrono(chrono::Utc::now() - REBUILD_LOCK_EXPIRY)
+}
+
+// Adds a newly stored session to its rollup. While the rollups are being rebuilt it's queued on
+// the rebuild's lock instead, since the rollups it would be added to are about to be replaced.
+pub async fn add_session(db: &Database, session: &Document) -> mongodb::error::Result<()> {
+    if let Ok(id) = session.get_object_id("_id") {
+        let queued = db
+            .database
+            .collection::<Document>(&db.collections.migrations)
+            .update_one(
+                doc! {"_id": REBUILD_LOCK, "HeartbeatAt": {"$gte": rebuild_lock_expired_before()}},
+                doc! {"$push": {"Pending": id}},
+                None,
+            )
+            .await?
+            .matched_count
+            == 1;
+        if queued {
+            return Ok(());
+        }
+    }
+
+    count_session(
+        db,
+        &db.collections.daily_rollups,
+        &db.collections.rollup_players,
+        session,
+    )
+    .await
+}
+
+// Days the retention job has pruned sessions from, as YYYY-MM-DD
+async fn pruned_days(db: &Database) -> mongodb::error::Result<HashSet<String>> {
+    let days = db
+        .database
+        .collection::<Document>(&db.collections.retention_reports)
+        .distinct("days", None, None)
+        .await?;
+
+    Ok(days
+        .into_iter()
+        .filter_map(|day| day.as_str().map(String::from))
+        .collect())
+}
+
+fn staging_name(collection: &str) -> String {
+    format!("{}_rebuild", collection)
+}
+
+// Computes the rollups into staging collections and then puts them in place of the live ones.
+// Returns the sessions that were counted.
+async fn rebuild_into_staging(db: &Database) -> mongodb::error::Result<HashSet<ObjectId>> {
+    let names = &db.collections;
+    let pruned_days = pruned_days(db).await?;
+
+    let options = FindOptions::builder()
+        .projection(doc! {
+            SESSION_COLLECTOR: 1,
+            format!("{}.FeedbackComments", FEEDBACK_COLLECTOR): 1,
+        })
+        .build();
+    let mut cursor = db
+        .database
+        .collection::<Document>(&names.sessions)
+        .find(
+            doc! {format!("{}.StartTime", SESSION_COLLECTOR): {"$type": "date"}},
+            options,
+        )
+        .await?;
+
+    // Keyed by the rollup key as a string, since documents can't be map keys
+    let mut rollups: BTreeMap<String, (Document, Counters, HashSet<String>)> = BTreeMap::new();
+    let mut counted = HashSet::new();
+    while let Some(session) = cursor.try_next().await? {
+        let (key, counters, ip) = match session_rollup(&session) {
+            Some(rollup) => rollup,
+            None => continue,
+        };
+        if pruned_days.contains(key.get_str("Day").unwrap_or_default()) {
+            continue;
+        }
+
+        let (_, totals, players) = rollups
+            .entry(key.to_string())
+            .or_insert_with(|| (key, Counters::new(), HashSet::new()));
+        for (name, value) in counters {
+            *totals.entry(name).or_default() += value;
+        }
+        players.extend(ip);
+        counted.extend(session.get_object_id("_id").ok());
+    }
+
+    // Pruned days have nothing left to recompute them from, so their rollups are carried over
+    let pruned_days: Vec<String> = pruned_days.into_iter().collect();
+    let declared_indexes = db.declared_indexes();
+    for live in [&names.daily_rollups, &names.rollup_players] {
+        let staging = db.database.collection::<Document>(&staging_name(live));
+        staging.drop(None).await?;
+        for (_, index) in declared_indexes
+            .iter()
+            .filter(|(collection, _)| collection == live)
+        {
+            staging.create_index(index.clone(), None).await?;
+        }
+
+        let carry_over = [
+            doc! {"$match": {"Day": {"$in": &pruned_days}}},
+            doc! {"$merge": {"into": staging_name(live)}},
+        ];
+        db.database
+            .collection::<Document>(live)
+            .aggregate(carry_over, None)
+            .await?;
+    }
+
+    let mut rollup_documents = Vec::with_capacity(rollups.len());
+    let mut player_documents = Vec::new();
+    for (key, counters, players) in rollups.into_values() {
+        for player in players {
+            let mut document = key.clone();
+            document.insert("Player", player);
+            player_documents.push(document);
+        }
+        let mut rollup = key;
+        rollup.extend(nest_counters(&counters));
+        rollup_documents.push(rollup);
+    }
+    for (live, documents) in [
+        (&names.daily_rollups, rollup_documents),
+        (&names.rollup_players, player_documents),
+    ] {
+        if !documents.is_empty() {
+            db.database
+                .collection::<Document>(&staging_name(live))
+                .insert_many(documents, None)
+                .await?;
+        }
+    }
+
+    let namespace = |collection: &str| format!("{}.{}", db.database.name(), collection);
+    for live in [&names.rollup_players, &names.daily_rollups] {
+        db.client
+            .database("admin")
+            .run_command(
+                doc! {
+                    "renameCollection": namespace(&staging_name(live)),
+                    "to": namespace(live),
+                    "dropTarget": true,
+                },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(counted)
+}
+
+// Recomputes the rollups from the sessions collection. Days that the retention job has pruned
+// sessions from keep their old rollups, since there's nothing left to recompute them from.
+// Sessions stored while it runs are queued on its lock and counted once the new rollups are in
+// place, so it's safe to run while uploads come in.
+pub async fn rebuild(db: &Database) -> mongodb::error::Result<()> {
+    let locks = db
+        .database
+        .collection::<Document>(&db.collections.migrations);
+
+    // A rebuild that died leaves its lock behind. This one counts what it queued.
+    locks
+        .delete_one(
+            doc! {"_id": REBUILD_LOCK, "HeartbeatAt": {"$lt": rebuild_lock_expired_before()}},
+            None,
+        )
+        .await?;
+    let now = mongodb::bson::DateTime::now();
+    let lock = doc! {"_id": REBUILD_LOCK, "StartedAt": now, "HeartbeatAt": now, "Pending": []};
+    match locks.insert_one(lock, None).await {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key_error(&e) => {
+            return Err(std::io::Error::other("the rollups are already being rebuilt").into())
+        }
+        Err(e) => return Err(e),
+    }
+
+    // Keeps the lock alive for as long as the rebuild runs
+    let heartbeat = {
+        let locks = locks.clone();
+        tokio::task::spawn(async move {
+            loop {
+                tokio::time::sleep(REBUILD_HEARTBEAT_INTERVAL).await;
+                let heartbeat = doc! {"$set": {"HeartbeatAt": mongodb::bson::DateTime::now()}};
+                if let Err(e) = locks
+                    .update_one(doc! {"_id": REBUILD_LOCK}, heartbeat, None)
+                    .await
+                {
+                    eprintln!("Rollup rebuild: failed to refresh its lock! {}", e);
+                }
+            }
+        })
+    };
+
+    let result = rebuild_into_staging(db).await;
+    heartbeat.abort();
+
+    // Once the lock is gone sessions are added to the new rollups directly
+    let pending = locks
+        .find_one_and_delete(doc! {"_id": REBUILD_LOCK}, None)
+        .await?
+        .and_then(|lock| lock.get_array("Pending").ok().cloned())
+        .unwrap_or_default();
+    let sessions = db.database.collection::<Document>(&db.collections.sessions);
+    for id in pending.iter().filter_map(Bson::as_object_id) {
+        // Sessions stored just before the rebuild read them are already counted. When it failed
+        // the old rollups were kept, and they're missing every queued session.
+        if result.as_ref().is_ok_and(|counted| counted.contains(&id)) {
+            continue;
+        }
+        if let Some(session) = sessions.find_one(doc! {"_id": id}, None).await? {
+            count_session(
+                db,
+                &db.collections.daily_rollups,
+                &db.collections.rollup_players,
+                &session,
+            )
+            .await?;
+        }
+    }
+
+    result.map(|_| ())
+}
+
+fn get_number(document: &Document, key: &str) -> i64 {
+    match document.get(key) {
+        Some(Bson::Int32(value)) => *value as i64,
+        Some(Bson::Int64(value)) => *value,
+        Some(Bson::Double(value)) => *value as i64,
+        _ => 0,
+    }
+}
+
+// Totals for the filter. Sessions pruned by the retention job are still counted.
+pub async fn get_totals(
+    db: &Database,
+    filter: &StatsFilter,
+) -> mongodb::error::Result<SessionTotals> {
+    let game = doc! {"$ne": ["$IsPlayInEditorSession", true]};
+    let pipeline = [
+        doc! {"$match": rollup_filter(filter)},
+        doc! {"$facet": {
+            "sessions": [
+                {"$group": {
+                    "_id": null,
+                    "pie_sessions": {"$sum": {"$cond": [game.clone(), 0, "$Sessions"]}},
+                    "game_sessions": {"$sum": {"$cond": [game.clone(), "$Sessions", 0]}},
+                    "timed_sessions": {"$sum": {"$cond": [game.clone(), "$TimedSessions", 0]}},
+                    "play_time_ms": {"$sum": {"$cond": [game, "$PlayTimeMs", 0]}},
+                }},
+            ],
+            "play_time": [
+                {"$match": {"IsPlayInEditorSession": false}},
+                {"$project": {"buckets": {"$objectToArray": {"$ifNull": ["$PlayTimeHistogram", {}]}}}},
+                {"$unwind": "$buckets"},
+                {"$group": {
+                    "_id": "$buckets.k",
+                    "sessions": {"$sum": "$buckets.v.Sessions"},
+                    "play_time_ms": {"$sum": "$buckets.v.PlayTimeMs"},
+                }},
+            ],
+        }},
+    ];
+
+    let result = db
+        .database
+        .collection::<Document>(&db.collections.daily_rollups)
+        .aggregate(pipeline, None)
+        .await?
+        .try_next()
+        .await?
+        .unwrap_or_default();
+
+    let facet = |name: &str| -> Vec<Document> {
+        result
+            .get_array(name)
+            .map(|results| {
+                results
+                    .iter()
+                    .filter_map(|result| result.as_document().cloned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let sessions = facet("sessions").into_iter().next().unwrap_or_default();
+
+    let players_pipeline = [
+        doc! {"$match": rollup_filter(filter)},
+        doc! {"$group": {"_id": "$Player"}},
+        doc! {"$count": "count"},
+    ];
+    let players = db
+        .database
+        .collection::<Document>(&db.collections.rollup_players)
+        .aggregate(players_pipeline, None)
+        .await?
+        .try_next()
+        .await?
+        .unwrap_or_default();
+
+    let buckets = facet("play_time");
+    let play_time_buckets = PLAY_TIME_BUCKETS
+        .iter()
+        .map(|min_secs| {
+            let bucket = buckets
+                .iter()
+                .find(|bucket| bucket.get_str("_id") == Ok(min_secs.to_string().as_str()));
+            PlayTimeBucketTotals {
+                min_secs: *min_secs,
+                sessions: bucket.map_or(0, |bucket| get_number(bucket, "sessions") as u64),
+                play_time_ms: bucket.map_or(0, |bucket| get_number(bucket, "play_time_ms")),
+            }
+        })
+        .collect();
+
+    Ok(SessionTotals {
+        pie_sessions: get_number(&sessions, "pie_sessions") as u64,
+        game_sessions: get_number(&sessions, "game_sessions") as u64,
+        unique_players: get_number(&players, "count") as u64,
+        timed_sessions: get_number(&sessions, "timed_sessions") as u64,
+        play_time_ms: get_number(&sessions, "play_time_ms"),
+        play_time_buckets,
+    })
+}
+
+// The totals get_totals gives for the rollups of `sessions`, worked out without mongo from stored
+// session documents
+pub fn session_totals<'a>(
+    filter: &StatsFilter,
+    sessions: impl IntoIterator<Item = &'a Document>,
+) -> SessionTotals {
+    let mut totals = SessionTotals::default();
+    let mut players = HashSet::new();
+    let mut play_times_ms = Vec::new();
+
+    for (key, counters, ip) in sessions.into_iter().filter_map(session_rollup) {
+        if !rollup_matches(filter, &key) {
+            continue;
+        }
+        let counter = |name: &str| counters.get(name).copied().unwrap_or(0);
+
+        if key.get_bool("IsPlayInEditorSession") == Ok(true) {
+            totals.pie_sessions += counter("Sessions") as u64;
+        } else {
+            totals.game_sessions += counter("Sessions") as u64;
+            if counter("TimedSessions") > 0 {
+                totals.timed_sessions += 1;
+                totals.play_time_ms += counter("PlayTimeMs");
+                play_times_ms.push(counter("PlayTimeMs"));
+            }
+        }
+        players.extend(ip);
+    }
+
+    totals.unique_players = players.len() as u64;
+    totals.play_time_buckets = play_time_buckets(play_times_ms);
+    totals
+}
+
+// The play time distribution of the totals' game sessions. Rollups only know how many sessions
+// each histogram bucket has and how long they lasted altogether, so percentiles are interpolated
+// inside their bucket and partly trimmed buckets count with their average.
+pub fn play_time_distribution(totals: &SessionTotals) -> PlayTimeDistribution {
+    let buckets = &totals.play_time_buckets;
+    let sessions: u64 = buckets.iter().map(|bucket| bucket.sessions).sum();
+    let play_time_ms: i64 = buckets.iter().map(|bucket| bucket.play_time_ms).sum();
+    let average_secs = |bucket: &PlayTimeBucketTotals| {
+        bucket.play_time_ms as f64 / bucket.sessions.max(1) as f64 / 1000.0
+    };
+
+    let histogram = buckets
+        .iter()
+        .enumerate()
+        .map(|(i, bucket)| PlayTimeBucket {
+            min_secs: bucket.min_secs,
+            max_secs: buckets.get(i + 1).map(|next| next.min_secs),
+            count: bucket.sessions,
+        })
+        .collect();
+
+    if sessions == 0 {
+        return PlayTimeDistribution {
+            histogram,
+            ..Default::default()
+        };
+    }
+
+    // Play time of the session with the given rank, counting from 1
+    let at_rank = |rank: u64| {
+        let mut before = 0;
+        for (i, bucket) in buckets.iter().enumerate() {
+            if rank <= before + bucket.sessions {
+                // The last bucket has no upper edge to interpolate to
+                let max_secs = match buckets.get(i + 1) {
+                    Some(next) => next.min_secs as f64,
+                    None => return average_secs(bucket),
+                };
+                let share = (rank - before) as f64 / bucket.sessions as f64;
+                return bucket.min_secs as f64 + share * (max_secs - bucket.min_secs as f64);
+            }
+            before += bucket.sessions;
+        }
+        0.0
+    };
+    let percentiles = PLAY_TIME_PERCENTILES
+        .iter()
+        .map(|p| PlayTimePercentile {
+            percentile: *p,
+            secs: at_rank(((*p as f64 / 100.0) * sessions as f64).ceil().max(1.0) as u64),
+        })
+        .collect();
+
+    // Sessions ranked in (sessions * trim, sessions * (1 - trim)], the same as the pipeline
+    let first = (sessions as f64 * PLAY_TIME_TRIM).floor() as u64;
+    let last = (sessions as f64 * (1.0 - PLAY_TIME_TRIM)).floor() as u64;
+    let mut before = 0;
+    let mut trimmed_sessions = 0;
+    let mut trimmed_secs = 0.0;
+    for bucket in buckets {
+        let kept = (before + bucket.sessions)
+            .min(last)
+            .saturating_sub(before.max(first));
+        if kept == bucket.sessions {
+            trimmed_secs += bucket.play_time_ms as f64 / 1000.0;
+        } else {
+            trimmed_secs += kept as f64 * average_secs(bucket);
+        }
+        trimmed_sessions += kept;
+        before += bucket.sessions;
+    }
+
+    PlayTimeDistribution {
+        sessions,
+        mean_secs: Some(play_time_ms as f64 / sessions as f64 / 1000.0),
+        trimmed_mean_secs: (trimmed_sessions > 0).then(|| trimmed_secs / trimmed_sessions as f64),
+        percentiles,
+        histogram,
+    }
+}
+
+// The rows get_daily gives for the rollups of `sessions`, like session_totals
+pub fn session_daily<'a>(
+    filter: &StatsFilter,
+    sessions: impl IntoIterator<Item = &'a Document>,
+) -> Vec<DailyRollup> {
+    let mut days: BTreeMap<String, (DailyRollup, HashSet<String>)> = BTreeMap::new();
+
+    for (key, counters, ip) in sessions.into_iter().filter_map(session_rollup) {
+        if !rollup_matches(filter, &key) {
+            continue;
+        }
+        let counter = |name: &str| counters.get(name).copied().unwrap_or(0);
+
+        let day = key.get_str("Day").unwrap_or(UNKNOWN).to_string();
+        let (row, players) = days.entry(day.clone()).or_insert_with(|| {
+            let row = DailyRollup {
+                day,
+                pie_sessions: 0,
+                game_sessions: 0,
+                unique_players: 0,
+                play_time_secs: 0.0,
+                feedback_comments: 0,
+            };
+            (row, HashSet::new())
+        });
+        if key.get_bool("IsPlayInEditorSession") == Ok(true) {
+            row.pie_sessions += counter("Sessions") as u64;
+        } else {
+            row.game_sessions += counter("Sessions") as u64;
+            row.play_time_secs += counter("PlayTimeMs") as f64 / 1000.0;
+        }
+        row.feedback_comments += counter("FeedbackComments") as u64;
+        players.extend(ip);
+    }
+
+    days.into_values()
+        .map(|(row, players)| DailyRollup {
+            unique_players: players.len() as u64,
+            ..row
+        })
+        .collect()
+}
+
+// One row per day for the filter
+pub async fn get_daily(
+    db: &Database,
+    filter: &StatsFilter,
+) -> mongodb::error::Result<Vec<DailyRollup>> {
+    let game = doc! {"$ne": ["$IsPlayInEditorSession", true]};
+    let pipeline = [
+        doc! {"$match": rollup_filter(filter)},
+        doc! {"$group": {
+            "_id": "$Day",
+            "pie_sessions": {"$sum": {"$cond": [game.clone(), 0, "$Sessions"]}},
+            "game_sessions": {"$sum": {"$cond": [game.clone(), "$Sessions", 0]}},
+            "play_time_ms": {"$sum": {"$cond": [game, "$PlayTimeMs", 0]}},
+            "feedback_comments": {"$sum": "$FeedbackComments"},
+        }},
+        doc! {"$sort": {"_id": 1}},
+    ];
+    let players_pipeline = [
+        doc! {"$match": rollup_filter(filter)},
+        doc! {"$group": {"_id": {"Day": "$Day", "Player": "$Player"}}},
+        doc! {"$group": {"_id": "$_id.Day", "count": {"$sum": 1}}},
+    ];
+
+    let rows: Vec<Document> = db
+        .database
+        .collection::<Document>(&db.collections.daily_rollups)
+        .aggregate(pipeline, None)
+        .await?
+        .try_collect()
+        .await?;
+    let players: HashMap<String, i64> = db
+        .database
+        .collection::<Document>(&db.collections.rollup_players)
+        .aggregate(players_pipeline, None)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .iter()
+        .filter_map(|day| {
+            Some((
+                day.get_str("_id").ok()?.to_string(),
+                get_number(day, "count"),
+            ))
+        })
+        .collect();
+
+    Ok(rows
+        .iter()
+        .map(|row| DailyRollup {
+            day: row.get_str("_id").unwrap_or_default().to_string(),
+            pie_sessions: get_number(row, "pie_sessions") as u64,
+            game_sessions: get_number(row, "game_sessions") as u64,
+            unique_players: row
+                .get_str("_id")
+                .ok()
+                .and_then(|day| players.get(day))
+                .copied()
+                .unwrap_or(0) as u64,
+            play_time_secs: get_number(row, "play_time_ms") as f64 / 1000.0,
+            feedback_comments: get_number(row, "feedback_comments") as u64,
+        })
+        .collect())
+}
+
+// Entry point for `unreal-analytics-server rebuild-rollups`
+pub async fn run_cli() {
+    let config = crate::config::read_config();
+    if config.storage.backend != crate::config::StorageBackend::Mongo {
+        println!("Nothing to rebuild, the storage backend isn't mongo");
+        return;
+    }
+    let db = crate::database::connect_to_db(&config);
+
+    match rebuild(&db).await {
+        Ok(()) => println!("Rebuilt daily rollups"),
+        Err(e) => {
+            eprintln!("Failed to rebuild daily rollups! Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    match crate::reporting::rebuild_first_seen(&db).await {
+        Ok(()) => println!("Rebuilt first seen days"),
+        Err(e) => {
+            eprintln!("Failed to rebuild first seen days! Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    match crate::reporting::rebuild_player_days(&db).await {
+        Ok(()) => println!("Rebuilt played days"),
+        Err(e) => {
+            eprintln!("Failed to rebuild played days! Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Totals with the given (bucket edge, sessions, play time) and every other bucket empty
+    fn totals(filled: &[(i64, u64, i64)]) -> SessionTotals {
+        let play_time_buckets = PLAY_TIME_BUCKETS
+            .iter()
+            .map(|min_secs| {
+                let (sessions, play_time_ms) = filled
+                    .iter()
+                    .find(|(edge, _, _)| edge == min_secs)
+                    .map_or((0, 0), |(_, sessions, play_time_ms)| {
+                        (*sessions, *play_time_ms)
+                    });
+                PlayTimeBucketTotals {
+                    min_secs: *min_secs,
+                    sessions,
+                    play_time_ms,
+                }
+            })
+            .collect();
+        SessionTotals {
+            play_time_buckets,
+            ..Default::default()
+        }
+    }
+
+    fn percentile_secs(distribution: &PlayTimeDistribution, percentile: u32) -> f64 {
+        distribution
+            .percentiles
+            .iter()
+            .find(|p| p.percentile == percentile)
+            .unwrap()
+            .secs
+    }
+
+    fn date(day: u32, hour: u32) -> Option<chrono::DateTime<chrono::Utc>> {
+        use chrono::TimeZone;
+        Some(
+            chrono::Utc
+                .with_ymd_and_hms(2024, 3, day, hour, 0, 0)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn days_are_widened_to_whole_days() {
+        let filter = StatsFilter {
+            from: date(2, 12),
+            to: date(4, 0),
+            ..Default::default()
+        };
+        let days = ["2024-03-01", "2024-03-02", "2024-03-03", "2024-03-04"];
+        let matches: Vec<bool> = days.iter().map(|day| day_matches(&filter, day)).collect();
+        assert_eq!(matches, [false, true, true, false]);
+
+        // Anything after midnight counts the whole day
+        let filter = StatsFilter {
+            to: date(4, 1),
+            ..filter
+        };
+        assert!(day_matches(&filter, "2024-03-04"));
+        assert!(day_matches(&StatsFilter::default(), UNKNOWN));
+    }
+
+    #[test]
+    fn session_totals_match_the_rollup_filter() {
+        let session = |start_time: &str, pie: bool, country: &str, ip: &str| {
+            let start = chrono::DateTime::parse_from_rfc3339(start_time)
+                .unwrap()
+                .to_utc();
+            doc! {SESSION_COLLECTOR: {
+                "StartTime": start,
+                "EndTime": start + chrono::TimeDelta::minutes(10),
+                "IsPlayInEditorSession": pie,
+                "CountryCode": country,
+                "ip": ip,
+            }}
+        };
+        let sessions = [
+            session("2024-03-01T23:00:00Z", false, "NL", "a"),
+            session("2024-03-02T08:00:00Z", false, "NL", "a"),
+            session("2024-03-02T09:00:00Z", true, "DE", "b"),
+            doc! {SESSION_COLLECTOR: {"IsPlayInEditorSession": false}},
+        ];
+
+        let filter = StatsFilter {
+            from: date(2, 12),
+            ..Default::default()
+        };
+        let totals = session_totals(&filter, &sessions);
+        assert_eq!((totals.game_sessions, totals.pie_sessions), (1, 1));
+        assert_eq!(totals.unique_players, 2);
+        assert_eq!(totals.timed_sessions, 1);
+        assert_eq!(totals.play_time_ms, 600_000);
+        assert_eq!(totals.play_time_buckets[3].sessions, 1);
+
+        let filter = StatsFilter {
+            country: Some("nl".to_string()),
+            ..Default::default()
+        };
+        let totals = session_totals(&filter, &sessions);
+        assert_eq!((totals.game_sessions, totals.pie_sessions), (2, 0));
+        assert_eq!(totals.unique_players, 1);
+    }
+
+    #[test]
+    fn play_time_bucket_picks_the_edge_below() {
+        assert_eq!(play_time_bucket(-5), 0);
+        assert_eq!(play_time_bucket(59_999), 0);
+        assert_eq!(play_time_bucket(60_000), 60);
+        assert_eq!(play_time_bucket(100 * 3600 * 1000), 28800);
+    }
+
+    #[test]
+    fn play_time_buckets_leave_out_negative_play_times() {
+        let buckets = play_time_buckets([30_000, 45_000, 90_000, -1_000]);
+        assert_eq!(buckets.len(), PLAY_TIME_BUCKETS.len());
+        assert_eq!((buckets[0].sessions, buckets[0].play_time_ms), (2, 75_000));
+        assert_eq!((buckets[1].sessions, buckets[1].play_time_ms), (1, 90_000));
+        assert_eq!(buckets.iter().map(|b| b.sessions).sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn no_sessions_still_has_the_histogram() {
+        let distribution = play_time_distribution(&totals(&[]));
+        assert_eq!(distribution.sessions, 0);
+        assert_eq!(distribution.mean_secs, None);
+        assert_eq!(distribution.trimmed_mean_secs, None);
+        assert!(distribution.percentiles.is_empty());
+        assert_eq!(distribution.histogram.len(), PLAY_TIME_BUCKETS.len());
+        assert!(distribution
+            .histogram
+            .iter()
+            .all(|bucket| bucket.count == 0));
+        assert_eq!(distribution.histogram.last().unwrap().max_secs, None);
+    }
+
+    #[test]
+    fn percentiles_are_interpolated_inside_their_bucket() {
+        // 10 sessions between 1 and 5 minutes, 25 minutes altogether
+        let distribution = play_time_distribution(&totals(&[(60, 10, 1_500_000)]));
+        assert_eq!(distribution.sessions, 10);
+        assert_eq!(distribution.mean_secs, Some(150.0));
+        // Rank 3 of 10 is 3/10 of the way from 60 to 300
+        assert_eq!(percentile_secs(&distribution, 25), 132.0);
+        assert_eq!(percentile_secs(&distribution, 50), 180.0);
+        assert_eq!(percentile_secs(&distribution, 99), 300.0);
+        // The longest session is trimmed, the rest count with the bucket's average
+        assert_eq!(distribution.trimmed_mean_secs, Some(150.0));
+    }
+
+    #[test]
+    fn empty_buckets_are_skipped_and_the_last_uses_its_average() {
+        let distribution =
+            play_time_distribution(&totals(&[(0, 1, 30_000), (28800, 1, 36_000_000)]));
+        assert_eq!(distribution.sessions, 2);
+        assert_eq!(distribution.mean_secs, Some(18_015.0));
+        assert_eq!(percentile_secs(&distribution, 50), 60.0);
+        assert_eq!(percentile_secs(&distribution, 75), 36_000.0);
+        assert_eq!(distribution.trimmed_mean_secs, Some(30.0));
+        let counts: Vec<u64> = distribution.histogram.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [1, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::auth::ApiKey;
+use crate::build_info::BuildInfo;
+use crate::cloudflare;
+use crate::database::AddSessionResult;
+use crate::routes::session_upload::try_spawn_discord_message_task;
+use crate::session::{AnalyticsSession, FieldError};
+use crate::ServerState;
+
+use rocket::data::{self, ByteUnit, Data, FromData, ToByteUnit};
+use rocket::request::Request;
+use rocket::serde::json::{serde_json, Json, Value};
+use rocket::State;
+use rocket::{http::Status, post};
+use serde::Serialize;
+
+#[derive(Debug)]
+pub enum SessionBatchError {
+    TooLarge,
+    Io(std::io::Error),
+    UnsupportedEncoding(String),
+    Malformed(String),
+}
+
+// The sessions inside of a batch upload body. Each item is either the session's json or the
+// reason it couldn't be parsed, so that one bad line doesn't throw away the rest of the batch.
+pub struct SessionBatch(pub Vec<Result<Value, String>>);
+
+// Decompresses the body according to its Content-Encoding header. The decompressed size is capped
+// at `limit` as well so a small compressed body can't expand into something huge.
+fn decode_body(
+    encoding: Option<&str>,
+    body: Vec<u8>,
+    limit: ByteUnit,
+) -> Result<Vec<u8>, SessionBatchError> {
+    let mut decoder: Box<dyn Read> = match encoding.map(|e| e.trim().to_ascii_lowercase()) {
+        None => return Ok(body),
+        Some(encoding) => match encoding.as_str() {
+            "" | "identity" => return Ok(body),
+            "gzip" | "x-gzip" => Box::new(flate2::read::GzDecoder::new(body.as_slice())),
+            "zstd" => Box::new(
+                zstd::stream::read::Decoder::new(body.as_slice()).map_err(SessionBatchError::Io)?,
+            ),
+            _ => return Err(SessionBatchError::UnsupportedEncoding(encoding)),
+        },
+    };
+
+    let mut decoded = Vec::new();
+    decoder
+        .by_ref()
+        .take(limit.as_u64() + 1)
+        .read_to_end(&mut decoded)
+        .map_err(SessionBatchError::Io)?;
+
+    if decoded.len() as u64 > limit.as_u64() {
+        return Err(SessionBatchError::TooLarge);
+    }
+
+    Ok(decoded)
+}
+
+// Splits the body into session values. A body starting with `[` is a json array of sessions,
+// anything else is treated as newline delimited json with one session per line.
+fn split_sessions(body: &[u8]) -> Result<Vec<Result<Value, String>>, SessionBatchError> {
+    let body = std::str::from_utf8(body)
+        .map_err(|e| SessionBatchError::Malformed(format!("body is not valid utf-8: {}", e)))?;
+
+    if body.trim_start().starts_with('[') {
+        let sessions: Vec<Value> = serde_json::from_str(body)
+            .map_err(|e| SessionBatchError::Malformed(format!("invalid json array: {}", e)))?;
+
+        return Ok(sessions.into_iter().map(Ok).collect());
+    }
+
+    Ok(body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<Value>(line)
+                .map_err(|e| format!("line {}: invalid json: {}", i + 1, e))
+        })
+        .collect())
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for SessionBatch {
+    type Error = SessionBatchError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        use rocket::outcome::Outcome::*;
+
+        let limit = req.limits().get("batch").unwrap_or(16.mebibytes());
+
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return Error((Status::PayloadTooLarge, SessionBatchError::TooLarge)),
+            Err(e) => return Error((Status::InternalServerError, SessionBatchError::Io(e))),
+        };
+
+        let body = match decode_body(req.headers().get_one("Content-Encoding"), body, limit) {
+            Ok(body) => body,
+            Err(SessionBatchError::TooLarge) => {
+                return Error((Status::PayloadTooLarge, SessionBatchError::TooLarge))
+            }
+            Err(SessionBatchError::UnsupportedEncoding(encoding)) => {
+                return Error((
+                    Status::UnsupportedMediaType,
+                    SessionBatchError::UnsupportedEncoding(encoding),
+                ))
+            }
+            Err(e) => return Error((Status::BadRequest, e)),
+        };
+
+        match split_sessions(&body) {
+            Ok(sessions) => Success(SessionBatch(sessions)),
+            Err(e) => Error((Status::BadRequest, e)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchItemStatus {
+    // Stored, don't send it again
+    Inserted,
+    // Was already stored by an earlier upload, don't send it again
+    Duplicate,
+    // Only counted since the player turned telemetry off, don't send it again
+    Anonymous,
+    // The session will never be accepted, retrying won't help
+    Invalid,
+    // Something went wrong on the server, the session should be retried later
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub status: BatchItemStatus,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchUploadResult {
+    pub results: Vec<BatchItemResult>,
+}
+
+impl BatchItemResult {
+    fn new(index: usize, status: BatchItemStatus) -> Self {
+        BatchItemResult {
+            index,
+            status,
+            id: None,
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[post("/batch", data = "<batch>")]
+pub async fn upload_session_batch(
+    _key: ApiKey,
+    cloudflare_info: cloudflare::CloudflareInfo,
+    build_info: BuildInfo,
+    batch: SessionBatch,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<BatchUploadResult>, Status> {
+    let ip_policy = state.ip_policy();
+    let mut results = Vec::with_capacity(batch.0.len());
+
+    // Parse and validate every item, keeping track of which result each valid session belongs to
+    let mut sessions = Vec::new();
+    let mut session_indices = Vec::new();
+    for (index, item) in batch.0.into_iter().enumerate() {
+        let mut result = BatchItemResult::new(index, BatchItemStatus::Invalid);
+
+        match item
+            .map_err(|e| vec![FieldError::new("", e)])
+            .and_then(|value| AnalyticsSession::from_value(value).map_err(|errors| errors.errors))
+        {
+            Ok(mut session) => {
+                // Some builds send their build details in headers instead of the session. Fill
+                // those in first, since the anonymous count keeps them too.
+                session.set_build_info(&build_info);
+                // Only the collector's SessionID can identify a session inside of a batch. Set
+                // before counting, so a retry of an anonymous session isn't counted twice.
+                session.set_idempotency_key(None);
+
+                match state.storage.count_if_anonymous(&session).await {
+                    Ok(true) => result.status = BatchItemStatus::Anonymous,
+                    Ok(false) => {
+                        session.set_cloudflare_info(&cloudflare_info, &ip_policy);
+                        state.scrubber.scrub_session(&mut session);
+                        session_indices.push(results.len());
+                        sessions.push(session);
+                    }
+                    Err(e) => {
+                        result.status = BatchItemStatus::Failed;
+                        result.errors = vec![FieldError::new("", e.to_string())];
+                    }
+                }
+            }
+            Err(errors) => result.errors = errors,
+        }
+
+        results.push(result);
+    }
+
+    if sessions.is_empty() {
+        return Ok(Json(BatchUploadResult { results }));
+    }
+
+    let inserted = state.storage.add_sessions(&sessions).await.map_err(|e| {
+        eprintln!("Failed to insert session batch into database! Error: {}", e);
+        Status::InternalServerError
+    })?;
+
+    for ((session, result_index), insert_result) in
+        sessions.into_iter().zip(session_indices).zip(inserted)
+    {
+        let result = &mut results[result_index];
+        match insert_result {
+            Ok(AddSessionResult::Inserted(id)) => {
+                result.status = BatchItemStatus::Inserted;
+                result.id = Some(id.to_hex());
+
+                if let Some(session_id) = &session.collector.session_id {
+                    if let Err(e) = state.storage.end_live_session(session_id).await {
+                        eprintln!("Failed to end live session! Error: {}", e);
+                    }
+                }
+
+                try_spawn_discord_message_task(state, session, id);
+            }
+            Ok(AddSessionResult::Duplicate(id)) => {
+                result.status = BatchItemStatus::Duplicate;
+                result.id = Some(id.to_hex());
+            }
+            Err(e) => {
+                result.status = BatchItemStatus::Failed;
+                result.errors = vec![FieldError::new("", e)];
+            }
+        }
+    }
+
+    Ok(Json(BatchUploadResult { results }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decodes_supported_encodings() {
+        let body = b"{\"a\": 1}\n".to_vec();
+        let limit = 1.kibibytes();
+
+        for encoding in [None, Some(""), Some("identity")] {
+            assert_eq!(decode_body(encoding, body.clone(), limit).unwrap(), body);
+        }
+        assert_eq!(
+            decode_body(Some(" GZIP "), gzip(&body), limit).unwrap(),
+            body
+        );
+        let zstd = zstd::encode_all(body.as_slice(), 0).unwrap();
+        assert_eq!(decode_body(Some("zstd"), zstd, limit).unwrap(), body);
+
+        assert!(matches!(
+            decode_body(Some("br"), body, limit),
+            Err(SessionBatchError::UnsupportedEncoding(encoding)) if encoding == "br"
+        ));
+        assert!(matches!(
+            decode_body(Some("gzip"), b"not gzip".to_vec(), limit),
+            Err(SessionBatchError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn decoded_size_is_capped() {
+        let limit = 1.kibibytes();
+        let at_limit = vec![b' '; limit.as_u64() as usize];
+        assert_eq!(
+            decode_body(Some("gzip"), gzip(&at_limit), limit).unwrap(),
+            at_limit
+        );
+
+        // A megabyte of zeros compresses to about a kilobyte
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(matches!(
+            decode_body(Some("gzip"), bomb, limit),
+            Err(SessionBatchError::TooLarge)
+        ));
+        let bomb = zstd::encode_all(vec![0; 1024 * 1024].as_slice(), 0).unwrap();
+        assert!(matches!(
+            decode_body(Some("zstd"), bomb, limit),
+            Err(SessionBatchError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn splits_arrays_and_lines() {
+        let sessions = split_sessions(b" [{\"a\": 1}, {\"a\": 2}]").unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(Result::is_ok));
+        assert!(matches!(
+            split_sessions(b"[{\"a\": 1},"),
+            Err(SessionBatchError::Malformed(_))
+        ));
+
+        let sessions = split_sessions(b"{\"a\": 1}\n\n  \n{\"a\":\n{\"a\": 3}\r\n").unwrap();
+        assert_eq!(sessions.len(), 3);
+        assert!(sessions[0].is_ok());
+        assert!(sessions[1].as_ref().unwrap_err().starts_with("line 4:"));
+        assert!(sessions[2].is_ok());
+
+        assert!(matches!(
+            split_sessions(&[0xff, 0xfe]),
+            Err(SessionBatchError::Malformed(_))
+        ));
+    }
+}
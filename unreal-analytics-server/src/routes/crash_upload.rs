@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use crate::auth::{AdminKey, ApiKey};
+use crate::cloudflare;
+use crate::crash::{self, AttachmentKind, CrashContext};
+use crate::database::CrashGroup;
+use crate::gitlab::{self, GitlabIssue};
+use crate::opt_outs;
+use crate::routes::feedback::gitlab_error_status;
+use crate::storage::Storage;
+use crate::ServerState;
+
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket::{get, http::Status, post, FromForm};
+use serde::Serialize;
+
+// Multipart body sent by the crash reporter. This isn't what the stock CrashReportClient sends, it
+// posts a compressed blob of every file to its DataRouterUrl. The game's crash handler, or a small
+// relay in front of the server, has to unpack the report and post the files under these names:
+// `context` for CrashContext.runtime-xml (Rocket treats dots in field names as nesting), `log` for
+// the game's log and `minidump` for UEMinidump.dmp.
+#[derive(FromForm)]
+pub struct CrashUpload<'r> {
+    #[field(name = "NetID")]
+    pub net_id: Option<String>,
+    #[field(name = "SessionID")]
+    pub session_id: Option<String>,
+    // False when the player turned telemetry off, older builds don't send it
+    #[field(name = "TelemetryConsent")]
+    pub telemetry_consent: Option<bool>,
+    pub context: TempFile<'r>,
+    pub log: Option<TempFile<'r>>,
+    pub minidump: Option<TempFile<'r>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CrashUploadResult {
+    pub id: String,
+    pub signature: String,
+    // How many times this crash has been seen, including this one
+    pub occurrences: i64,
+}
+
+// Removes the attachments of a crash that couldn't be stored, so they aren't left behind
+async fn discard_attachments(storage: &dyn Storage, attachments: Vec<Document>) {
+    let crash = doc! {"Attachments": attachments};
+    if let Err(e) = storage.delete_crash_attachments(&crash).await {
+        eprintln!("Crash: failed to remove orphaned attachments! Error: {}", e);
+    }
+}
+
+#[post("/crash", data = "<upload>")]
+pub async fn upload_crash(
+    _key: ApiKey,
+    cloudflare_info: cloudflare::CloudflareInfo,
+    upload: Form<CrashUpload<'_>>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<CrashUploadResult>, Status> {
+    let mut upload = upload.into_inner();
+
+    let config = state
+        .read_config()
+        .ok_or(Status::InternalServerError)?
+        .crash_reports;
+
+    let context_xml = crash::read_temp_file(&upload.context).await.map_err(|e| {
+        eprintln!("Crash: failed to read crash context! Error: {}", e);
+        Status::InternalServerError
+    })?;
+    let context_xml = String::from_utf8_lossy(&context_xml);
+    let context = CrashContext::parse(&context_xml).map_err(|e| {
+        eprintln!("Crash: failed to parse crash context! Error: {}", e);
+        Status::UnprocessableEntity
+    })?;
+
+    // Crashes usually come from sessions that never got to upload, so fall back to the live session
+    let mut net_id = upload.net_id.clone();
+    if let (None, Some(session_id)) = (&net_id, &upload.session_id) {
+        net_id = state
+            .storage
+            .find_net_id_for_session(session_id)
+            .await
+            .map_err(|e| {
+                eprintln!("Crash: failed to look up session! Error: {}", e);
+                Status::InternalServerError
+            })?;
+    }
+
+    // Crashes are still wanted from players that turned telemetry off, just nothing that could tell
+    // who they were. The log and minidump can, so no attachments are kept either.
+    let net_ids: Vec<&str> = net_id.as_deref().into_iter().collect();
+    let opted_out = state.storage.opted_out(&net_ids).await.map_err(|e| {
+        eprintln!("Crash: failed to look up opt outs! Error: {}", e);
+        Status::InternalServerError
+    })?;
+    let is_anonymous =
+        !opt_outs::may_store(upload.telemetry_consent, net_id.as_deref(), &opted_out);
+    if is_anonymous {
+        net_id = None;
+    }
+
+    let crash_id = ObjectId::new();
+    let mut attachments = Vec::new();
+    let files: [(AttachmentKind, Option<&mut TempFile<'_>>); 3] = [
+        (AttachmentKind::Context, Some(&mut upload.context)),
+        (AttachmentKind::Log, upload.log.as_mut()),
+        (AttachmentKind::Minidump, upload.minidump.as_mut()),
+    ];
+    for (kind, file) in files {
+        if let Some(file) = file.filter(|_| !is_anonymous) {
+            match state
+                .storage
+                .store_crash_attachment(&config, &crash_id, kind, file)
+                .await
+            {
+                Ok(attachment) => attachments.push(attachment),
+                Err(e) => {
+                    eprintln!("Crash: failed to store {}! Error: {}", kind.as_str(), e);
+                    discard_attachments(state.storage.as_ref(), attachments).await;
+                    return Err(Status::InternalServerError);
+                }
+            }
+        }
+    }
+
+    let signature = context.signature();
+    let mut crash = mongodb::bson::to_document(&context).map_err(|e| {
+        eprintln!("Crash: failed to serialize crash context! Error: {}", e);
+        Status::InternalServerError
+    })?;
+    crash.extend(doc! {
+        "_id": crash_id,
+        "Signature": &signature,
+        "SignatureFrames": context.signature_frames(),
+        "NetID": net_id,
+        "SessionID": upload.session_id.clone().filter(|_| !is_anonymous),
+        "ReceivedTime": mongodb::bson::DateTime::now(),
+        "CountryCode": (!is_anonymous).then(|| cloudflare_info.country.clone()),
+        "Attachments": attachments.clone(),
+    });
+
+    let group = match state.storage.add_crash(crash).await {
+        Ok(group) => group,
+        Err(e) => {
+            eprintln!("Crash: failed to insert crash into database! Error: {}", e);
+            discard_attachments(state.storage.as_ref(), attachments).await;
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    Ok(Json(CrashUploadResult {
+        id: crash_id.to_hex(),
+        signature,
+        occurrences: group.count,
+    }))
+}
+
+// Lists crash groups with the most frequent first
+#[get("/crashes?<limit>")]
+pub async fn get_crash_groups(
+    _key: ApiKey,
+    limit: Option<i64>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<CrashGroup>>, Status> {
+    let groups = state
+        .storage
+        .get_crash_groups(limit.unwrap_or(50).clamp(1, 500))
+        .await
+        .map_err(|e| {
+            eprintln!("Crash: failed to read crash groups! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(groups))
+}
+
+// Files a crash group as a GitLab issue. Filing the same group twice returns the first issue.
+#[post("/crashes/<signature>/gitlab_issue")]
+pub async fn create_crash_gitlab_issue(
+    _key: AdminKey,
+    signature: &str,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<GitlabIssue>, Status> {
+    let issue = gitlab::file_crash_group(state, signature)
+        .await
+        .map_err(gitlab_error_status)?;
+
+    issue.map(Json).ok_or(Status::NotFound)
+}
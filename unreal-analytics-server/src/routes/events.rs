@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use crate::auth::ApiKey;
+use crate::database::{EventCount, EventGrouping, EventQuery};
+use crate::funnel::{FunnelQuery, FunnelStep, FunnelStepResult};
+use crate::gameplay_event::{is_valid_property_name, GameplayEvent};
+use crate::opt_outs;
+use crate::routes::session_upload::SessionUploadError;
+use crate::session::ValidationErrors;
+use crate::ServerState;
+
+use rocket::serde::json::{Json, Value};
+use rocket::State;
+use rocket::{get, http::Status, post, FromForm};
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct EventUploadResult {
+    pub inserted: u64,
+}
+
+// Accepts a single event or an array of them. Nothing is stored unless every event is valid.
+#[post("/events", data = "<events>")]
+pub async fn upload_events(
+    _key: ApiKey,
+    events: Json<Value>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<EventUploadResult>, SessionUploadError> {
+    let values = match events.into_inner() {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    let mut parsed = Vec::with_capacity(values.len());
+    let mut errors = Vec::new();
+    for (i, value) in values.into_iter().enumerate() {
+        match GameplayEvent::from_value(value, &format!("[{}]", i)) {
+            Ok(event) => parsed.push(event),
+            Err(event_errors) => errors.extend(event_errors),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(SessionUploadError::Invalid(Json(ValidationErrors {
+            errors,
+        })));
+    }
+
+    // Events from players that turned telemetry off are dropped
+    let net_ids: Vec<&str> = parsed.iter().filter_map(|e| e.net_id.as_deref()).collect();
+    let opted_out = state.storage.opted_out(&net_ids).await.map_err(|e| {
+        eprintln!("Failed to look up opt outs! Error: {}", e);
+        SessionUploadError::Failed(Status::InternalServerError)
+    })?;
+    parsed.retain(|e| opt_outs::may_store(e.telemetry_consent, e.net_id.as_deref(), &opted_out));
+
+    if parsed.is_empty() {
+        return Ok(Json(EventUploadResult { inserted: 0 }));
+    }
+
+    let inserted = state.storage.add_events(&parsed).await.map_err(|e| {
+        eprintln!("Failed to insert events into database! Error: {}", e);
+        SessionUploadError::Failed(Status::InternalServerError)
+    })?;
+
+    Ok(Json(EventUploadResult { inserted }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct EventCountsResult {
+    pub total: u64,
+    pub groups: Vec<EventCount>,
+}
+
+// Builds an event query from the common query parameters. Dates are RFC 3339 or YYYY-MM-DD.
+pub fn parse_event_query(
+    name: Option<String>,
+    build: Option<String>,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<EventQuery, Status> {
+    let parse_date = |date: Option<&str>| match date {
+        Some(date) => crate::utils::parse_date_param(date)
+            .map(Some)
+            .ok_or(Status::BadRequest),
+        None => Ok(None),
+    };
+
+    Ok(EventQuery {
+        name,
+        build,
+        from: parse_date(from)?,
+        to: parse_date(to)?,
+    })
+}
+
+// Query parameters of the event counts route
+#[derive(FromForm, Debug)]
+pub struct EventCountsQuery {
+    pub name: Option<String>,
+    pub build: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub group_by: Option<String>,
+    pub property: Option<String>,
+}
+
+// Counts events, grouped by `name` (the default), `build` or `property` (needs `property` to be set)
+#[get("/events/counts?<counts..>")]
+pub async fn get_event_counts(
+    _key: ApiKey,
+    counts: EventCountsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<EventCountsResult>, Status> {
+    let query = parse_event_query(
+        counts.name,
+        counts.build,
+        counts.from.as_deref(),
+        counts.to.as_deref(),
+    )?;
+
+    let grouping = match (
+        counts.group_by.as_deref().unwrap_or("name"),
+        counts.property,
+    ) {
+        ("name", _) => EventGrouping::Name,
+        ("build", _) => EventGrouping::Build,
+        ("property", Some(property)) if is_valid_property_name(&property) => {
+            EventGrouping::Property(property)
+        }
+        _ => return Err(Status::BadRequest),
+    };
+
+    let db_error = |e: mongodb::error::Error| {
+        eprintln!("Failed to count events! Error: {}", e);
+        Status::InternalServerError
+    };
+
+    let total = state.storage.count_events(&query).await.map_err(db_error)?;
+    let groups = state
+        .storage
+        .group_events(&query, &grouping)
+        .await
+        .map_err(db_error)?;
+
+    Ok(Json(EventCountsResult { total, groups }))
+}
+
+// Players that got through each step of a funnel, in order. `steps` is a comma separated list of
+// "EventName" or "EventName:Property=Value". With `window_secs` every step has to happen within
+// that long of the first one.
+#[get("/events/funnel?<steps>&<build>&<from>&<to>&<window_secs>")]
+pub async fn get_funnel(
+    _key: ApiKey,
+    steps: &str,
+    build: Option<String>,
+    from: Option<&str>,
+    to: Option<&str>,
+    window_secs: Option<u32>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<FunnelStepResult>>, Status> {
+    let steps = FunnelStep::parse_list(steps).map_err(|_| Status::BadRequest)?;
+    let query = FunnelQuery {
+        steps,
+        events: parse_event_query(None, build, from, to)?,
+        window: window_secs.map(|secs| chrono::Duration::seconds(secs as i64)),
+    };
+
+    let funnel = state.storage.get_funnel(&query).await.map_err(|e| {
+        eprintln!("Failed to compute funnel! Error: {}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(funnel))
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use crate::auth::AdminKey;
+use crate::feedback::{FeedbackItem, FeedbackQuery, FeedbackStatus, FeedbackUpdate};
+use crate::gitlab::{self, GitlabError, GitlabIssue};
+use crate::ServerState;
+
+use mongodb::bson::oid::ObjectId;
+use rocket::State;
+use rocket::{get, http::Status, patch, post, serde::json::Json, FromForm};
+
+// Query parameters of the feedback list route
+#[derive(FromForm, Debug)]
+pub struct FeedbackListQuery {
+    pub status: Option<String>,
+    pub tag: Option<String>,
+    pub assignee: Option<String>,
+    pub search: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
+
+// Lists feedback comments, newest first. `search` matches part of the comment.
+#[get("/feedback?<list..>")]
+pub async fn get_feedback(
+    _key: AdminKey,
+    list: FeedbackListQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<FeedbackItem>>, Status> {
+    let status = match list.status {
+        Some(status) => Some(FeedbackStatus::parse(&status).ok_or(Status::BadRequest)?),
+        None => None,
+    };
+    let query = FeedbackQuery {
+        status,
+        tag: list.tag,
+        assignee: list.assignee,
+        search: list.search,
+    };
+
+    let feedback = state
+        .storage
+        .get_feedback(
+            &query,
+            list.limit.unwrap_or(50).clamp(1, 500),
+            list.skip.unwrap_or(0),
+        )
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read feedback! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(feedback))
+}
+
+// Changes the status, tags or assignee of a feedback comment
+#[patch("/feedback/<id>", data = "<update>")]
+pub async fn update_feedback(
+    _key: AdminKey,
+    id: &str,
+    update: Json<FeedbackUpdate>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<FeedbackItem>, Status> {
+    let id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+
+    let feedback = state
+        .storage
+        .update_feedback(id, &update)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to update feedback! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    feedback.map(Json).ok_or(Status::NotFound)
+}
+
+// Maps a failed attempt at filing an issue to a response status
+pub fn gitlab_error_status(e: GitlabError) -> Status {
+    eprintln!("Failed to create GitLab issue! Error: {}", e);
+    match e {
+        GitlabError::NotConfigured => Status::ServiceUnavailable,
+        GitlabError::Database(_) => Status::InternalServerError,
+        GitlabError::Http(_) | GitlabError::Api(..) => Status::BadGateway,
+        GitlabError::InProgress => Status::Conflict,
+    }
+}
+
+// Files a feedback comment as a GitLab issue. Filing the same comment twice returns the first issue.
+#[post("/feedback/<id>/gitlab_issue")]
+pub async fn create_feedback_gitlab_issue(
+    _key: AdminKey,
+    id: &str,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<GitlabIssue>, Status> {
+    let id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+
+    let issue = gitlab::file_feedback(state, id)
+        .await
+        .map_err(gitlab_error_status)?;
+
+    issue.map(Json).ok_or(Status::NotFound)
+}
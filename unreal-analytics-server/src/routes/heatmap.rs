@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use crate::auth::ApiKey;
+use crate::heatmap::{HeatmapGrid, PositionSample};
+use crate::opt_outs;
+use crate::routes::session_upload::SessionUploadError;
+use crate::session::{FieldError, ValidationErrors};
+use crate::ServerState;
+
+use rocket::http::ContentType;
+use rocket::serde::json::{Json, Value};
+use rocket::State;
+use rocket::{get, http::Status, post, Responder};
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct PositionUploadResult {
+    pub inserted: u64,
+}
+
+// Accepts a single position sample or an array of them. Nothing is stored unless every sample is
+// valid.
+#[post("/telemetry/positions", data = "<samples>")]
+pub async fn upload_positions(
+    _key: ApiKey,
+    samples: Json<Value>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<PositionUploadResult>, SessionUploadError> {
+    let values = match samples.into_inner() {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    let mut parsed = Vec::with_capacity(values.len());
+    let mut errors = Vec::new();
+    for (i, value) in values.into_iter().enumerate() {
+        let field_prefix = format!("[{}]", i);
+        match serde_path_to_error::deserialize::<_, PositionSample>(value) {
+            Ok(sample) => {
+                errors.extend(sample.validate(&field_prefix));
+                parsed.push(sample);
+            }
+            Err(err) => {
+                let field = match err.path().to_string().as_str() {
+                    "." => field_prefix,
+                    path => format!("{}.{}", field_prefix, path),
+                };
+                errors.push(FieldError::new(field, err.into_inner().to_string()));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(SessionUploadError::Invalid(Json(ValidationErrors {
+            errors,
+        })));
+    }
+
+    // Positions of players that turned telemetry off are dropped
+    let net_ids: Vec<&str> = parsed.iter().filter_map(|s| s.net_id.as_deref()).collect();
+    let opted_out = state.storage.opted_out(&net_ids).await.map_err(|e| {
+        eprintln!("Failed to look up opt outs! Error: {}", e);
+        SessionUploadError::Failed(Status::InternalServerError)
+    })?;
+    parsed.retain(|s| opt_outs::may_store(s.telemetry_consent, s.net_id.as_deref(), &opted_out));
+
+    if parsed.is_empty() {
+        return Ok(Json(PositionUploadResult { inserted: 0 }));
+    }
+
+    let inserted = state.storage.add_positions(&parsed).await.map_err(|e| {
+        eprintln!("Failed to insert positions into database! Error: {}", e);
+        SessionUploadError::Failed(Status::InternalServerError)
+    })?;
+
+    Ok(Json(PositionUploadResult { inserted }))
+}
+
+#[derive(Responder)]
+pub enum HeatmapResponse {
+    Png((ContentType, Vec<u8>)),
+    Json(Json<HeatmapGrid>),
+}
+
+// Bins the positions recorded on a map into a grid. Returns a png by default, or the raw grid
+// with `format=json`.
+#[get("/heatmap/<map>?<event_type>&<format>&<resolution>")]
+pub async fn get_heatmap(
+    _key: ApiKey,
+    map: &str,
+    event_type: Option<String>,
+    format: Option<&str>,
+    resolution: Option<u32>,
+    state: &State<Arc<ServerState>>,
+) -> Result<HeatmapResponse, Status> {
+    let config = state
+        .read_config()
+        .ok_or(Status::InternalServerError)?
+        .heatmaps;
+
+    let db_error = |e: mongodb::error::Error| {
+        eprintln!("Failed to build heatmap! Error: {}", e);
+        Status::InternalServerError
+    };
+
+    let bounds = match config.maps.get(map) {
+        Some(bounds) => *bounds,
+        None => state
+            .storage
+            .get_position_bounds(map, event_type.as_deref())
+            .await
+            .map_err(db_error)?
+            .ok_or(Status::NotFound)?,
+    };
+
+    let mut grid = HeatmapGrid::new(
+        map.to_string(),
+        event_type.clone(),
+        bounds,
+        resolution.unwrap_or(config.resolution),
+    );
+    state
+        .storage
+        .bin_positions(&mut grid, event_type.as_deref())
+        .await
+        .map_err(db_error)?;
+
+    match format.unwrap_or("png") {
+        "png" => {
+            let png = grid.to_png().map_err(|e| {
+                eprintln!("Failed to encode heatmap png! Error: {}", e);
+                Status::InternalServerError
+            })?;
+
+            Ok(HeatmapResponse::Png((ContentType::PNG, png)))
+        }
+        "json" => Ok(HeatmapResponse::Json(Json(grid))),
+        _ => Err(Status::BadRequest),
+    }
+}
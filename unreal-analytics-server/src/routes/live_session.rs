@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use crate::auth::ApiKey;
+use crate::build_info::BuildInfo;
+use crate::cloudflare;
+use crate::opt_outs;
+use crate::routes::session_upload::SessionUploadError;
+use crate::session::{LiveSessionEvent, ValidationErrors};
+use crate::ServerState;
+
+use rocket::State;
+use rocket::{http::Status, post, serde::json::Json};
+
+fn validate_event(event: &LiveSessionEvent) -> Result<(), SessionUploadError> {
+    let errors = event.validate();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(SessionUploadError::Invalid(Json(ValidationErrors {
+            errors,
+        })))
+    }
+}
+
+async fn touch_live_session(
+    state: &ServerState,
+    cloudflare_info: &cloudflare::CloudflareInfo,
+    build_info: &BuildInfo,
+    event: Json<LiveSessionEvent>,
+) -> Result<String, SessionUploadError> {
+    let mut event = event.into_inner();
+    validate_event(&event)?;
+    event.set_build_info(build_info);
+
+    // Players that turned telemetry off don't show up as live either
+    let net_ids: Vec<&str> = event.net_id.as_deref().into_iter().collect();
+    let opted_out = state.storage.opted_out(&net_ids).await.map_err(|e| {
+        eprintln!("Failed to look up opt outs! Error: {}", e);
+        SessionUploadError::Failed(Status::InternalServerError)
+    })?;
+    if !opt_outs::may_store(event.telemetry_consent, event.net_id.as_deref(), &opted_out) {
+        return Ok("".to_string());
+    }
+
+    match state
+        .storage
+        .touch_live_session(&event, cloudflare_info)
+        .await
+    {
+        Ok(()) => Ok("".to_string()),
+        Err(e) => {
+            eprintln!("Failed to update live session in database! Error: {}", e);
+            Err(SessionUploadError::Failed(Status::InternalServerError))
+        }
+    }
+}
+
+// Sent by the game when a session starts
+#[post("/session/start", data = "<event>")]
+pub async fn start_session(
+    _key: ApiKey,
+    cloudflare_info: cloudflare::CloudflareInfo,
+    build_info: BuildInfo,
+    event: Json<LiveSessionEvent>,
+    state: &State<Arc<ServerState>>,
+) -> Result<String, SessionUploadError> {
+    touch_live_session(state, &cloudflare_info, &build_info, event).await
+}
+
+// Sent by the game every so often while the session is running
+#[post("/session/heartbeat", data = "<event>")]
+pub async fn session_heartbeat(
+    _key: ApiKey,
+    cloudflare_info: cloudflare::CloudflareInfo,
+    build_info: BuildInfo,
+    event: Json<LiveSessionEvent>,
+    state: &State<Arc<ServerState>>,
+) -> Result<String, SessionUploadError> {
+    touch_live_session(state, &cloudflare_info, &build_info, event).await
+}
+
+// Sent by the game when the session ends normally
+#[post("/session/end", data = "<event>")]
+pub async fn end_session(
+    _key: ApiKey,
+    event: Json<LiveSessionEvent>,
+    state: &State<Arc<ServerState>>,
+) -> Result<String, SessionUploadError> {
+    validate_event(&event)?;
+
+    match state.storage.end_live_session(&event.session_id).await {
+        Ok(true) => Ok("".to_string()),
+        Ok(false) => Err(SessionUploadError::Failed(Status::NotFound)),
+        Err(e) => {
+            eprintln!("Failed to end live session in database! Error: {}", e);
+            Err(SessionUploadError::Failed(Status::InternalServerError))
+        }
+    }
+}
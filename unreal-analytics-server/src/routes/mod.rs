@@ -1,2 +1,12 @@
+pub mod batch_upload;
+pub mod crash_upload;
+pub mod events;
+pub mod feedback;
+pub mod heatmap;
+pub mod index;
+pub mod live_session;
+pub mod players;
+pub mod reports;
+pub mod search;
 pub mod session_upload;
-pub mod index;
\ No newline at end of file
+pub mod stats;
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use crate::auth::{AdminKey, ApiKey};
+use crate::database::PlayerProfile;
+use crate::privacy::{ErasureMode, ErasureReport, ErasureRequest, ErasureTarget};
+use crate::ServerState;
+
+use rocket::State;
+use rocket::{get, http::Status, post, serde::json::Json};
+use serde::Deserialize;
+
+// Everything a player has done across all of their sessions
+#[get("/players/<net_id>")]
+pub async fn get_player_profile(
+    _key: AdminKey,
+    net_id: &str,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<PlayerProfile>, Status> {
+    let profile = state
+        .storage
+        .get_player_profile(net_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to read player profile! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    profile.map(Json).ok_or(Status::NotFound)
+}
+
+// Sent by the game when the player changes their telemetry setting
+#[derive(Deserialize, Debug)]
+pub struct OptOutBody {
+    #[serde(rename = "NetID")]
+    pub net_id: String,
+
+    #[serde(rename = "OptOut")]
+    pub opt_out: bool,
+}
+
+// Opting out stops anything new about the player being stored. What's already stored is kept,
+// removing it is an erasure request through /erasure, which needs the admin key.
+#[post("/opt_out", data = "<body>")]
+pub async fn set_opt_out(
+    _key: ApiKey,
+    body: Json<OptOutBody>,
+    state: &State<Arc<ServerState>>,
+) -> Result<String, Status> {
+    let net_id = body.net_id.trim();
+    if net_id.is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    if body.opt_out {
+        if state.storage.register_opt_out(net_id).await.map_err(|e| {
+            eprintln!("Failed to opt out player! Error: {}", e);
+            Status::InternalServerError
+        })? {
+            println!("Player opted out of telemetry");
+        }
+    } else {
+        state.storage.withdraw_opt_out(net_id).await.map_err(|e| {
+            eprintln!("Failed to opt in player! Error: {}", e);
+            Status::InternalServerError
+        })?;
+    }
+
+    Ok("".to_string())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErasureBody {
+    pub net_id: Option<String>,
+    pub ip: Option<String>,
+    pub mode: ErasureMode,
+    // Recorded in the audit record
+    pub requested_by: Option<String>,
+}
+
+// Deletes or anonymizes everything stored about a player, by NetID or IP
+#[post("/erasure", data = "<body>")]
+pub async fn erase_player(
+    _key: AdminKey,
+    body: Json<ErasureBody>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<ErasureReport>, Status> {
+    let target = match (&body.net_id, &body.ip) {
+        (Some(net_id), None) if !net_id.trim().is_empty() => {
+            ErasureTarget::NetId(net_id.trim().to_string())
+        }
+        (None, Some(ip)) => ErasureTarget::Ip(ip.trim().parse().map_err(|_| Status::BadRequest)?),
+        _ => return Err(Status::BadRequest),
+    };
+    let request = ErasureRequest {
+        target,
+        mode: body.mode,
+        requested_by: body
+            .requested_by
+            .clone()
+            .unwrap_or_else(|| "api".to_string()),
+    };
+
+    let config = state.read_config().unwrap_or(state.default_config.clone());
+    let report = state
+        .storage
+        .erase(&config, &state.secrets, &request)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to erase player data! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    println!("Erasure by {}: {}", request.requested_by, report);
+    Ok(Json(report))
+}
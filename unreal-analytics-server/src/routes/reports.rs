@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use crate::auth::ApiKey;
+use crate::reporting::{ActivePlayers, RetentionCohort};
+use crate::routes::stats::StatsQuery;
+use crate::ServerState;
+
+use rocket::State;
+use rocket::{get, http::Status, serde::json::Json};
+
+// Daily, weekly and monthly active players for each day, the last 30 days by default
+#[get("/reports/active?<query..>")]
+pub async fn get_active_players(
+    _key: ApiKey,
+    query: StatsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<ActivePlayers>>, Status> {
+    let filter = query.to_filter()?;
+    let cache_ttl_secs = state
+        .read_config()
+        .ok_or(Status::InternalServerError)?
+        .reporting
+        .cache_ttl_secs;
+
+    let report = state
+        .storage
+        .get_active_players(&filter, cache_ttl_secs)
+        .await
+        .map_err(|e| {
+            eprintln!("Reports: failed to compute active players! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(report))
+}
+
+// D1/D7/D30 retention for players grouped by the day they first played
+#[get("/reports/retention?<query..>")]
+pub async fn get_retention(
+    _key: ApiKey,
+    query: StatsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<RetentionCohort>>, Status> {
+    let filter = query.to_filter()?;
+    let cache_ttl_secs = state
+        .read_config()
+        .ok_or(Status::InternalServerError)?
+        .reporting
+        .cache_ttl_secs;
+
+    let report = state
+        .storage
+        .get_retention_cohorts(&filter, cache_ttl_secs)
+        .await
+        .map_err(|e| {
+            eprintln!("Reports: failed to compute retention! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(report))
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use crate::auth::AdminKey;
+use crate::database::{SearchHit, SearchQuery};
+use crate::ServerState;
+
+use mongodb::bson::{oid::ObjectId, Bson};
+use rocket::State;
+use rocket::{
+    get,
+    http::Status,
+    serde::json::{Json, Value},
+};
+
+// Finds feedback comments and sessions matching `q`, best matches first
+#[get("/search?<q>&<from>&<to>&<limit>&<skip>")]
+pub async fn search(
+    _key: AdminKey,
+    q: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    limit: Option<u64>,
+    skip: Option<u64>,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<SearchHit>>, Status> {
+    let parse_date = |date: Option<&str>| match date {
+        Some(date) => crate::utils::parse_date_param(date)
+            .map(Some)
+            .ok_or(Status::BadRequest),
+        None => Ok(None),
+    };
+
+    if q.trim().is_empty() {
+        return Err(Status::BadRequest);
+    }
+    let query = SearchQuery {
+        text: q.to_string(),
+        from: parse_date(from)?,
+        to: parse_date(to)?,
+    };
+
+    let hits = state
+        .storage
+        .search(&query, limit.unwrap_or(20).clamp(1, 100), skip.unwrap_or(0))
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to search! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(hits))
+}
+
+// A stored session, so search results have something to link to
+#[get("/sessions/<id>")]
+pub async fn get_session(
+    _key: AdminKey,
+    id: &str,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Value>, Status> {
+    let id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+
+    let session = state.storage.get_session_document(id).await.map_err(|e| {
+        eprintln!("Failed to read session! Error: {}", e);
+        Status::InternalServerError
+    })?;
+
+    session
+        .map(|session| Json(Bson::Document(session).into_relaxed_extjson()))
+        .ok_or(Status::NotFound)
+}
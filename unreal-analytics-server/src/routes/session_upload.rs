@@ -1,166 +1,51 @@
-use std::sync::Arc;
-
-use crate::{cloudflare, get_server_state};
-use chrono::{NaiveDateTime, TimeDelta};
-use serenity::builder::ExecuteWebhook;
+use crate::build_info::BuildInfo;
+use crate::cloudflare;
+use crate::database::AddSessionResult;
+use crate::idempotency::IdempotencyKey;
+use crate::session::{AnalyticsSession, ValidationErrors};
+use crate::ServerState;
+use mongodb::bson::oid::ObjectId;
+use serenity::builder::{CreateActionRow, ExecuteWebhook};
 use serenity::{builder::CreateAttachment, http::Http, model::webhook::Webhook};
+use std::sync::Arc;
 
 use rocket::{
     http::Status,
     post,
     serde::json::serde_json,
     serde::json::{Json, Value},
-    State,
+    Responder, State,
 };
 
-fn parse_end_time(session: &Json<Value>) -> Option<NaiveDateTime> {
-    let start_time_str = session
-        .as_object()?
-        .get("BP_SessionAnalyicsCollector_C")?
-        .get("EndTime")?
-        .as_str()?;
-
-    NaiveDateTime::parse_from_str(start_time_str, "%Y.%m.%d-%H.%M.%S").ok()
-}
-
-fn parse_start_time(session: &Json<Value>) -> Option<NaiveDateTime> {
-    let start_time_str = session
-        .as_object()?
-        .get("BP_SessionAnalyicsCollector_C")?
-        .get("StartTime")?
-        .as_str()?;
-
-    NaiveDateTime::parse_from_str(start_time_str, "%Y.%m.%d-%H.%M.%S").ok()
-}
-
-fn get_feedback_comments(session: &Json<Value>) -> Option<Vec<&str>> {
-    let feedback_array: &Vec<Value> = session
-        .as_object()?
-        .get("BP_CactusGameFeedbackCollector_C")?
-        .get("FeedbackComments")?
-        .as_array()?;
-
-    Some(
-        feedback_array
-            .iter()
-            .map(|val| val.as_str())
-            .flatten()
-            .collect::<Vec<&str>>(),
-    )
-}
-
-fn is_steam_session(session: &Json<Value>) -> Option<bool> {
-    Some(
-        session
-            .as_object()?
-            .get("BP_SessionAnalyicsCollector_C")?
-            .get("PlayerControllerData")?
-            .as_array()?
-            .first()?
-            .get("SteamAnalyticsData")
-            .is_some(),
-    )
-}
-
-fn is_editor_session(session: &Json<Value>) -> Option<bool> {
-    session
-        .as_object()?
-        .get("BP_SessionAnalyicsCollector_C")?
-        .get("IsPlayInEditorSession")?
-        .as_bool()
-}
-
-// Returns the NetID from inside PlayerControllerData
-fn get_net_id(session: &Json<Value>) -> Option<&str> {
-    session
-        .as_object()?
-        .get("BP_SessionAnalyicsCollector_C")?
-        .get("PlayerControllerData")?
-        .as_array()?
-        .first()?
-        .get("NetID")?
-        .as_str()
-}
-
-// Returns the country code and name
-fn get_country_data(session: &Json<Value>) -> Option<(&str, &str)> {
-    let country_code = session
-        .as_object()?
-        .get("BP_SessionAnalyicsCollector_C")?
-        .get("CountryCode")?
-        .as_str()?;
-
-    let country = session
-        .as_object()?
-        .get("BP_SessionAnalyicsCollector_C")?
-        .get("CountryName")?
-        .as_str()?;
-
-    Some((country_code, country))
-}
-
-// Inserts the IP into the BP_SessionAnalyicsCollector_C object
-fn insert_cloudflare_info_into_session_collector(
-    cloudflare_info: &cloudflare::CloudflareInfo,
-    session: &Json<Value>,
-) -> Result<Json<Value>, Box<dyn std::error::Error>> {
-    let mut res = session.clone();
-
-    let session_obj = res
-        .as_object_mut()
-        .ok_or("Failed to find json object in request")?;
-
-    let session_collector_obj = session_obj
-        .get_mut("BP_SessionAnalyicsCollector_C")
-        .ok_or("Failed to find BP_SessionAnalyicsCollector_C in json object")?
-        .as_object_mut()
-        .ok_or("Failed to convert BP_SessionAnalyicsCollector_C to json object")?;
-
-    session_collector_obj.insert(
-        "ip".to_string(),
-        Value::String(cloudflare_info.ip.to_string()),
-    );
-
-    session_collector_obj.insert(
-        "CountryCode".to_string(),
-        Value::String(cloudflare_info.country.clone()),
-    );
-
-    session_collector_obj.insert(
-        "CountryName".to_string(),
-        Value::String(cloudflare_info.get_country_name().to_string()),
-    );
-
-    Ok(res)
+#[derive(Responder, Debug)]
+pub enum SessionUploadError {
+    #[response(status = 422, content_type = "json")]
+    Invalid(Json<ValidationErrors>),
+    Failed(Status),
 }
 
 // Looks at the session data and picks out NetID, StartTime, EndTime, and feedback comments for the discord message
-fn build_content_string(session: &Json<Value>) -> Result<String, Box<dyn std::error::Error>> {
-    // Look into the json and pick out some interesting data
-    let net_id = get_net_id(&session).ok_or("Unable to find Net ID in session data")?;
-    let is_steam_session =
-        is_steam_session(&session).ok_or("Unable to find SteamAnalyticsData in session data")?;
+fn build_content_string(session: &AnalyticsSession) -> Result<String, Box<dyn std::error::Error>> {
+    // Look into the session and pick out some interesting data
+    let net_id = session
+        .net_id()
+        .ok_or("Unable to find Net ID in session data")?;
 
-    let comments = get_feedback_comments(&session).unwrap_or(Vec::new());
+    let session_duration = crate::utils::session_duration_to_string(&session.duration());
 
-    let start_time =
-        parse_start_time(&session).ok_or("Unable to find StartTime in session data")?;
-    let end_time = parse_end_time(&session).ok_or("Unable to find EndTime in session data")?;
-
-    let session_duration = crate::utils::session_duration_to_string(&(end_time - start_time));
-
-    let (country_code, country_name) =
-        get_country_data(&session).ok_or("Unable to find country data")?;
+    let (country_code, country_name) = session
+        .country_data()
+        .ok_or("Unable to find country data")?;
     let country_string = format!("({}/{})", country_code, country_name);
 
-    let game_name_type_string = if is_editor_session(&session).unwrap_or(false) {
+    let game_name_type_string = if session.is_editor_session() {
         "PIE game"
     } else {
         "game"
     };
 
     // If it's a steam session, put their steam page in the message
-    let mut content_str = if is_steam_session {
+    let mut content_str = if session.is_steam_session() {
         format!(
             "https://steamcommunity.com/profiles/{}\n{}\nPlayed a {} for {}",
             net_id, country_string, game_name_type_string, session_duration
@@ -173,7 +58,8 @@ fn build_content_string(session: &Json<Value>) -> Result<String, Box<dyn std::er
     };
 
     // If they added comments, put that in the message
-    if comments.len() > 0 {
+    let comments = session.feedback_comments();
+    if !comments.is_empty() {
         content_str += "\n\nFeedback comments:";
         for comment in comments {
             content_str += &format!("\n`{}`", comment);
@@ -184,17 +70,23 @@ fn build_content_string(session: &Json<Value>) -> Result<String, Box<dyn std::er
 }
 
 async fn send_discord_session_info(
-    url: &String,
-    session: Json<Value>,
+    url: &str,
+    session: AnalyticsSession,
+    button: Option<ObjectId>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let http = Http::new("");
 
     let content_str = build_content_string(&session)?;
-    let session_str = serde_json::ser::to_string_pretty(session.as_object().unwrap())?;
+    let session_str = serde_json::ser::to_string_pretty(&session)?;
 
     let webhook = Webhook::from_url(&http, url).await?;
     let file = CreateAttachment::bytes(session_str, "AnalyticsSession.json");
-    let builder = ExecuteWebhook::new().content(content_str).add_file(file);
+    let mut builder = ExecuteWebhook::new().content(content_str).add_file(file);
+    if let Some(id) = button {
+        builder = builder.components(vec![CreateActionRow::Buttons(vec![
+            crate::commands::gitlab_issue::button(id),
+        ])]);
+    }
 
     println!("Sending discord message");
     webhook.execute(&http, true, builder).await?;
@@ -205,8 +97,11 @@ async fn send_discord_session_info(
 
 use crate::auth::ApiKey;
 
-pub fn try_spawn_discord_message_task(session: Json<Value>) {
-    let state = get_server_state();
+pub fn try_spawn_discord_message_task(
+    state: &ServerState,
+    session: AnalyticsSession,
+    id: ObjectId,
+) {
     let config = match state.read_config() {
         Some(config) => config,
         None => {
@@ -220,14 +115,19 @@ pub fn try_spawn_discord_message_task(session: Json<Value>) {
     }
 
     // Don't send editor session messages if it's an editor sesh
-    if is_editor_session(&session).unwrap_or(false) && !config.discord_config.notify_editor_sessions
-    {
+    if session.is_editor_session() && !config.discord_config.notify_editor_sessions {
         return;
     }
 
+    // Only sessions with feedback get a button to file it
+    let button = (config.gitlab.session_message_button && !session.feedback_comments().is_empty())
+        .then_some(id);
+
     let url = state.secrets.keys.discord_webhook.to_string();
     tokio::task::spawn(async move {
-        send_discord_session_info(&url, session).await.unwrap_or(());
+        send_discord_session_info(&url, session, button)
+            .await
+            .unwrap_or(());
     });
 }
 
@@ -235,32 +135,62 @@ pub fn try_spawn_discord_message_task(session: Json<Value>) {
 pub async fn upload_session(
     _key: ApiKey,
     cloudflare_info: cloudflare::CloudflareInfo,
+    idempotency_key: IdempotencyKey,
+    build_info: BuildInfo,
     session: Json<Value>,
-) -> Result<String, Status> {
-    // Modify the session data, add the IP
-    let modified_session =
-        insert_cloudflare_info_into_session_collector(&cloudflare_info, &session)
-            .map_err(|_| Status { code: 400 })?;
+    state: &State<Arc<ServerState>>,
+) -> Result<String, SessionUploadError> {
+    let mut session = AnalyticsSession::from_value(session.into_inner())
+        .map_err(|errors| SessionUploadError::Invalid(Json(errors)))?;
+
+    // Some builds send their build details in headers instead of the session. Fill those in
+    // first, since the anonymous count keeps them too.
+    session.set_build_info(&build_info);
+    // Also before counting, so a retry of an anonymous session isn't counted twice
+    session.set_idempotency_key(idempotency_key.0);
+
+    // Players that turned telemetry off are only counted, nothing about them is stored
+    let is_anonymous = state
+        .storage
+        .count_if_anonymous(&session)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to count anonymous session! Error: {}", e);
+            SessionUploadError::Failed(Status::InternalServerError)
+        })?;
+    if is_anonymous {
+        // Nothing was stored, so there's no id to send back
+        return Ok(String::new());
+    }
 
-    let state = crate::get_server_state();
+    // Modify the session data, add the IP
+    session.set_cloudflare_info(&cloudflare_info, &state.ip_policy());
+    // Before it's stored, since it also ends up in discord and GitLab from there
+    state.scrubber.scrub_session(&mut session);
 
     // Throw it into the database
-    let db_res = state.db.add_session(&modified_session).await;
+    let db_res = state.storage.add_session(&session).await;
 
     match db_res {
-        Ok(_) => {
+        Ok(AddSessionResult::Inserted(id)) => {
+            // The full session made it, so it's no longer in progress
+            if let Some(session_id) = &session.collector.session_id {
+                if let Err(e) = state.storage.end_live_session(session_id).await {
+                    eprintln!("Failed to end live session! Error: {}", e);
+                }
+            }
+
             // Spawn the discord task async so that we don't have to wait before returning a http response
-            try_spawn_discord_message_task(modified_session);
+            try_spawn_discord_message_task(state, session, id);
 
-            Ok("".to_string())
+            Ok(id.to_hex())
         }
+        // A retry of a session that's already stored, answer the same way as the first time
+        Ok(AddSessionResult::Duplicate(id)) => Ok(id.to_hex()),
         Err(e) => {
-            eprintln!(
-                "Failed to insert session into database! Error: {}",
-                e.to_string()
-            );
+            eprintln!("Failed to insert session into database! Error: {}", e);
 
-            Err(Status { code: 500 })
+            Err(SessionUploadError::Failed(Status::InternalServerError))
         }
     }
 }
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use crate::auth::ApiKey;
+use crate::database::{PerformanceStats, PlayTimeDistribution, PlayerStats, StatsFilter};
+use crate::rollups::DailyRollup;
+use crate::ServerState;
+
+use rocket::State;
+use rocket::{get, http::Status, serde::json::Json, FromForm};
+
+// Query parameters accepted by every stats route. Dates are RFC 3339 or YYYY-MM-DD.
+#[derive(FromForm, Debug)]
+pub struct StatsQuery {
+    pub build: Option<String>,
+    pub platform: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub pie: Option<bool>,
+    pub country: Option<String>,
+    pub steam: Option<bool>,
+}
+
+impl StatsQuery {
+    pub fn to_filter(&self) -> Result<StatsFilter, Status> {
+        let parse_date = |date: &Option<String>| match date {
+            Some(date) => crate::utils::parse_date_param(date)
+                .map(Some)
+                .ok_or(Status::BadRequest),
+            None => Ok(None),
+        };
+
+        Ok(StatsFilter {
+            build_version: self.build.clone(),
+            platform: self.platform.clone(),
+            from: parse_date(&self.from)?,
+            to: parse_date(&self.to)?,
+            pie: self.pie,
+            country: self.country.clone(),
+            steam: self.steam,
+        })
+    }
+}
+
+// Session counts, unique players and average play time
+#[get("/stats/players?<query..>")]
+pub async fn get_player_stats(
+    _key: ApiKey,
+    query: StatsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<PlayerStats>, Status> {
+    let filter = query.to_filter()?;
+    let stats = state
+        .storage
+        .get_players_stats(&filter)
+        .await
+        .map_err(|e| {
+            eprintln!("Stats: failed to read player stats! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(stats))
+}
+
+// Play time percentiles, trimmed average and histogram for game sessions
+#[get("/stats/play_time?<query..>")]
+pub async fn get_play_time_stats(
+    _key: ApiKey,
+    query: StatsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<PlayTimeDistribution>, Status> {
+    let filter = query.to_filter()?;
+    let stats = state
+        .storage
+        .get_play_time_distribution(&filter)
+        .await
+        .map_err(|e| {
+            eprintln!("Stats: failed to read play time stats! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(stats))
+}
+
+// Frame time percentiles, hitches and memory per build and hardware class
+#[get("/stats/performance?<query..>")]
+pub async fn get_performance_stats(
+    _key: ApiKey,
+    query: StatsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<PerformanceStats>>, Status> {
+    let filter = query.to_filter()?;
+    let stats = state
+        .storage
+        .get_performance_stats(&filter)
+        .await
+        .map_err(|e| {
+            eprintln!("Stats: failed to read performance stats! Error: {}", e);
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(stats))
+}
+
+// Session counters per day from the daily rollups. Dates are widened to whole days.
+#[get("/stats/daily?<query..>")]
+pub async fn get_daily_stats(
+    _key: ApiKey,
+    query: StatsQuery,
+    state: &State<Arc<ServerState>>,
+) -> Result<Json<Vec<DailyRollup>>, Status> {
+    let filter = query.to_filter()?;
+    let rows = state.storage.get_daily_stats(&filter).await.map_err(|e| {
+        eprintln!("Stats: failed to read daily rollups! Error: {}", e);
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(rows))
+}
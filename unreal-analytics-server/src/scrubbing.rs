@@ -0,0 +1,191 @@
+use regex::{NoExpand, Regex};
+use rocket::serde::json::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ScrubbingConfig;
+use crate::session::{AnalyticsSession, FEEDBACK_COLLECTOR};
+
+// Rule name for matches from the blocklist file
+const BLOCKLIST_RULE: &str = "blocklist";
+
+// Stored in the session so it's known that text was removed, but not what it was
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Redaction {
+    #[serde(rename = "Field")]
+    pub field: String,
+
+    #[serde(rename = "Rule")]
+    pub rule: String,
+
+    #[serde(rename = "Count")]
+    pub count: u32,
+}
+
+// The scrubbing rules, compiled once at startup
+#[derive(Debug, Default)]
+pub struct Scrubber {
+    rules: Vec<(String, Regex)>,
+    replacement: String,
+    extra_collectors: Vec<String>,
+}
+
+// Matches a blocklist term on its own, not as part of a longer word
+fn blocklist_pattern(term: &str) -> String {
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if is_word_char(term.chars().next()) {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if is_word_char(term.chars().last()) {
+        r"\b"
+    } else {
+        ""
+    };
+
+    format!("{}{}{}", start, regex::escape(term), end)
+}
+
+impl Scrubber {
+    pub fn new(config: &ScrubbingConfig) -> Result<Self, String> {
+        if !config.enabled {
+            return Ok(Scrubber::default());
+        }
+
+        let mut rules = Vec::new();
+        for rule in &config.rules {
+            let regex = Regex::new(&rule.pattern)
+                .map_err(|e| format!("scrubbing rule `{}` is invalid: {}", rule.name, e))?;
+            rules.push((rule.name.clone(), regex));
+        }
+
+        if !config.blocklist_file.is_empty() {
+            let contents = std::fs::read_to_string(&config.blocklist_file).map_err(|e| {
+                format!(
+                    "could not read blocklist `{}`: {}",
+                    config.blocklist_file, e
+                )
+            })?;
+            let terms: Vec<String> = contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(blocklist_pattern)
+                .collect();
+
+            if !terms.is_empty() {
+                let regex = Regex::new(&format!("(?i){}", terms.join("|")))
+                    .map_err(|e| format!("blocklist is invalid: {}", e))?;
+                rules.push((BLOCKLIST_RULE.to_string(), regex));
+            }
+        }
+
+        Ok(Scrubber {
+            rules,
+            replacement: config.replacement.clone(),
+            extra_collectors: config.extra_collectors.clone(),
+        })
+    }
+
+    fn scrub_str(&self, field: &str, text: &mut String, redactions: &mut Vec<Redaction>) {
+        for (name, regex) in &self.rules {
+            let count = regex.find_iter(text).count();
+            if count == 0 {
+                continue;
+            }
+
+            let replacement = self.replacement.replace("{rule}", name);
+            *text = regex.replace_all(text, NoExpand(&replacement)).into_owned();
+            redactions.push(Redaction {
+                field: field.to_string(),
+                rule: name.clone(),
+                count: count as u32,
+            });
+        }
+    }
+
+    // Scrubs every string in the value
+    fn scrub_value(&self, field: &str, value: &mut Value, redactions: &mut Vec<Redaction>) {
+        match value {
+            Value::String(text) => self.scrub_str(field, text, redactions),
+            Value::Array(values) => {
+                for (i, value) in values.iter_mut().enumerate() {
+                    self.scrub_value(&format!("{}[{}]", field, i), value, redactions);
+                }
+            }
+            Value::Object(values) => {
+                for (key, value) in values.iter_mut() {
+                    self.scrub_value(&format!("{}.{}", field, key), value, redactions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Removes personal details from the session's free text and records what was removed in its
+    // Redactions
+    pub fn scrub_session(&self, session: &mut AnalyticsSession) {
+        // Only the server gets to say what was redacted
+        let mut redactions = Vec::new();
+
+        if let Some(feedback) = &mut session.feedback {
+            for (i, comment) in feedback.comments.iter_mut().enumerate() {
+                let field = format!("{}.FeedbackComments[{}]", FEEDBACK_COLLECTOR, i);
+                self.scrub_str(&field, comment, &mut redactions);
+            }
+            for (key, value) in feedback.extra.iter_mut() {
+                let field = format!("{}.{}", FEEDBACK_COLLECTOR, key);
+                self.scrub_value(&field, value, &mut redactions);
+            }
+        }
+
+        for collector in &self.extra_collectors {
+            if let Some(value) = session.extra_collectors.get_mut(collector) {
+                self.scrub_value(collector, value, &mut redactions);
+            }
+        }
+
+        session.redactions = redactions;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Scrubs with the rules that ship in config/App.toml
+    fn scrub(text: &str) -> String {
+        let scrubber = Scrubber::new(&crate::config::read_config().scrubbing).unwrap();
+        let mut text = text.to_string();
+        scrubber.scrub_str("Comment", &mut text, &mut Vec::new());
+        text
+    }
+
+    #[test]
+    fn default_rules_keep_technical_details() {
+        for text in [
+            "1920 1080 at 12.5 fps",
+            "4.27.2 (12345678)",
+            "crashed at 2024-03-01 12:30:45",
+            "stuck at X=1234.5 Y=-567.8 Z=+1234.56",
+            "@Shooter42 keeps camping",
+            "discord crashed when alt tabbing",
+        ] {
+            assert_eq!(scrub(text), text);
+        }
+    }
+
+    #[test]
+    fn default_rules_remove_contact_details() {
+        for (text, scrubbed) in [
+            ("mail bob@example.com", "mail [email]"),
+            ("call +1 555 123 4567", "call [phone]"),
+            ("call +31612345678", "call [phone]"),
+            ("call (555) 123-4567", "call [phone]"),
+            ("add Foo.Bar#1234", "add [discord]"),
+            ("my discord is foo.bar", "my [discord]"),
+        ] {
+            assert_eq!(scrub(text), scrubbed);
+        }
+    }
+}
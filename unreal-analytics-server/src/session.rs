@@ -0,0 +1,631 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use rocket::serde::json::{serde_json::Map, Value};
+
+use crate::build_info::BuildInfo;
+use crate::cloudflare;
+use crate::privacy::IpPolicy;
+use crate::scrubbing::Redaction;
+
+pub const SESSION_COLLECTOR: &str = "BP_SessionAnalyicsCollector_C";
+pub const FEEDBACK_COLLECTOR: &str = "BP_CactusGameFeedbackCollector_C";
+pub const PERFORMANCE_COLLECTOR: &str = "BP_PerformanceAnalyticsCollector_C";
+
+// Newest session schema version this server understands. Uploads without a
+// SchemaVersion field are treated as version 1.
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+// Format Unreal uses when it serializes an FDateTime to a string
+pub const UNREAL_DATE_TIME_FORMAT: &str = "%Y.%m.%d-%H.%M.%S";
+
+// (De)serializes a NaiveDateTime in Unreal's FDateTime string format
+pub(crate) mod unreal_date_time {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        date: &NaiveDateTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&date.format(super::UNREAL_DATE_TIME_FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<NaiveDateTime, D::Error> {
+        let date_str = String::deserialize(deserializer)?;
+        NaiveDateTime::parse_from_str(&date_str, super::UNREAL_DATE_TIME_FORMAT).map_err(|err| {
+            serde::de::Error::custom(format!(
+                "invalid date time `{}`, expected YYYY.MM.DD-HH.MM.SS ({})",
+                date_str, err
+            ))
+        })
+    }
+
+    // Same as above, for optional date times
+    pub mod option {
+        use chrono::NaiveDateTime;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            date: &Option<NaiveDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match date {
+                Some(date) => super::serialize(date, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<NaiveDateTime>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] NaiveDateTime);
+
+            let date = Option::<Wrapper>::deserialize(deserializer)?;
+            Ok(date.map(|Wrapper(date)| date))
+        }
+    }
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+// A full session upload. Collectors that the server doesn't have a type for are kept as-is in
+// `extra_collectors` so that new blueprint collectors get stored without a server release.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AnalyticsSession {
+    #[serde(rename = "SchemaVersion", default = "default_schema_version")]
+    pub schema_version: u32,
+
+    // Set by the server from the collector's SessionID or the Idempotency-Key header. A unique
+    // index on it stops retried uploads from being stored twice.
+    #[serde(
+        rename = "IdempotencyKey",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idempotency_key: Option<String>,
+
+    #[serde(rename = "BP_SessionAnalyicsCollector_C")]
+    pub collector: SessionCollector,
+
+    #[serde(
+        rename = "BP_CactusGameFeedbackCollector_C",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub feedback: Option<FeedbackCollector>,
+
+    #[serde(
+        rename = "BP_PerformanceAnalyticsCollector_C",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub performance: Option<PerformanceCollector>,
+
+    // Set by the server, see scrubbing::Scrubber
+    #[serde(rename = "Redactions", default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<Redaction>,
+
+    #[serde(flatten)]
+    pub extra_collectors: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SessionCollector {
+    // GUID generated by the game when the session starts
+    #[serde(rename = "SessionID", default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+
+    #[serde(
+        rename = "BuildVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub build_version: Option<String>,
+
+    // Perforce changelist the build was made from
+    #[serde(
+        rename = "Changelist",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub changelist: Option<u64>,
+
+    // Unreal platform name, like Windows or Linux
+    #[serde(rename = "Platform", default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+
+    // Development, Shipping, etc
+    #[serde(
+        rename = "BuildConfiguration",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub build_configuration: Option<String>,
+
+    #[serde(rename = "StartTime", with = "unreal_date_time")]
+    pub start_time: NaiveDateTime,
+
+    #[serde(rename = "EndTime", with = "unreal_date_time")]
+    pub end_time: NaiveDateTime,
+
+    #[serde(rename = "IsPlayInEditorSession", default)]
+    pub is_play_in_editor_session: bool,
+
+    #[serde(rename = "PlayerControllerData", default)]
+    pub player_controller_data: Vec<PlayerControllerData>,
+
+    // False when the player turned telemetry off, older builds don't send it
+    #[serde(
+        rename = "TelemetryConsent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub telemetry_consent: Option<bool>,
+
+    // Filled in by the server from the cloudflare headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+
+    #[serde(
+        rename = "CountryCode",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub country_code: Option<String>,
+
+    #[serde(
+        rename = "CountryName",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub country_name: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlayerControllerData {
+    #[serde(rename = "NetID")]
+    pub net_id: String,
+
+    #[serde(
+        rename = "SteamAnalyticsData",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub steam_analytics_data: Option<SteamAnalyticsData>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// Only present for players that are logged into steam. The contents are passed through untouched.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SteamAnalyticsData {
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FeedbackCollector {
+    #[serde(rename = "FeedbackComments", default)]
+    pub comments: Vec<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// Number of frames that took at most `upper_bound_ms` (and more than the previous bucket's bound)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FrameTimeBucket {
+    #[serde(rename = "UpperBoundMs")]
+    pub upper_bound_ms: f64,
+
+    #[serde(rename = "Count")]
+    pub count: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PerformanceCollector {
+    #[serde(rename = "FrameTimeHistogram", default)]
+    pub frame_time_histogram: Vec<FrameTimeBucket>,
+
+    #[serde(rename = "HitchCount", default)]
+    pub hitch_count: u64,
+
+    #[serde(rename = "CPUBrand", default, skip_serializing_if = "Option::is_none")]
+    pub cpu_brand: Option<String>,
+
+    #[serde(rename = "GPUBrand", default, skip_serializing_if = "Option::is_none")]
+    pub gpu_brand: Option<String>,
+
+    #[serde(
+        rename = "PeakMemoryMB",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub peak_memory_mb: Option<f64>,
+
+    // Filled in by the server from the GPU when the game doesn't send it
+    #[serde(
+        rename = "HardwareClass",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hardware_class: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// Rough bucket for a GPU so that performance can be compared between similar machines
+pub fn classify_gpu(gpu_brand: &str) -> &'static str {
+    let gpu = gpu_brand.to_ascii_lowercase();
+
+    if gpu.contains("rtx") {
+        "NVIDIA RTX"
+    } else if gpu.contains("nvidia") || gpu.contains("geforce") {
+        "NVIDIA"
+    } else if gpu.contains("radeon") || gpu.contains("amd") {
+        "AMD"
+    } else if gpu.contains("arc") && gpu.contains("intel") {
+        "Intel Arc"
+    } else if gpu.contains("intel") {
+        "Intel Integrated"
+    } else if gpu.contains("apple") {
+        "Apple"
+    } else {
+        "Other"
+    }
+}
+
+impl PerformanceCollector {
+    fn validate(&self, errors: &mut Vec<FieldError>) {
+        let mut previous_bound = 0.0;
+        for (i, bucket) in self.frame_time_histogram.iter().enumerate() {
+            if !bucket.upper_bound_ms.is_finite() || bucket.upper_bound_ms <= previous_bound {
+                errors.push(FieldError::new(
+                    format!(
+                        "{}.FrameTimeHistogram[{}].UpperBoundMs",
+                        PERFORMANCE_COLLECTOR, i
+                    ),
+                    "bucket bounds must be positive and increasing",
+                ));
+            }
+            previous_bound = bucket.upper_bound_ms;
+        }
+
+        if let Some(peak_memory_mb) = self.peak_memory_mb {
+            if !peak_memory_mb.is_finite() || peak_memory_mb < 0.0 {
+                errors.push(FieldError::new(
+                    format!("{}.PeakMemoryMB", PERFORMANCE_COLLECTOR),
+                    "PeakMemoryMB must be a positive number",
+                ));
+            }
+        }
+    }
+
+    pub fn fill_hardware_class(&mut self) {
+        if self.hardware_class.is_none() {
+            self.hardware_class =
+                Some(classify_gpu(self.gpu_brand.as_deref().unwrap_or("")).to_string());
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+// Body of the 422 response sent back when a session fails to parse or validate
+#[derive(Serialize, Debug, Clone)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl AnalyticsSession {
+    // Deserializes and validates a session, returning every problem found with it
+    pub fn from_value(value: Value) -> Result<Self, ValidationErrors> {
+        let mut session: AnalyticsSession =
+            serde_path_to_error::deserialize(value).map_err(|err| {
+                let field = match err.path().to_string().as_str() {
+                    "." => "".to_string(),
+                    path => path.to_string(),
+                };
+
+                ValidationErrors {
+                    errors: vec![FieldError::new(field, err.into_inner().to_string())],
+                }
+            })?;
+
+        let errors = session.validate();
+        if errors.is_empty() {
+            if let Some(performance) = &mut session.performance {
+                performance.fill_hardware_class();
+            }
+
+            Ok(session)
+        } else {
+            Err(ValidationErrors { errors })
+        }
+    }
+
+    // Checks the things that serde can't, like the end time coming after the start time
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if self.schema_version == 0 || self.schema_version > CURRENT_SCHEMA_VERSION {
+            errors.push(FieldError::new(
+                "SchemaVersion",
+                format!(
+                    "unsupported schema version {}, this server supports versions 1 to {}",
+                    self.schema_version, CURRENT_SCHEMA_VERSION
+                ),
+            ));
+        }
+
+        if self.collector.end_time < self.collector.start_time {
+            errors.push(FieldError::new(
+                format!("{}.EndTime", SESSION_COLLECTOR),
+                "EndTime is before StartTime",
+            ));
+        }
+
+        if let Some(session_id) = &self.collector.session_id {
+            if session_id.trim().is_empty() || session_id.len() > 128 {
+                errors.push(FieldError::new(
+                    format!("{}.SessionID", SESSION_COLLECTOR),
+                    "SessionID must be between 1 and 128 characters",
+                ));
+            }
+        }
+
+        for (i, player_controller) in self.collector.player_controller_data.iter().enumerate() {
+            if player_controller.net_id.trim().is_empty() {
+                errors.push(FieldError::new(
+                    format!("{}.PlayerControllerData[{}].NetID", SESSION_COLLECTOR, i),
+                    "NetID is empty",
+                ));
+            }
+        }
+
+        if let Some(performance) = &self.performance {
+            performance.validate(&mut errors);
+        }
+
+        errors
+    }
+
+    // Returns the NetID of the first player controller
+    pub fn net_id(&self) -> Option<&str> {
+        self.collector
+            .player_controller_data
+            .first()
+            .map(|player_controller| player_controller.net_id.as_str())
+    }
+
+    pub fn is_steam_session(&self) -> bool {
+        self.collector
+            .player_controller_data
+            .first()
+            .is_some_and(|player_controller| player_controller.steam_analytics_data.is_some())
+    }
+
+    pub fn has_consent(&self) -> bool {
+        crate::opt_outs::has_consent(self.collector.telemetry_consent)
+    }
+
+    pub fn is_editor_session(&self) -> bool {
+        self.collector.is_play_in_editor_session
+    }
+
+    pub fn duration(&self) -> chrono::TimeDelta {
+        self.collector.end_time - self.collector.start_time
+    }
+
+    pub fn feedback_comments(&self) -> &[String] {
+        match &self.feedback {
+            Some(feedback) => &feedback.comments,
+            None => &[],
+        }
+    }
+
+    // Returns the country code and name
+    pub fn country_data(&self) -> Option<(&str, &str)> {
+        Some((
+            self.collector.country_code.as_deref()?,
+            self.collector.country_name.as_deref()?,
+        ))
+    }
+
+    // Picks the key used to recognize retries of this session. The collector's SessionID wins over
+    // the header since it stays the same even if the game rebuilds the request.
+    pub fn set_idempotency_key(&mut self, header_key: Option<String>) {
+        self.idempotency_key = self.collector.session_id.clone().or(header_key);
+    }
+
+    // Stores the IP and country from the cloudflare headers in the session collector. The IP is
+    // stored as the policy says.
+    pub fn set_cloudflare_info(
+        &mut self,
+        cloudflare_info: &cloudflare::CloudflareInfo,
+        ip_policy: &IpPolicy,
+    ) {
+        self.collector.ip = Some(ip_policy.stored_ip(&cloudflare_info.ip));
+        self.collector.country_code = Some(cloudflare_info.country.clone());
+        self.collector.country_name = Some(cloudflare_info.get_country_name());
+    }
+
+    // Fills in build details the collector didn't send from the X-Build-* headers
+    pub fn set_build_info(&mut self, build_info: &BuildInfo) {
+        let collector = &mut self.collector;
+        if collector.build_version.is_none() {
+            collector.build_version = build_info.build_version.clone();
+        }
+        if collector.changelist.is_none() {
+            collector.changelist = build_info.changelist;
+        }
+        if collector.platform.is_none() {
+            collector.platform = build_info.platform.clone();
+        }
+        if collector.build_configuration.is_none() {
+            collector.build_configuration = build_info.build_configuration.clone();
+        }
+    }
+}
+
+// Sent by the game while a session is still in progress, see routes::live_session
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LiveSessionEvent {
+    #[serde(rename = "SessionID")]
+    pub session_id: String,
+
+    #[serde(rename = "NetID", default)]
+    pub net_id: Option<String>,
+
+    #[serde(rename = "IsPlayInEditorSession", default)]
+    pub is_play_in_editor_session: bool,
+
+    #[serde(
+        rename = "BuildVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub build_version: Option<String>,
+
+    #[serde(rename = "Platform", default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+
+    // False when the player turned telemetry off, older builds don't send it
+    #[serde(
+        rename = "TelemetryConsent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub telemetry_consent: Option<bool>,
+}
+
+impl LiveSessionEvent {
+    // Fills in build details the event didn't send from the X-Build-* headers
+    pub fn set_build_info(&mut self, build_info: &BuildInfo) {
+        if self.build_version.is_none() {
+            self.build_version = build_info.build_version.clone();
+        }
+        if self.platform.is_none() {
+            self.platform = build_info.platform.clone();
+        }
+    }
+
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if self.session_id.trim().is_empty() || self.session_id.len() > 128 {
+            errors.push(FieldError::new(
+                "SessionID",
+                "SessionID must be between 1 and 128 characters",
+            ));
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::serde_json::json;
+
+    fn session_with_performance(performance: Value) -> Result<AnalyticsSession, ValidationErrors> {
+        AnalyticsSession::from_value(json!({
+            SESSION_COLLECTOR: {
+                "StartTime": "2024.03.01-12.00.00",
+                "EndTime": "2024.03.01-13.00.00",
+            },
+            PERFORMANCE_COLLECTOR: performance,
+        }))
+    }
+
+    #[test]
+    fn classifies_gpus() {
+        for (gpu, class) in [
+            ("NVIDIA GeForce RTX 3070", "NVIDIA RTX"),
+            ("NVIDIA GeForce GTX 1060 6GB", "NVIDIA"),
+            ("AMD Radeon RX 6800 XT", "AMD"),
+            ("Intel(R) Arc(TM) A770 Graphics", "Intel Arc"),
+            ("Intel(R) UHD Graphics 630", "Intel Integrated"),
+            ("Apple M2", "Apple"),
+            ("", "Other"),
+        ] {
+            assert_eq!(classify_gpu(gpu), class);
+        }
+    }
+
+    #[test]
+    fn fills_in_the_hardware_class() {
+        let session = session_with_performance(json!({"GPUBrand": "AMD Radeon RX 580"})).unwrap();
+        let performance = session.performance.unwrap();
+        assert_eq!(performance.hardware_class.as_deref(), Some("AMD"));
+        assert_eq!(performance.hitch_count, 0);
+
+        let session = session_with_performance(json!({
+            "GPUBrand": "AMD Radeon RX 580",
+            "HardwareClass": "Low",
+        }))
+        .unwrap();
+        assert_eq!(
+            session.performance.unwrap().hardware_class.as_deref(),
+            Some("Low")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_histograms_and_memory() {
+        let histogram = |bounds: &[f64]| {
+            let buckets: Vec<Value> = bounds
+                .iter()
+                .map(|bound| json!({"UpperBoundMs": bound, "Count": 1}))
+                .collect();
+            session_with_performance(json!({"FrameTimeHistogram": buckets}))
+        };
+
+        assert!(histogram(&[8.0, 16.0, 33.0]).is_ok());
+        assert!(histogram(&[]).is_ok());
+
+        let errors = histogram(&[8.0, 8.0, 33.0, 16.0]).unwrap_err().errors;
+        let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "BP_PerformanceAnalyticsCollector_C.FrameTimeHistogram[1].UpperBoundMs",
+                "BP_PerformanceAnalyticsCollector_C.FrameTimeHistogram[3].UpperBoundMs",
+            ]
+        );
+        assert!(histogram(&[0.0]).is_err());
+
+        let errors = session_with_performance(json!({"PeakMemoryMB": -1.0}))
+            .unwrap_err()
+            .errors;
+        assert_eq!(
+            errors[0].field,
+            "BP_PerformanceAnalyticsCollector_C.PeakMemoryMB"
+        );
+    }
+}
@@ -0,0 +1,2037 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use rocket::fs::TempFile;
+
+use super::{BoxError, Storage};
+use crate::cloudflare::CloudflareInfo;
+use crate::config::{self, BlobStorage, CrashReportConfig, RetentionConfig, Secrets};
+use crate::crash::{self, AttachmentKind};
+use crate::database::{
+    self, get_chrono, get_number, AddSessionResult, CrashGroup, Database, EventCount,
+    EventGrouping, EventQuery, LiveSessionStatus, PerformanceStats, PlayTimeDistribution,
+    PlayerFeedback, PlayerProfile, PlayerSessionSummary, PlayerStats, SearchHit, SearchQuery,
+    StatsFilter, GITLAB_CLAIM_EXPIRY, MAX_SEARCH_RESULTS, PLAYER_RECENT_SESSIONS,
+};
+use crate::feedback::{self, FeedbackItem, FeedbackQuery, FeedbackUpdate};
+use crate::funnel::{FunnelEvent, FunnelQuery, FunnelStepResult};
+use crate::gameplay_event::GameplayEvent;
+use crate::gitlab::GitlabIssue;
+use crate::heatmap::{HeatmapGrid, PositionSample};
+use crate::opt_outs::{self, AnonymousSession};
+use crate::privacy::{self, ErasureMode, ErasureReport, ErasureRequest, ErasureTarget};
+use crate::reporting::{self, ActivePlayers, RetentionCohort};
+use crate::retention::RetentionReport;
+use crate::rollups::{self, DailyRollup};
+use crate::session::{
+    AnalyticsSession, LiveSessionEvent, FEEDBACK_COLLECTOR, PERFORMANCE_COLLECTOR,
+    SESSION_COLLECTOR,
+};
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+
+// Keeps everything in memory, for running without a mongod. Nothing survives a restart. Stats are
+// worked out from the stored sessions on every call, there are no rollups or report caches.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    // Each stored the same way as in its mongo collection
+    sessions: Mutex<Vec<Document>>,
+    anonymous_sessions: Mutex<Vec<AnonymousSession>>,
+    anonymous_session_keys: Mutex<HashSet<String>>,
+    // NetIDs of players that opted out
+    opt_outs: Mutex<HashSet<String>>,
+    live_sessions: Mutex<Vec<Document>>,
+    feedback: Mutex<Vec<Document>>,
+    crashes: Mutex<Vec<Document>>,
+    crash_groups: Mutex<Vec<Document>>,
+    // Stands in for the GridFS bucket, keyed by the id in the attachment's Location
+    crash_files: Mutex<HashMap<ObjectId, Vec<u8>>>,
+    events: Mutex<Vec<Document>>,
+    positions: Mutex<Vec<Document>>,
+    erasure_requests: Mutex<Vec<Document>>,
+}
+
+// A panic while holding a lock can't leave anything half written, so keep going
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Sessions matching the filter, with PIE sessions left out unless the filter asks for them
+    fn game_sessions(&self, filter: &StatsFilter) -> Vec<Document> {
+        let filter = StatsFilter {
+            pie: Some(filter.pie.unwrap_or(false)),
+            ..filter.clone()
+        };
+
+        lock(&self.sessions)
+            .iter()
+            .filter(|session| matches_filter(&filter, session))
+            .cloned()
+            .collect()
+    }
+
+    // Sessions are locked before feedback everywhere both are needed
+    fn insert(
+        &self,
+        sessions: &mut Vec<Document>,
+        session: &AnalyticsSession,
+    ) -> mongodb::error::Result<AddSessionResult> {
+        // Same rule as the unique IdempotencyKey index
+        if let Some(key) = &session.idempotency_key {
+            let original = sessions
+                .iter()
+                .find(|document| document.get_str("IdempotencyKey").ok() == Some(key.as_str()));
+            if let Some(id) = original.and_then(|document| document.get_object_id("_id").ok()) {
+                return Ok(AddSessionResult::Duplicate(id));
+            }
+        }
+
+        let id = ObjectId::new();
+        let mut document = database::session_to_document(session)?;
+        document.insert("_id", id);
+
+        lock(&self.feedback).extend(
+            feedback::feedback_from_session(id, &document)
+                .into_iter()
+                .map(with_new_id),
+        );
+        sessions.push(document);
+
+        Ok(AddSessionResult::Inserted(id))
+    }
+
+    // Same matching as privacy::erase, without the archives since nothing is archived here
+    async fn erase_records(
+        &self,
+        salt: &str,
+        request: &ErasureRequest,
+    ) -> Result<ErasureReport, BoxError> {
+        let mut report = ErasureReport::default();
+
+        let (net_ids, ips) = match &request.target {
+            ErasureTarget::NetId(net_id) => (vec![net_id.clone()], Vec::new()),
+            ErasureTarget::Ip(ip) => (
+                Vec::new(),
+                vec![ip.to_string(), privacy::keyed_hash(salt, &ip.to_string())],
+            ),
+        };
+        let matches_target = |session: &Document| match &request.target {
+            ErasureTarget::NetId(net_id) => privacy::session_net_ids(session).contains(net_id),
+            ErasureTarget::Ip(_) => collector(session)
+                .and_then(|collector| collector.get_str("ip").ok())
+                .is_some_and(|ip| ips.iter().any(|known| known == ip)),
+        };
+
+        let mut session_object_ids: Vec<ObjectId> = Vec::new();
+        let mut session_ids: Vec<String> = Vec::new();
+        // Every NetID in the matched sessions, for an IP it's whoever played from it
+        let mut session_players = net_ids.clone();
+        {
+            let sessions = lock(&self.sessions);
+            for session in sessions.iter().filter(|session| matches_target(session)) {
+                session_object_ids.extend(session.get_object_id("_id").ok());
+                if let Some(session_id) =
+                    collector(session).and_then(|collector| collector.get_str("SessionID").ok())
+                {
+                    session_ids.push(session_id.to_string());
+                }
+                for net_id in privacy::session_net_ids(session) {
+                    if !session_players.contains(&net_id) {
+                        session_players.push(net_id);
+                    }
+                }
+            }
+        }
+        // Only the target's own NetIDs are replaced in sessions shared with other players
+        let erased_net_ids = match &request.target {
+            ErasureTarget::NetId(_) => net_ids.clone(),
+            ErasureTarget::Ip(_) => session_players,
+        };
+
+        let is_session = |session: &Document| {
+            session
+                .get_object_id("_id")
+                .is_ok_and(|id| session_object_ids.contains(&id))
+        };
+        let related = |document: &Document| {
+            document
+                .get_str("NetID")
+                .is_ok_and(|net_id| net_ids.iter().any(|known| known == net_id))
+                || document
+                    .get_str("SessionID")
+                    .is_ok_and(|session_id| session_ids.iter().any(|known| known == session_id))
+        };
+        let is_feedback = |comment: &Document| {
+            comment
+                .get_str("NetID")
+                .is_ok_and(|net_id| net_ids.iter().any(|known| known == net_id))
+                || comment
+                    .get_object_id("SessionObjectID")
+                    .is_ok_and(|id| session_object_ids.contains(&id))
+        };
+
+        // Logs and minidumps can't be scrubbed, so they go in both modes
+        let crashes: Vec<Document> = lock(&self.crashes)
+            .iter()
+            .filter(|crash| related(crash))
+            .cloned()
+            .collect();
+        for crash in &crashes {
+            self.delete_crash_attachments(crash).await?;
+        }
+
+        // Stats here are worked out from the sessions, so unlike the mongo rollups they lose
+        // whatever is erased
+        match request.mode {
+            ErasureMode::Delete => {
+                report.sessions = remove_where(&mut lock(&self.sessions), is_session);
+                report.feedback = remove_where(&mut lock(&self.feedback), is_feedback);
+                report.crashes = remove_where(&mut lock(&self.crashes), related);
+                report.events = remove_where(&mut lock(&self.events), related);
+                report.positions = remove_where(&mut lock(&self.positions), related);
+                report.live_sessions = remove_where(&mut lock(&self.live_sessions), related);
+            }
+            ErasureMode::Anonymize => {
+                // Random, so the records of the player stay together without pointing back at them
+                let pseudonym = format!("erased-{}", ObjectId::new().to_hex());
+                let set_net_id = doc! {"NetID": &pseudonym};
+
+                for session in lock(&self.sessions)
+                    .iter_mut()
+                    .filter(|session| is_session(session))
+                {
+                    anonymize_session(session, &erased_net_ids, &pseudonym);
+                    report.sessions += 1;
+                }
+                report.feedback = update_where(
+                    &mut lock(&self.feedback),
+                    is_feedback,
+                    doc! {"NetID": &pseudonym, "Comment": privacy::ERASED_COMMENT},
+                );
+                report.crashes = update_where(
+                    &mut lock(&self.crashes),
+                    related,
+                    doc! {"NetID": &pseudonym, "Attachments": []},
+                );
+                report.events = update_where(&mut lock(&self.events), related, set_net_id.clone());
+                report.positions =
+                    update_where(&mut lock(&self.positions), related, set_net_id.clone());
+                report.live_sessions =
+                    update_where(&mut lock(&self.live_sessions), related, set_net_id);
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+fn with_new_id(mut document: Document) -> Document {
+    document.insert("_id", ObjectId::new());
+    document
+}
+
+fn has_id(document: &Document, id: ObjectId) -> bool {
+    document.get_object_id("_id") == Ok(id)
+}
+
+fn has_signature(document: &Document, signature: &str) -> bool {
+    document.get_str("Signature") == Ok(signature)
+}
+
+// Returns how many documents were removed
+fn remove_where(documents: &mut Vec<Document>, matches: impl Fn(&Document) -> bool) -> u64 {
+    let before = documents.len();
+    documents.retain(|document| !matches(document));
+    (before - documents.len()) as u64
+}
+
+// Sets the fields of `set` in every matching document, returns how many there were
+fn update_where(
+    documents: &mut [Document],
+    matches: impl Fn(&Document) -> bool,
+    set: Document,
+) -> u64 {
+    let mut updated = 0;
+    for document in documents.iter_mut().filter(|document| matches(document)) {
+        document.extend(set.clone());
+        updated += 1;
+    }
+    updated
+}
+
+// Like a mongo limit, where 0 means no limit
+fn limit_to_usize(limit: i64) -> usize {
+    match limit.unsigned_abs() {
+        0 => usize::MAX,
+        limit => limit as usize,
+    }
+}
+
+// Equality on top level fields, like a mongo filter without operators
+fn matches_fields(document: &Document, fields: &Document) -> bool {
+    fields
+        .iter()
+        .all(|(key, value)| document.get(key) == Some(value))
+}
+
+// Follows a dotted field path through nested documents
+fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let (first, rest) = match path.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (path, None),
+    };
+
+    match (document.get(first)?, rest) {
+        (value, None) => Some(value),
+        (Bson::Document(document), Some(rest)) => get_path(document, rest),
+        _ => None,
+    }
+}
+
+fn collector(session: &Document) -> Option<&Document> {
+    session.get_document(SESSION_COLLECTOR).ok()
+}
+
+fn get_f64(document: &Document, key: &str) -> Option<f64> {
+    match document.get(key)? {
+        Bson::Double(value) => Some(*value),
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+// Same checks as StatsFilter::session_filter, with exact dates. Stats worked out from rollups go
+// through rollups::session_totals instead, which widens them to whole days.
+fn matches_filter(filter: &StatsFilter, session: &Document) -> bool {
+    let collector = match collector(session) {
+        Some(collector) => collector,
+        None => return false,
+    };
+    let get_str = |key: &str| collector.get_str(key).ok();
+
+    if filter.build_version.is_some() && get_str("BuildVersion") != filter.build_version.as_deref()
+    {
+        return false;
+    }
+    if filter.platform.is_some() && get_str("Platform") != filter.platform.as_deref() {
+        return false;
+    }
+
+    if filter.from.is_some() || filter.to.is_some() {
+        let start_time = match get_chrono(collector, "StartTime") {
+            Some(start_time) => start_time,
+            None => return false,
+        };
+        if filter.from.is_some_and(|from| start_time < from)
+            || filter.to.is_some_and(|to| start_time >= to)
+        {
+            return false;
+        }
+    }
+
+    if let Some(pie) = filter.pie {
+        if collector.get_bool("IsPlayInEditorSession").ok() != Some(pie) {
+            return false;
+        }
+    }
+    if let Some(country) = &filter.country {
+        if get_str("CountryCode") != Some(country.to_uppercase().as_str()) {
+            return false;
+        }
+    }
+    if let Some(steam) = filter.steam {
+        let has_steam = collector
+            .get_array("PlayerControllerData")
+            .ok()
+            .and_then(|player_controllers| player_controllers.first())
+            .and_then(|player_controller| player_controller.as_document())
+            .is_some_and(|player_controller| player_controller.contains_key("SteamAnalyticsData"));
+        if has_steam != steam {
+            return false;
+        }
+    }
+
+    true
+}
+
+// Same checks as opt_outs::anonymous_filter
+fn anonymous_matches_filter(filter: &StatsFilter, session: &AnonymousSession) -> bool {
+    let day = session.start_time.format(DAY_FORMAT).to_string();
+
+    rollups::day_matches(filter, &day)
+        && (filter.build_version.is_none() || session.build_version == filter.build_version)
+        && (filter.platform.is_none() || session.platform == filter.platform)
+        && filter
+            .pie
+            .is_none_or(|pie| session.is_play_in_editor_session == pie)
+}
+
+// Same checks as FeedbackQuery::to_match_document
+fn feedback_matches(query: &FeedbackQuery, comment: &Document) -> bool {
+    query
+        .status
+        .is_none_or(|status| comment.get_str("Status") == Ok(status.as_str()))
+        && query
+            .tag
+            .as_ref()
+            .is_none_or(|tag| database::get_strings(comment, "Tags").contains(tag))
+        && query
+            .assignee
+            .as_ref()
+            .is_none_or(|assignee| comment.get_str("Assignee") == Ok(assignee.as_str()))
+        && query.search.as_ref().is_none_or(|search| {
+            comment
+                .get_str("Comment")
+                .unwrap_or_default()
+                .to_lowercase()
+                .contains(&search.to_lowercase())
+        })
+}
+
+// Same checks as EventQuery::to_match_document
+fn event_matches(query: &EventQuery, event: &Document) -> bool {
+    let timestamp = get_chrono(event, "Timestamp");
+
+    query
+        .name
+        .as_ref()
+        .is_none_or(|name| event.get_str("Name") == Ok(name.as_str()))
+        && query
+            .build
+            .as_ref()
+            .is_none_or(|build| event.get_str("Build") == Ok(build.as_str()))
+        && query
+            .from
+            .is_none_or(|from| timestamp.is_some_and(|timestamp| timestamp >= from))
+        && query
+            .to
+            .is_none_or(|to| timestamp.is_some_and(|timestamp| timestamp < to))
+}
+
+// How many of the query's words are in `text`, or None if it isn't a hit. Words are matched as
+// case insensitive substrings, without the stemming mongo's $text does. Quotes are dropped and
+// -words have to be missing.
+fn search_score(query: &str, text: &str) -> Option<f64> {
+    let text = text.to_lowercase();
+    let mut score = 0.0;
+
+    for word in query.split_whitespace() {
+        let word = word.trim_matches('"').to_lowercase();
+        match word.strip_prefix('-') {
+            Some(excluded) if !excluded.is_empty() && text.contains(excluded) => return None,
+            Some(excluded) if !excluded.is_empty() => {}
+            _ if !word.is_empty() && text.contains(&word) => score += 1.0,
+            _ => {}
+        }
+    }
+
+    (score > 0.0).then_some(score)
+}
+
+// Same rules as the claim in Database::claim_feedback_gitlab_issue
+fn claim_gitlab_issue(document: Option<&mut Document>) -> bool {
+    let document = match document {
+        Some(document) => document,
+        None => return false,
+    };
+    let now = chrono::Utc::now();
+
+    let has_issue = !matches!(document.get("GitlabIssue"), None | Some(Bson::Null));
+    let is_pending = get_chrono(document, "GitlabIssuePending")
+        .is_some_and(|pending| pending >= now - GITLAB_CLAIM_EXPIRY);
+    if has_issue || is_pending {
+        return false;
+    }
+
+    document.insert("GitlabIssuePending", now);
+    true
+}
+
+// Same changes as Database::set_feedback_gitlab_issue, except the updated time
+fn set_gitlab_issue(
+    document: Option<&mut Document>,
+    issue: &GitlabIssue,
+) -> mongodb::error::Result<()> {
+    if let Some(document) = document {
+        document.insert("GitlabIssue", mongodb::bson::to_document(issue)?);
+        document.remove("GitlabIssuePending");
+    }
+    Ok(())
+}
+
+// Same changes as privacy::erase makes to a session of an anonymized player
+fn anonymize_session(session: &mut Document, net_ids: &[String], pseudonym: &str) {
+    if let Ok(collector) = session.get_document_mut(SESSION_COLLECTOR) {
+        collector.remove("ip");
+        let player_controllers = collector.get_array_mut("PlayerControllerData");
+        for player_controller in player_controllers.into_iter().flatten() {
+            if let Bson::Document(player_controller) = player_controller {
+                let erased = player_controller
+                    .get_str("NetID")
+                    .is_ok_and(|net_id| net_ids.iter().any(|known| known == net_id));
+                if erased {
+                    player_controller.insert("NetID", pseudonym);
+                    if player_controller.contains_key("SteamAnalyticsData") {
+                        player_controller.insert("SteamAnalyticsData", Document::new());
+                    }
+                }
+            }
+        }
+    }
+    if let Ok(feedback) = session.get_document_mut(FEEDBACK_COLLECTOR) {
+        if let Ok(comments) = feedback.get_array_mut("FeedbackComments") {
+            comments.fill(Bson::String(privacy::ERASED_COMMENT.to_string()));
+        }
+    }
+}
+
+// The part of Database::get_player_profile worked out for each session
+struct ProfileSession<'a> {
+    document: &'a Document,
+    collector: &'a Document,
+    start_time: Option<chrono::DateTime<chrono::Utc>>,
+    // Only set when both the start and end time are
+    play_time_ms: Option<i64>,
+    is_pie: bool,
+}
+
+impl<'a> ProfileSession<'a> {
+    fn new(document: &'a Document) -> Option<Self> {
+        let collector = collector(document)?;
+        let start_time = get_chrono(collector, "StartTime");
+        let play_time_ms = start_time
+            .zip(get_chrono(collector, "EndTime"))
+            .map(|(start_time, end_time)| (end_time - start_time).num_milliseconds());
+
+        Some(ProfileSession {
+            document,
+            collector,
+            start_time,
+            play_time_ms,
+            is_pie: collector.get_bool("IsPlayInEditorSession") == Ok(true),
+        })
+    }
+
+    fn get_str(&self, key: &str) -> Option<String> {
+        self.collector.get_str(key).ok().map(String::from)
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    async fn add_session(
+        &self,
+        session: &AnalyticsSession,
+    ) -> mongodb::error::Result<AddSessionResult> {
+        self.insert(&mut lock(&self.sessions), session)
+    }
+
+    async fn add_sessions(
+        &self,
+        sessions: &[AnalyticsSession],
+    ) -> mongodb::error::Result<Vec<Result<AddSessionResult, String>>> {
+        let mut stored = lock(&self.sessions);
+
+        Ok(sessions
+            .iter()
+            .map(|session| self.insert(&mut stored, session).map_err(|e| e.to_string()))
+            .collect())
+    }
+
+    async fn get_session_document(&self, id: ObjectId) -> mongodb::error::Result<Option<Document>> {
+        Ok(lock(&self.sessions)
+            .iter()
+            .find(|session| has_id(session, id))
+            .cloned())
+    }
+
+    // Same rule as opt_outs::count_if_anonymous
+    async fn count_if_anonymous(&self, session: &AnalyticsSession) -> mongodb::error::Result<bool> {
+        let is_opted_out = {
+            let opt_outs = lock(&self.opt_outs);
+            session
+                .collector
+                .player_controller_data
+                .iter()
+                .any(|player_controller| opt_outs.contains(&player_controller.net_id))
+        };
+        if session.has_consent() && !is_opted_out {
+            return Ok(false);
+        }
+
+        if let Some(key) = &session.idempotency_key {
+            if !lock(&self.anonymous_session_keys).insert(key.clone()) {
+                return Ok(true);
+            }
+        }
+
+        lock(&self.anonymous_sessions).push(AnonymousSession::new(session));
+        Ok(true)
+    }
+
+    async fn is_opted_out(&self, net_id: &str) -> mongodb::error::Result<bool> {
+        Ok(lock(&self.opt_outs).contains(net_id))
+    }
+
+    async fn opted_out(&self, net_ids: &[&str]) -> mongodb::error::Result<HashSet<String>> {
+        let opt_outs = lock(&self.opt_outs);
+        Ok(net_ids
+            .iter()
+            .filter(|net_id| opt_outs.contains(**net_id))
+            .map(|net_id| net_id.to_string())
+            .collect())
+    }
+
+    async fn register_opt_out(&self, net_id: &str) -> mongodb::error::Result<bool> {
+        Ok(lock(&self.opt_outs).insert(net_id.to_string()))
+    }
+
+    async fn withdraw_opt_out(&self, net_id: &str) -> mongodb::error::Result<bool> {
+        Ok(lock(&self.opt_outs).remove(net_id))
+    }
+
+    async fn touch_live_session(
+        &self,
+        event: &LiveSessionEvent,
+        cloudflare_info: &CloudflareInfo,
+    ) -> mongodb::error::Result<()> {
+        let mut live_sessions = lock(&self.live_sessions);
+        let now = mongodb::bson::DateTime::now();
+
+        let index = match live_sessions
+            .iter()
+            .position(|live_session| live_session.get_str("SessionID") == Ok(&event.session_id))
+        {
+            // Ended sessions stay ended, a late heartbeat shouldn't reopen them
+            Some(index)
+                if live_sessions[index].get_str("Status")
+                    == Ok(LiveSessionStatus::Ended.as_str()) =>
+            {
+                return Ok(())
+            }
+            Some(index) => index,
+            None => {
+                live_sessions.push(doc! {"SessionID": &event.session_id, "StartTime": now});
+                live_sessions.len() - 1
+            }
+        };
+
+        live_sessions[index].extend(doc! {
+            "Status": LiveSessionStatus::Active.as_str(),
+            "LastHeartbeat": now,
+            "NetID": &event.net_id,
+            "IsPlayInEditorSession": event.is_play_in_editor_session,
+            "BuildVersion": &event.build_version,
+            "Platform": &event.platform,
+            "CountryCode": &cloudflare_info.country,
+            "CountryName": cloudflare_info.get_country_name(),
+        });
+        Ok(())
+    }
+
+    async fn end_live_session(&self, session_id: &str) -> mongodb::error::Result<bool> {
+        let mut live_sessions = lock(&self.live_sessions);
+        let now = mongodb::bson::DateTime::now();
+
+        let live_session = live_sessions.iter_mut().find(|live_session| {
+            live_session.get_str("SessionID") == Ok(session_id)
+                && live_session.get_str("Status") != Ok(LiveSessionStatus::Ended.as_str())
+        });
+        match live_session {
+            Some(live_session) => {
+                live_session.extend(doc! {
+                    "Status": LiveSessionStatus::Ended.as_str(),
+                    "LastHeartbeat": now,
+                    "EndTime": now,
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn sweep_abandoned_sessions(
+        &self,
+        timeout: chrono::TimeDelta,
+    ) -> mongodb::error::Result<u64> {
+        let cutoff = chrono::Utc::now() - timeout;
+        let mut abandoned = 0;
+
+        for live_session in lock(&self.live_sessions).iter_mut() {
+            let last_heartbeat = match live_session.get("LastHeartbeat") {
+                Some(Bson::DateTime(last_heartbeat)) => *last_heartbeat,
+                _ => continue,
+            };
+            if live_session.get_str("Status") == Ok(LiveSessionStatus::Active.as_str())
+                && last_heartbeat.to_chrono() < cutoff
+            {
+                live_session.insert("Status", LiveSessionStatus::Abandoned.as_str());
+                live_session.insert("EndTime", last_heartbeat);
+                abandoned += 1;
+            }
+        }
+
+        Ok(abandoned)
+    }
+
+    async fn find_net_id_for_session(
+        &self,
+        session_id: &str,
+    ) -> mongodb::error::Result<Option<String>> {
+        let live_net_id = lock(&self.live_sessions)
+            .iter()
+            .find(|live_session| live_session.get_str("SessionID") == Ok(session_id))
+            .and_then(|live_session| live_session.get_str("NetID").ok().map(String::from));
+        if live_net_id.is_some() {
+            return Ok(live_net_id);
+        }
+
+        Ok(lock(&self.sessions)
+            .iter()
+            .find(|session| {
+                collector(session)
+                    .is_some_and(|collector| collector.get_str("SessionID") == Ok(session_id))
+            })
+            .and_then(|session| privacy::session_net_ids(session).into_iter().next()))
+    }
+
+    async fn get_players_stats(&self, filter: &StatsFilter) -> mongodb::error::Result<PlayerStats> {
+        let totals = rollups::session_totals(filter, lock(&self.sessions).iter());
+
+        let avg_play_time = if totals.timed_sessions > 0 {
+            chrono::TimeDelta::milliseconds(totals.play_time_ms / totals.timed_sessions as i64)
+        } else {
+            chrono::TimeDelta::zero()
+        };
+
+        let mut live_filter = doc! {"IsPlayInEditorSession": false};
+        live_filter.extend(filter.live_session_filter());
+        live_filter.insert("Status", LiveSessionStatus::Active.as_str());
+        let currently_playing = lock(&self.live_sessions)
+            .iter()
+            .filter(|live_session| matches_fields(live_session, &live_filter))
+            .count() as u64;
+
+        Ok(PlayerStats {
+            pie_sessions: totals.pie_sessions,
+            game_sessions: totals.game_sessions,
+            unique_players: totals.unique_players,
+            avg_play_time,
+            currently_playing,
+            play_time: rollups::play_time_distribution(&totals),
+            anonymous_sessions: opt_outs::anonymous_filter(filter).map(|_| {
+                lock(&self.anonymous_sessions)
+                    .iter()
+                    .filter(|session| anonymous_matches_filter(filter, session))
+                    .count() as u64
+            }),
+        })
+    }
+
+    async fn get_play_time_distribution(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<PlayTimeDistribution> {
+        let totals = rollups::session_totals(filter, lock(&self.sessions).iter());
+        Ok(rollups::play_time_distribution(&totals))
+    }
+
+    async fn get_performance_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<PerformanceStats>> {
+        #[derive(Default)]
+        struct Group {
+            sessions: i64,
+            hitches: i64,
+            peak_memory: Vec<f64>,
+            histogram: HashMap<u64, (f64, i64)>,
+        }
+
+        let mut groups: HashMap<(Option<String>, Option<String>), Group> = HashMap::new();
+        for session in self.game_sessions(filter) {
+            let performance = match session.get_document(PERFORMANCE_COLLECTOR) {
+                Ok(performance) => performance,
+                Err(_) => continue,
+            };
+            let build_version = collector(&session)
+                .and_then(|collector| collector.get_str("BuildVersion").ok())
+                .map(String::from);
+            let hardware_class = performance.get_str("HardwareClass").ok().map(String::from);
+
+            let group = groups.entry((build_version, hardware_class)).or_default();
+            group.sessions += 1;
+            group.hitches += get_f64(performance, "HitchCount").unwrap_or(0.0) as i64;
+            group
+                .peak_memory
+                .extend(get_f64(performance, "PeakMemoryMB"));
+
+            let buckets = performance.get_array("FrameTimeHistogram");
+            for bucket in buckets.into_iter().flatten().filter_map(Bson::as_document) {
+                if let (Some(bound), Some(count)) =
+                    (get_f64(bucket, "UpperBoundMs"), get_f64(bucket, "Count"))
+                {
+                    // Keyed by the bits of the bound since f64 isn't Hash
+                    let entry = group.histogram.entry(bound.to_bits()).or_insert((bound, 0));
+                    entry.1 += count as i64;
+                }
+            }
+        }
+
+        let mut stats: Vec<PerformanceStats> = groups
+            .into_iter()
+            .map(|((build_version, hardware_class), group)| {
+                let mut histogram: Vec<(f64, i64)> = group.histogram.into_values().collect();
+                histogram.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                PerformanceStats {
+                    build_version,
+                    hardware_class,
+                    sessions: group.sessions,
+                    frames: histogram.iter().map(|(_, count)| count).sum(),
+                    p50_frame_time_ms: database::histogram_percentile(&histogram, 0.50),
+                    p95_frame_time_ms: database::histogram_percentile(&histogram, 0.95),
+                    p99_frame_time_ms: database::histogram_percentile(&histogram, 0.99),
+                    hitches_per_session: group.hitches as f64 / group.sessions.max(1) as f64,
+                    avg_peak_memory_mb: (!group.peak_memory.is_empty()).then(|| {
+                        group.peak_memory.iter().sum::<f64>() / group.peak_memory.len() as f64
+                    }),
+                    max_peak_memory_mb: group.peak_memory.iter().copied().reduce(f64::max),
+                }
+            })
+            .collect();
+
+        stats.sort_by(|a, b| {
+            (&b.build_version, &a.hardware_class).cmp(&(&a.build_version, &b.hardware_class))
+        });
+
+        Ok(stats)
+    }
+
+    async fn get_daily_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<DailyRollup>> {
+        Ok(rollups::session_daily(filter, lock(&self.sessions).iter()))
+    }
+
+    // Worked out on every call, so there's nothing to cache
+    async fn get_active_players(
+        &self,
+        filter: &StatsFilter,
+        _cache_ttl_secs: u64,
+    ) -> mongodb::error::Result<Vec<ActivePlayers>> {
+        Ok(reporting::session_active_players(
+            filter,
+            lock(&self.sessions).iter(),
+        ))
+    }
+
+    async fn get_retention_cohorts(
+        &self,
+        filter: &StatsFilter,
+        _cache_ttl_secs: u64,
+    ) -> mongodb::error::Result<Vec<RetentionCohort>> {
+        Ok(reporting::session_retention_cohorts(
+            filter,
+            lock(&self.sessions).iter(),
+        ))
+    }
+
+    // Same numbers as the pipeline in Database::get_player_profile
+    async fn get_player_profile(
+        &self,
+        net_id: &str,
+    ) -> mongodb::error::Result<Option<PlayerProfile>> {
+        if lock(&self.opt_outs).contains(net_id) {
+            return Ok(None);
+        }
+
+        let stored = lock(&self.sessions);
+        let mut sessions: Vec<ProfileSession> = stored
+            .iter()
+            .filter(|session| {
+                privacy::session_net_ids(session)
+                    .iter()
+                    .any(|id| id == net_id)
+            })
+            .filter_map(ProfileSession::new)
+            .collect();
+        if sessions.is_empty() {
+            return Ok(None);
+        }
+        sessions.sort_by_key(|session| session.start_time);
+
+        let mut countries = BTreeSet::new();
+        let mut builds = BTreeSet::new();
+        let mut platforms = BTreeSet::new();
+        for session in &sessions {
+            countries.extend(session.get_str("CountryName"));
+            builds.extend(session.get_str("BuildVersion"));
+            platforms.extend(session.get_str("Platform"));
+        }
+
+        let feedback = sessions
+            .iter()
+            .filter_map(|session| {
+                let comments = session
+                    .document
+                    .get_document(FEEDBACK_COLLECTOR)
+                    .and_then(|feedback| feedback.get_array("FeedbackComments"))
+                    .ok()
+                    .filter(|comments| !comments.is_empty())?;
+
+                Some(PlayerFeedback {
+                    session_id: session.get_str("SessionID"),
+                    time: session.start_time,
+                    comments: comments
+                        .iter()
+                        .filter_map(|comment| comment.as_str().map(String::from))
+                        .collect(),
+                })
+            })
+            .collect();
+
+        let recent_sessions = sessions
+            .iter()
+            .rev()
+            .take(PLAYER_RECENT_SESSIONS as usize)
+            .map(|session| PlayerSessionSummary {
+                id: session
+                    .document
+                    .get_object_id("_id")
+                    .map(|id| id.to_hex())
+                    .unwrap_or_default(),
+                session_id: session.get_str("SessionID"),
+                start_time: session.start_time,
+                play_time_secs: session.play_time_ms.map(|ms| ms / 1000),
+                build_version: session.get_str("BuildVersion"),
+                platform: session.get_str("Platform"),
+                country_code: session.get_str("CountryCode"),
+                is_play_in_editor_session: session.is_pie,
+            })
+            .collect();
+
+        let timed = || {
+            sessions
+                .iter()
+                .filter(|session| session.play_time_ms.is_some())
+        };
+        Ok(Some(PlayerProfile {
+            net_id: net_id.to_string(),
+            is_steam_player: sessions.iter().any(|session| {
+                session
+                    .collector
+                    .get_array("PlayerControllerData")
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Bson::as_document)
+                    .any(|player_controller| player_controller.contains_key("SteamAnalyticsData"))
+            }),
+            first_seen: timed().filter_map(|session| session.start_time).min(),
+            last_seen: timed()
+                .filter_map(|session| get_chrono(session.collector, "EndTime"))
+                .max(),
+            sessions: sessions.len() as u64,
+            pie_sessions: sessions.iter().filter(|session| session.is_pie).count() as u64,
+            total_play_time_secs: sessions
+                .iter()
+                .filter(|session| !session.is_pie)
+                .filter_map(|session| session.play_time_ms)
+                .sum::<i64>()
+                / 1000,
+            countries: countries.into_iter().collect(),
+            builds: builds.into_iter().collect(),
+            platforms: platforms.into_iter().collect(),
+            feedback,
+            recent_sessions,
+        }))
+    }
+
+    // Same paging as Database::search, see search_score for how text is matched
+    async fn search(
+        &self,
+        query: &SearchQuery,
+        limit: u64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<SearchHit>> {
+        let limit = limit.min(MAX_SEARCH_RESULTS.saturating_sub(skip));
+        if query.text.trim().is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut hits: Vec<SearchHit> = lock(&self.feedback)
+            .iter()
+            .filter_map(|comment| database::feedback_search_hit(comment, 0.0))
+            .collect();
+        hits.extend(
+            lock(&self.sessions)
+                .iter()
+                .filter_map(|session| database::session_search_hit(session, 0.0)),
+        );
+
+        let in_range = |time: Option<chrono::DateTime<chrono::Utc>>| {
+            (query.from.is_none() && query.to.is_none())
+                || time.is_some_and(|time| {
+                    query.from.is_none_or(|from| time >= from)
+                        && query.to.is_none_or(|to| time < to)
+                })
+        };
+        let mut hits: Vec<SearchHit> = hits
+            .into_iter()
+            .filter(|hit| in_range(hit.time))
+            .filter_map(|mut hit| {
+                hit.score = search_score(&query.text, &hit.text)?;
+                Some(hit)
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(hits
+            .into_iter()
+            .skip(skip as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn get_feedback(
+        &self,
+        query: &FeedbackQuery,
+        limit: i64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>> {
+        let mut comments: Vec<Document> = lock(&self.feedback)
+            .iter()
+            .filter(|comment| feedback_matches(query, comment))
+            .cloned()
+            .collect();
+        comments.sort_by(|a, b| {
+            get_chrono(b, "SessionTime")
+                .cmp(&get_chrono(a, "SessionTime"))
+                .then_with(|| get_number(a, "CommentIndex").cmp(&get_number(b, "CommentIndex")))
+        });
+
+        comments
+            .into_iter()
+            .skip(skip as usize)
+            .take(limit_to_usize(limit))
+            .map(|comment| Ok(mongodb::bson::from_document(comment)?))
+            .collect()
+    }
+
+    async fn get_feedback_item(
+        &self,
+        id: ObjectId,
+    ) -> mongodb::error::Result<Option<FeedbackItem>> {
+        let comment = lock(&self.feedback)
+            .iter()
+            .find(|comment| has_id(comment, id))
+            .cloned();
+
+        Ok(comment.map(mongodb::bson::from_document).transpose()?)
+    }
+
+    async fn get_session_feedback(
+        &self,
+        session_object_id: ObjectId,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>> {
+        let mut comments: Vec<Document> = lock(&self.feedback)
+            .iter()
+            .filter(|comment| comment.get_object_id("SessionObjectID") == Ok(session_object_id))
+            .cloned()
+            .collect();
+        comments.sort_by_key(|comment| get_number(comment, "CommentIndex"));
+
+        comments
+            .into_iter()
+            .map(|comment| Ok(mongodb::bson::from_document(comment)?))
+            .collect()
+    }
+
+    async fn update_feedback(
+        &self,
+        id: ObjectId,
+        update: &FeedbackUpdate,
+    ) -> mongodb::error::Result<Option<FeedbackItem>> {
+        let mut feedback = lock(&self.feedback);
+        let comment = match feedback.iter_mut().find(|comment| has_id(comment, id)) {
+            Some(comment) => comment,
+            None => return Ok(None),
+        };
+
+        if let Ok(set) = update.to_update_document().get_document("$set") {
+            comment.extend(set.clone());
+        }
+        Ok(Some(mongodb::bson::from_document(comment.clone())?))
+    }
+
+    async fn claim_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<bool> {
+        let mut feedback = lock(&self.feedback);
+        Ok(claim_gitlab_issue(
+            feedback.iter_mut().find(|comment| has_id(comment, id)),
+        ))
+    }
+
+    async fn release_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<()> {
+        if let Some(comment) = lock(&self.feedback)
+            .iter_mut()
+            .find(|comment| has_id(comment, id))
+        {
+            comment.remove("GitlabIssuePending");
+        }
+        Ok(())
+    }
+
+    async fn set_feedback_gitlab_issue(
+        &self,
+        id: ObjectId,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()> {
+        let mut feedback = lock(&self.feedback);
+        let comment = feedback.iter_mut().find(|comment| has_id(comment, id));
+        if let Some(comment) = comment {
+            comment.insert("UpdatedTime", mongodb::bson::DateTime::now());
+            set_gitlab_issue(Some(comment), issue)?;
+        }
+        Ok(())
+    }
+
+    // GridFS attachments are kept in memory with everything else
+    async fn store_crash_attachment(
+        &self,
+        config: &CrashReportConfig,
+        crash_id: &ObjectId,
+        kind: AttachmentKind,
+        file: &mut TempFile<'_>,
+    ) -> Result<Document, BoxError> {
+        match config.blob_storage {
+            BlobStorage::Disk => crash::store_on_disk(config, crash_id, kind, file).await,
+            BlobStorage::Gridfs => {
+                let size = file.len();
+                let bytes = crash::read_temp_file(file).await?;
+
+                let id = ObjectId::new();
+                lock(&self.crash_files).insert(id, bytes);
+
+                Ok(crash::attachment_document(
+                    kind,
+                    size,
+                    "gridfs",
+                    Bson::ObjectId(id),
+                ))
+            }
+        }
+    }
+
+    async fn delete_crash_attachments(&self, crash: &Document) -> Result<(), BoxError> {
+        let attachments = crash.get_array("Attachments").into_iter().flatten();
+        for attachment in attachments.filter_map(|attachment| attachment.as_document()) {
+            match (attachment.get_str("Storage"), attachment.get("Location")) {
+                (Ok("disk"), Some(Bson::String(path))) => crash::delete_from_disk(path).await?,
+                (Ok("gridfs"), Some(Bson::ObjectId(id))) => {
+                    lock(&self.crash_files).remove(id);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // Same changes as the upsert in Database::add_crash
+    async fn add_crash(&self, crash: Document) -> mongodb::error::Result<CrashGroup> {
+        let signature = crash.get_str("Signature").unwrap_or_default().to_string();
+        let now = mongodb::bson::DateTime::now();
+
+        let mut groups = lock(&self.crash_groups);
+        let index = match groups
+            .iter()
+            .position(|group| has_signature(group, &signature))
+        {
+            Some(index) => index,
+            None => {
+                groups.push(doc! {
+                    "Signature": &signature,
+                    "Count": 0_i64,
+                    "FirstSeen": now,
+                    "CrashType": crash.get("CrashType"),
+                    "ErrorMessage": crash.get("ErrorMessage"),
+                    "SignatureFrames": crash.get("SignatureFrames"),
+                    "BuildVersions": [],
+                });
+                groups.len() - 1
+            }
+        };
+
+        let group = &mut groups[index];
+        group.insert("Count", get_number(group, "Count").unwrap_or(0) + 1);
+        group.insert("LastSeen", now);
+        group.insert("LastCrashID", crash.get_object_id("_id").ok());
+        if let (Ok(build_version), Ok(build_versions)) = (
+            crash.get_str("BuildVersion"),
+            group.get_array_mut("BuildVersions"),
+        ) {
+            let build_version = Bson::String(build_version.to_string());
+            if !build_versions.contains(&build_version) {
+                build_versions.push(build_version);
+            }
+        }
+        let group = mongodb::bson::from_document(group.clone())?;
+
+        lock(&self.crashes).push(crash);
+        Ok(group)
+    }
+
+    async fn get_crash_groups(&self, limit: i64) -> mongodb::error::Result<Vec<CrashGroup>> {
+        let mut groups = lock(&self.crash_groups).clone();
+        groups.sort_by_key(|group| std::cmp::Reverse(get_number(group, "Count")));
+
+        groups
+            .into_iter()
+            .take(limit_to_usize(limit))
+            .map(|group| Ok(mongodb::bson::from_document(group)?))
+            .collect()
+    }
+
+    async fn get_crash_group(&self, signature: &str) -> mongodb::error::Result<Option<CrashGroup>> {
+        let group = lock(&self.crash_groups)
+            .iter()
+            .find(|group| has_signature(group, signature))
+            .cloned();
+
+        Ok(group.map(mongodb::bson::from_document).transpose()?)
+    }
+
+    async fn claim_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<bool> {
+        let mut groups = lock(&self.crash_groups);
+        Ok(claim_gitlab_issue(
+            groups
+                .iter_mut()
+                .find(|group| has_signature(group, signature)),
+        ))
+    }
+
+    async fn release_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<()> {
+        if let Some(group) = lock(&self.crash_groups)
+            .iter_mut()
+            .find(|group| has_signature(group, signature))
+        {
+            group.remove("GitlabIssuePending");
+        }
+        Ok(())
+    }
+
+    async fn set_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()> {
+        let mut groups = lock(&self.crash_groups);
+        set_gitlab_issue(
+            groups
+                .iter_mut()
+                .find(|group| has_signature(group, signature)),
+            issue,
+        )
+    }
+
+    async fn add_events(&self, events: &[GameplayEvent]) -> mongodb::error::Result<u64> {
+        let documents = events
+            .iter()
+            .map(|event| event.to_document())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stored = lock(&self.events);
+        stored.extend(documents.into_iter().map(with_new_id));
+        Ok(events.len() as u64)
+    }
+
+    async fn count_events(&self, query: &EventQuery) -> mongodb::error::Result<u64> {
+        Ok(lock(&self.events)
+            .iter()
+            .filter(|event| event_matches(query, event))
+            .count() as u64)
+    }
+
+    async fn group_events(
+        &self,
+        query: &EventQuery,
+        grouping: &EventGrouping,
+    ) -> mongodb::error::Result<Vec<EventCount>> {
+        let field_path = grouping.field_path();
+        let path = field_path.trim_start_matches('$');
+
+        // Bson isn't Hash, and there are only ever a handful of groups
+        let mut groups: Vec<(Bson, i64)> = Vec::new();
+        for event in lock(&self.events)
+            .iter()
+            .filter(|event| event_matches(query, event))
+        {
+            let key = get_path(event, path).cloned().unwrap_or(Bson::Null);
+            match groups.iter_mut().find(|(group, _)| *group == key) {
+                Some((_, count)) => *count += 1,
+                None => groups.push((key, 1)),
+            }
+        }
+        groups.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+        });
+
+        Ok(groups
+            .into_iter()
+            .map(|(key, count)| EventCount {
+                key: key.into_relaxed_extjson(),
+                count,
+            })
+            .collect())
+    }
+
+    // Same players as Database::get_funnel
+    async fn get_funnel(
+        &self,
+        query: &FunnelQuery,
+    ) -> mongodb::error::Result<Vec<FunnelStepResult>> {
+        let names: HashSet<&str> = query.steps.iter().map(|step| step.name.as_str()).collect();
+        let events_query = EventQuery {
+            name: None,
+            ..query.events.clone()
+        };
+
+        let mut players: BTreeMap<String, Vec<FunnelEvent>> = BTreeMap::new();
+        for event in lock(&self.events)
+            .iter()
+            .filter(|event| event_matches(&events_query, event))
+        {
+            let (name, timestamp) = match (event.get_str("Name"), get_chrono(event, "Timestamp")) {
+                (Ok(name), Some(timestamp)) if names.contains(name) => (name, timestamp),
+                _ => continue,
+            };
+            let player = event
+                .get_str("NetID")
+                .or_else(|_| event.get_str("SessionID"))
+                .unwrap_or("");
+
+            players
+                .entry(player.to_string())
+                .or_default()
+                .push(FunnelEvent {
+                    name: name.to_string(),
+                    timestamp,
+                    properties: event
+                        .get_document("Properties")
+                        .cloned()
+                        .unwrap_or_default(),
+                });
+        }
+
+        let mut counts = query.counts();
+        for events in players.values_mut() {
+            events.sort_by_key(|event| event.timestamp);
+            counts.add_player(events);
+        }
+
+        Ok(counts.results())
+    }
+
+    async fn add_positions(&self, samples: &[PositionSample]) -> mongodb::error::Result<u64> {
+        let mut stored = lock(&self.positions);
+        stored.extend(
+            samples
+                .iter()
+                .map(|sample| with_new_id(sample.to_document())),
+        );
+        Ok(samples.len() as u64)
+    }
+
+    async fn get_position_bounds(
+        &self,
+        map: &str,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<Option<config::MapBounds>> {
+        let filter = Database::position_filter(map, event_type);
+        let positions = lock(&self.positions);
+
+        let mut bounds: Option<config::MapBounds> = None;
+        for (x, y) in positions
+            .iter()
+            .filter(|position| matches_fields(position, &filter))
+            .filter_map(|position| Some((get_f64(position, "X")?, get_f64(position, "Y")?)))
+        {
+            let bounds = bounds.get_or_insert(config::MapBounds {
+                min_x: x,
+                max_x: x,
+                min_y: y,
+                max_y: y,
+            });
+            bounds.min_x = bounds.min_x.min(x);
+            bounds.max_x = bounds.max_x.max(x);
+            bounds.min_y = bounds.min_y.min(y);
+            bounds.max_y = bounds.max_y.max(y);
+        }
+
+        Ok(bounds)
+    }
+
+    // Same binning as Database::bin_positions
+    async fn bin_positions(
+        &self,
+        grid: &mut HeatmapGrid,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<()> {
+        let bounds = grid.bounds;
+        let (cell_width, cell_height) = grid.cell_size();
+        let filter = Database::position_filter(&grid.map, event_type);
+
+        let positions = lock(&self.positions);
+        for (x, y) in positions
+            .iter()
+            .filter(|position| matches_fields(position, &filter))
+            .filter_map(|position| Some((get_f64(position, "X")?, get_f64(position, "Y")?)))
+        {
+            if x < bounds.min_x || x > bounds.max_x || y < bounds.min_y || y > bounds.max_y {
+                continue;
+            }
+
+            grid.add(
+                ((x - bounds.min_x) / cell_width).floor() as i64,
+                ((y - bounds.min_y) / cell_height).floor() as i64,
+                1,
+            );
+        }
+
+        Ok(())
+    }
+
+    // Same audit record as privacy::erase
+    async fn erase(
+        &self,
+        _config: &config::Config,
+        secrets: &Secrets,
+        request: &ErasureRequest,
+    ) -> Result<ErasureReport, BoxError> {
+        let salt = &secrets.keys.ip_hash_salt;
+        let id = ObjectId::new();
+        lock(&self.erasure_requests).push(doc! {
+            "_id": id,
+            "Target": request.target.kind(),
+            "TargetHash": privacy::keyed_hash(salt, &request.target.value()),
+            "Mode": request.mode.as_str(),
+            "RequestedBy": &request.requested_by,
+            "RequestedAt": mongodb::bson::DateTime::now(),
+            "Status": "running",
+        });
+
+        let result = self.erase_records(salt, request).await;
+        let status = match &result {
+            Ok(report) => doc! {
+                "Status": "completed",
+                "CompletedAt": mongodb::bson::DateTime::now(),
+                "Report": mongodb::bson::to_document(report)?,
+            },
+            Err(e) => doc! {"Status": "failed", "Error": e.to_string()},
+        };
+        if let Some(audit) = lock(&self.erasure_requests)
+            .iter_mut()
+            .find(|audit| has_id(audit, id))
+        {
+            audit.extend(status);
+        }
+
+        result
+    }
+
+    // Nothing outlives a restart, so nothing gets old enough to prune
+    async fn prune_sessions(&self, _config: &RetentionConfig) -> Result<RetentionReport, BoxError> {
+        Ok(RetentionReport::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rocket::serde::json::json;
+
+    fn session(
+        build_version: &str,
+        start_time: &str,
+        end_time: &str,
+        pie: bool,
+    ) -> AnalyticsSession {
+        AnalyticsSession::from_value(json!({
+            SESSION_COLLECTOR: {
+                "BuildVersion": build_version,
+                "StartTime": start_time,
+                "EndTime": end_time,
+                "IsPlayInEditorSession": pie,
+                "PlayerControllerData": [{"NetID": "player"}],
+            },
+        }))
+        .unwrap()
+    }
+
+    fn game_session(ip: &str) -> AnalyticsSession {
+        let mut session = session("1.0", "2024.03.01-10.00.00", "2024.03.01-10.30.00", false);
+        session.collector.ip = Some(ip.to_string());
+        session
+    }
+
+    fn players(net_id: &str, steam: bool) -> Vec<crate::session::PlayerControllerData> {
+        let mut player = json!({"NetID": net_id});
+        if steam {
+            player["SteamAnalyticsData"] = json!({"SteamID": "76561197960287930"});
+        }
+        vec![rocket::serde::json::serde_json::from_value(player).unwrap()]
+    }
+
+    fn date(day: u32) -> Option<chrono::DateTime<chrono::Utc>> {
+        Some(chrono::Utc.with_ymd_and_hms(2024, 3, day, 0, 0, 0).unwrap())
+    }
+
+    #[rocket::async_test]
+    async fn add_session_counts_towards_stats() {
+        let storage = MemoryStorage::new();
+        let mut long_session = game_session("b");
+        long_session.collector.end_time += chrono::TimeDelta::minutes(30);
+        let mut pie_session = game_session("a");
+        pie_session.collector.is_play_in_editor_session = true;
+
+        for session in [game_session("a"), long_session, pie_session] {
+            let result = storage.add_session(&session).await.unwrap();
+            assert!(matches!(result, AddSessionResult::Inserted(_)));
+        }
+
+        let stats = storage
+            .get_players_stats(&StatsFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.game_sessions, 2);
+        assert_eq!(stats.pie_sessions, 1);
+        assert_eq!(stats.unique_players, 2);
+        assert_eq!(stats.avg_play_time, chrono::TimeDelta::minutes(45));
+        assert_eq!(stats.play_time.sessions, 2);
+        assert_eq!(stats.anonymous_sessions, Some(0));
+    }
+
+    #[rocket::async_test]
+    async fn duplicate_idempotency_key_returns_original() {
+        let storage = MemoryStorage::new();
+        let mut session = game_session("a");
+        session.set_idempotency_key(Some("key".to_string()));
+
+        let first = storage.add_session(&session).await.unwrap();
+        let retry = storage.add_session(&session).await.unwrap();
+        assert!(matches!(first, AddSessionResult::Inserted(_)));
+        assert_eq!(retry, AddSessionResult::Duplicate(first.id()));
+
+        let batch = storage
+            .add_sessions(&[session.clone(), game_session("b")])
+            .await
+            .unwrap();
+        assert_eq!(batch[0], Ok(AddSessionResult::Duplicate(first.id())));
+        assert!(matches!(batch[1], Ok(AddSessionResult::Inserted(_))));
+
+        let stats = storage
+            .get_players_stats(&StatsFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.game_sessions, 2);
+    }
+
+    #[rocket::async_test]
+    async fn stats_filters() {
+        let storage = MemoryStorage::new();
+
+        let mut new_build = session("1.1", "2024.03.02-10.00.00", "2024.03.02-11.00.00", false);
+        new_build.collector.country_code = Some("NL".to_string());
+        let mut steam = session("1.0", "2024.03.03-10.00.00", "2024.03.03-11.00.00", false);
+        steam.collector.player_controller_data = players("steam_player", true);
+        let pie = session("1.1", "2024.03.03-12.00.00", "2024.03.03-13.00.00", true);
+
+        for session in [game_session("a"), new_build, steam, pie] {
+            storage.add_session(&session).await.unwrap();
+        }
+
+        let count = |filter: StatsFilter| {
+            let storage = &storage;
+            async move {
+                let stats = storage.get_players_stats(&filter).await.unwrap();
+                (stats.game_sessions, stats.pie_sessions)
+            }
+        };
+
+        let build = |build_version: &str| StatsFilter {
+            build_version: Some(build_version.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(count(build("1.0")).await, (2, 0));
+        assert_eq!(count(build("1.1")).await, (1, 1));
+
+        let dates = StatsFilter {
+            from: date(2),
+            to: date(3),
+            ..Default::default()
+        };
+        assert_eq!(count(dates).await, (1, 0));
+
+        let country = StatsFilter {
+            country: Some("nl".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(count(country).await, (1, 0));
+
+        let pie = StatsFilter {
+            pie: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(count(pie).await, (0, 1));
+
+        let steam = StatsFilter {
+            steam: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(count(steam).await, (1, 0));
+    }
+
+    #[rocket::async_test]
+    async fn stats_dates_cover_whole_days_like_the_rollups() {
+        let storage = MemoryStorage::new();
+        storage.add_session(&game_session("a")).await.unwrap();
+        let mut anonymous = game_session("b");
+        anonymous.collector.telemetry_consent = Some(false);
+        storage.count_if_anonymous(&anonymous).await.unwrap();
+
+        let at = |hour: u32| {
+            Some(
+                chrono::Utc
+                    .with_ymd_and_hms(2024, 3, 1, hour, 0, 0)
+                    .unwrap(),
+            )
+        };
+        let count = |from, to| {
+            let storage = &storage;
+            async move {
+                let filter = StatsFilter {
+                    from,
+                    to,
+                    ..Default::default()
+                };
+                let stats = storage.get_players_stats(&filter).await.unwrap();
+                let play_time = storage.get_play_time_distribution(&filter).await.unwrap();
+                (
+                    stats.game_sessions,
+                    play_time.sessions,
+                    stats.anonymous_sessions,
+                )
+            }
+        };
+
+        // The sessions started at 10:00
+        assert_eq!(count(at(12), None).await, (1, 1, Some(1)));
+        assert_eq!(count(None, at(9)).await, (1, 1, Some(1)));
+        assert_eq!(count(None, at(0)).await, (0, 0, Some(0)));
+        assert_eq!(count(date(2), None).await, (0, 0, Some(0)));
+    }
+
+    #[rocket::async_test]
+    async fn sessions_without_consent_are_only_counted() {
+        let storage = MemoryStorage::new();
+        let mut session = game_session("a");
+        session.collector.telemetry_consent = Some(false);
+
+        assert!(storage.count_if_anonymous(&session).await.unwrap());
+        assert!(!storage
+            .count_if_anonymous(&game_session("b"))
+            .await
+            .unwrap());
+
+        // A retry is answered the same way but not counted again
+        session.set_idempotency_key(Some("key".to_string()));
+        assert!(storage.count_if_anonymous(&session).await.unwrap());
+        assert!(storage.count_if_anonymous(&session).await.unwrap());
+
+        let stats = storage
+            .get_players_stats(&StatsFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.anonymous_sessions, Some(2));
+        assert_eq!(stats.game_sessions, 0);
+
+        // Anonymous counts don't know the country
+        let country = StatsFilter {
+            country: Some("NL".to_string()),
+            ..Default::default()
+        };
+        let stats = storage.get_players_stats(&country).await.unwrap();
+        assert_eq!(stats.anonymous_sessions, None);
+    }
+
+    #[rocket::async_test]
+    async fn opted_out_players_are_only_counted() {
+        let storage = MemoryStorage::new();
+
+        assert!(storage.register_opt_out("player").await.unwrap());
+        assert!(!storage.register_opt_out("player").await.unwrap());
+        assert!(storage
+            .count_if_anonymous(&game_session("a"))
+            .await
+            .unwrap());
+
+        assert!(storage.withdraw_opt_out("player").await.unwrap());
+        assert!(!storage.withdraw_opt_out("player").await.unwrap());
+        assert!(!storage
+            .count_if_anonymous(&game_session("a"))
+            .await
+            .unwrap());
+
+        let filter = StatsFilter {
+            build_version: Some("1.0".to_string()),
+            ..Default::default()
+        };
+        let stats = storage.get_players_stats(&filter).await.unwrap();
+        assert_eq!(stats.anonymous_sessions, Some(1));
+    }
+
+    fn feedback_session(net_id: &str, comments: &[&str]) -> AnalyticsSession {
+        AnalyticsSession::from_value(json!({
+            SESSION_COLLECTOR: {
+                "SessionID": format!("{}-session", net_id),
+                "BuildVersion": "1.0",
+                "StartTime": "2024.03.01-10.00.00",
+                "EndTime": "2024.03.01-10.30.00",
+                "IsPlayInEditorSession": false,
+                "PlayerControllerData": [{"NetID": net_id}],
+            },
+            FEEDBACK_COLLECTOR: {"FeedbackComments": comments},
+        }))
+        .unwrap()
+    }
+
+    fn event(name: &str, net_id: &str, time: &str, level: i64) -> GameplayEvent {
+        GameplayEvent::from_value(
+            json!({
+                "Name": name,
+                "Timestamp": time,
+                "SessionID": format!("{}-session", net_id),
+                "NetID": net_id,
+                "Build": "1.0",
+                "Properties": {"Level": level},
+            }),
+            "event",
+        )
+        .unwrap()
+    }
+
+    fn position(x: f64, y: f64) -> PositionSample {
+        PositionSample {
+            session_id: "session".to_string(),
+            net_id: None,
+            map: "Arena".to_string(),
+            event_type: "Death".to_string(),
+            x,
+            y,
+            z: 0.0,
+            timestamp: None,
+            telemetry_consent: None,
+        }
+    }
+
+    fn live_event(session_id: &str) -> LiveSessionEvent {
+        LiveSessionEvent {
+            session_id: session_id.to_string(),
+            net_id: Some("player".to_string()),
+            is_play_in_editor_session: false,
+            build_version: Some("1.0".to_string()),
+            platform: None,
+            telemetry_consent: None,
+        }
+    }
+
+    fn cloudflare_info() -> CloudflareInfo {
+        CloudflareInfo {
+            ip: "127.0.0.1".parse().unwrap(),
+            country: "NL".to_string(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn feedback_is_triaged_and_claimed_once() {
+        let storage = MemoryStorage::new();
+        let result = storage
+            .add_session(&feedback_session("player", &["too hard", "", "laggy"]))
+            .await
+            .unwrap();
+
+        let feedback = storage
+            .get_feedback(&FeedbackQuery::default(), 0, 0)
+            .await
+            .unwrap();
+        assert_eq!(feedback.len(), 2);
+        assert_eq!(feedback[0].comment, "too hard");
+        assert_eq!(
+            storage
+                .get_session_feedback(result.id())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let id = feedback[1].id;
+        let update = FeedbackUpdate {
+            status: Some(feedback::FeedbackStatus::Acknowledged),
+            tags: Some(vec![" Network ".to_string()]),
+            assignee: None,
+        };
+        let updated = storage.update_feedback(id, &update).await.unwrap().unwrap();
+        assert_eq!(updated.tags, vec!["network"]);
+
+        let query = FeedbackQuery {
+            status: Some(feedback::FeedbackStatus::Acknowledged),
+            search: Some("LAG".to_string()),
+            ..Default::default()
+        };
+        let found = storage.get_feedback(&query, 0, 0).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, id);
+
+        assert!(storage.claim_feedback_gitlab_issue(id).await.unwrap());
+        assert!(!storage.claim_feedback_gitlab_issue(id).await.unwrap());
+        storage.release_feedback_gitlab_issue(id).await.unwrap();
+        assert!(storage.claim_feedback_gitlab_issue(id).await.unwrap());
+
+        let issue = GitlabIssue {
+            iid: 7,
+            web_url: "https://gitlab.example/issues/7".to_string(),
+        };
+        storage.set_feedback_gitlab_issue(id, &issue).await.unwrap();
+        assert!(!storage.claim_feedback_gitlab_issue(id).await.unwrap());
+        let item = storage.get_feedback_item(id).await.unwrap().unwrap();
+        assert_eq!(item.gitlab_issue.map(|issue| issue.iid), Some(7));
+    }
+
+    #[rocket::async_test]
+    async fn events_are_grouped_and_counted_in_funnels() {
+        let storage = MemoryStorage::new();
+        let events = [
+            event("Start", "a", "2024.03.01-10.00.00", 1),
+            event("Finish", "a", "2024.03.01-10.05.00", 1),
+            event("Start", "b", "2024.03.01-10.00.00", 2),
+            event("Start", "c", "2024.03.02-10.00.00", 1),
+        ];
+        assert_eq!(storage.add_events(&events).await.unwrap(), 4);
+
+        let query = EventQuery {
+            to: date(2),
+            ..Default::default()
+        };
+        assert_eq!(storage.count_events(&query).await.unwrap(), 3);
+
+        let groups = storage
+            .group_events(
+                &EventQuery::default(),
+                &EventGrouping::Property("Level".to_string()),
+            )
+            .await
+            .unwrap();
+        let groups: Vec<(String, i64)> = groups
+            .into_iter()
+            .map(|group| (group.key.to_string(), group.count))
+            .collect();
+        assert_eq!(groups, vec![("1".to_string(), 3), ("2".to_string(), 1)]);
+
+        let funnel = FunnelQuery {
+            steps: crate::funnel::FunnelStep::parse_list("Start,Finish").unwrap(),
+            events: query,
+            window: None,
+        };
+        let results = storage.get_funnel(&funnel).await.unwrap();
+        assert_eq!(results[0].players, 2);
+        assert_eq!(results[1].players, 1);
+    }
+
+    #[rocket::async_test]
+    async fn positions_are_binned_within_bounds() {
+        let storage = MemoryStorage::new();
+        let samples = [position(0.0, 0.0), position(9.0, 9.0), position(20.0, 0.0)];
+        storage.add_positions(&samples).await.unwrap();
+
+        let bounds = storage
+            .get_position_bounds("Arena", Some("Death"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((bounds.min_x, bounds.max_x), (0.0, 20.0));
+        assert!(storage
+            .get_position_bounds("Lobby", None)
+            .await
+            .unwrap()
+            .is_none());
+
+        let bounds = config::MapBounds {
+            min_x: 0.0,
+            max_x: 10.0,
+            min_y: 0.0,
+            max_y: 10.0,
+        };
+        let mut grid = HeatmapGrid::new("Arena".to_string(), None, bounds, 2);
+        storage.bin_positions(&mut grid, None).await.unwrap();
+        assert_eq!(grid.total, 2);
+        assert_eq!(grid.cells, vec![1, 0, 0, 1]);
+    }
+
+    #[rocket::async_test]
+    async fn live_sessions_are_counted_until_they_end_or_go_quiet() {
+        let storage = MemoryStorage::new();
+        let info = cloudflare_info();
+        storage
+            .touch_live_session(&live_event("a"), &info)
+            .await
+            .unwrap();
+        storage
+            .touch_live_session(&live_event("b"), &info)
+            .await
+            .unwrap();
+
+        let stats = storage
+            .get_players_stats(&StatsFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.currently_playing, 2);
+
+        assert!(storage.end_live_session("a").await.unwrap());
+        assert!(!storage.end_live_session("a").await.unwrap());
+        // A late heartbeat doesn't reopen it
+        storage
+            .touch_live_session(&live_event("a"), &info)
+            .await
+            .unwrap();
+
+        // Everything is older than a cutoff in the future
+        let abandoned = storage
+            .sweep_abandoned_sessions(chrono::TimeDelta::seconds(-60))
+            .await
+            .unwrap();
+        assert_eq!(abandoned, 1);
+
+        let stats = storage
+            .get_players_stats(&StatsFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(stats.currently_playing, 0);
+        assert_eq!(
+            storage.find_net_id_for_session("b").await.unwrap(),
+            Some("player".to_string())
+        );
+    }
+
+    #[rocket::async_test]
+    async fn crashes_are_grouped_by_signature() {
+        let storage = MemoryStorage::new();
+        let crash = |signature: &str, build_version: &str| {
+            doc! {
+                "_id": ObjectId::new(),
+                "Signature": signature,
+                "CrashType": "Crash",
+                "SignatureFrames": [signature],
+                "BuildVersion": build_version,
+            }
+        };
+
+        storage.add_crash(crash("a", "1.0")).await.unwrap();
+        storage.add_crash(crash("b", "1.0")).await.unwrap();
+        let group = storage.add_crash(crash("b", "1.1")).await.unwrap();
+        assert_eq!(group.count, 2);
+        assert_eq!(group.build_versions, vec!["1.0", "1.1"]);
+
+        let groups = storage.get_crash_groups(1).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].signature, "b");
+
+        assert!(storage.claim_crash_group_gitlab_issue("a").await.unwrap());
+        assert!(!storage.claim_crash_group_gitlab_issue("a").await.unwrap());
+        assert!(!storage.claim_crash_group_gitlab_issue("c").await.unwrap());
+    }
+
+    #[rocket::async_test]
+    async fn erasing_a_player_leaves_other_players_alone() {
+        let storage = MemoryStorage::new();
+        storage
+            .add_session(&feedback_session("erased", &["my name is Alice"]))
+            .await
+            .unwrap();
+        storage
+            .add_session(&feedback_session("kept", &["fun"]))
+            .await
+            .unwrap();
+        storage
+            .add_events(&[event("Start", "erased", "2024.03.01-10.00.00", 1)])
+            .await
+            .unwrap();
+        storage
+            .touch_live_session(&live_event("erased-session"), &cloudflare_info())
+            .await
+            .unwrap();
+
+        let request = |mode| ErasureRequest {
+            target: ErasureTarget::NetId("erased".to_string()),
+            mode,
+            requested_by: "test".to_string(),
+        };
+
+        let report = storage
+            .erase_records("salt", &request(ErasureMode::Anonymize))
+            .await
+            .unwrap();
+        assert_eq!(report.sessions, 1);
+        assert_eq!(report.feedback, 1);
+        assert_eq!(report.events, 1);
+        assert_eq!(report.live_sessions, 1);
+        assert!(storage
+            .get_player_profile("erased")
+            .await
+            .unwrap()
+            .is_none());
+        let comments: Vec<String> = storage
+            .get_feedback(&FeedbackQuery::default(), 0, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.comment)
+            .collect();
+        assert!(comments.contains(&privacy::ERASED_COMMENT.to_string()));
+        assert!(comments.contains(&"fun".to_string()));
+
+        let report = storage
+            .erase_records("salt", &request(ErasureMode::Delete))
+            .await
+            .unwrap();
+        // Nothing points at the NetID any more
+        assert_eq!(report.sessions, 0);
+        assert_eq!(report.live_sessions, 0);
+
+        let profile = storage.get_player_profile("kept").await.unwrap().unwrap();
+        assert_eq!(profile.sessions, 1);
+        assert_eq!(profile.feedback[0].comments, vec!["fun"]);
+    }
+}
@@ -0,0 +1,540 @@
+mod memory;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use mongodb::bson::{oid::ObjectId, Document};
+use rocket::fs::TempFile;
+
+pub use memory::MemoryStorage;
+
+use crate::cloudflare::CloudflareInfo;
+use crate::config::{self, CrashReportConfig, RetentionConfig, Secrets, StorageBackend};
+use crate::crash::{self, AttachmentKind};
+use crate::database::{
+    self, AddSessionResult, CrashGroup, Database, EventCount, EventGrouping, EventQuery,
+    PerformanceStats, PlayTimeDistribution, PlayerProfile, PlayerStats, SearchHit, SearchQuery,
+    StatsFilter,
+};
+use crate::feedback::{FeedbackItem, FeedbackQuery, FeedbackUpdate};
+use crate::funnel::{FunnelQuery, FunnelStepResult};
+use crate::gameplay_event::GameplayEvent;
+use crate::gitlab::GitlabIssue;
+use crate::heatmap::{HeatmapGrid, PositionSample};
+use crate::migrations;
+use crate::opt_outs;
+use crate::privacy::{self, ErasureReport, ErasureRequest};
+use crate::reporting::{self, ActivePlayers, RetentionCohort};
+use crate::retention::{self, RetentionReport};
+use crate::rollups::{self, DailyRollup};
+use crate::session::{AnalyticsSession, LiveSessionEvent};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Everything the server records and everything it's asked about it: sessions and the stats worked
+// out from them, opt outs, live sessions, feedback, crashes, gameplay events and positions. Routes,
+// commands and background tasks only go through this, so any backend can stand in for mongo.
+#[rocket::async_trait]
+pub trait Storage: Send + Sync + std::fmt::Debug {
+    // Gets the store ready for use, like creating indexes and fixing up old documents
+    async fn migrate(&self) -> Result<(), BoxError>;
+
+    async fn add_session(
+        &self,
+        session: &AnalyticsSession,
+    ) -> mongodb::error::Result<AddSessionResult>;
+
+    // One entry per session, with either the id it was stored under or why it couldn't be stored
+    async fn add_sessions(
+        &self,
+        sessions: &[AnalyticsSession],
+    ) -> mongodb::error::Result<Vec<Result<AddSessionResult, String>>>;
+
+    async fn get_session_document(&self, id: ObjectId) -> mongodb::error::Result<Option<Document>>;
+
+    // Counts the session instead of it being stored when the player turned telemetry off, see
+    // opt_outs. Returns whether it was counted.
+    async fn count_if_anonymous(&self, session: &AnalyticsSession) -> mongodb::error::Result<bool>;
+
+    async fn is_opted_out(&self, net_id: &str) -> mongodb::error::Result<bool>;
+
+    // The ones out of `net_ids` that opted out
+    async fn opted_out(&self, net_ids: &[&str]) -> mongodb::error::Result<HashSet<String>>;
+
+    // Returns false if the player was already opted out
+    async fn register_opt_out(&self, net_id: &str) -> mongodb::error::Result<bool>;
+
+    // Returns false if the player wasn't opted out
+    async fn withdraw_opt_out(&self, net_id: &str) -> mongodb::error::Result<bool>;
+
+    // Opens a live session, or keeps it open if it already is
+    async fn touch_live_session(
+        &self,
+        event: &LiveSessionEvent,
+        cloudflare_info: &CloudflareInfo,
+    ) -> mongodb::error::Result<()>;
+
+    // Marks the live session as ended now that the full session is stored. Returns false if there
+    // was no such live session.
+    async fn end_live_session(&self, session_id: &str) -> mongodb::error::Result<bool>;
+
+    // Returns how many live sessions were marked as abandoned
+    async fn sweep_abandoned_sessions(
+        &self,
+        timeout: chrono::TimeDelta,
+    ) -> mongodb::error::Result<u64>;
+
+    async fn find_net_id_for_session(
+        &self,
+        session_id: &str,
+    ) -> mongodb::error::Result<Option<String>>;
+
+    async fn get_players_stats(&self, filter: &StatsFilter) -> mongodb::error::Result<PlayerStats>;
+
+    async fn get_play_time_distribution(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<PlayTimeDistribution>;
+
+    async fn get_performance_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<PerformanceStats>>;
+
+    async fn get_daily_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<DailyRollup>>;
+
+    // Reports for days that won't change any more can be cached for `cache_ttl_secs`
+    async fn get_active_players(
+        &self,
+        filter: &StatsFilter,
+        cache_ttl_secs: u64,
+    ) -> mongodb::error::Result<Vec<ActivePlayers>>;
+
+    async fn get_retention_cohorts(
+        &self,
+        filter: &StatsFilter,
+        cache_ttl_secs: u64,
+    ) -> mongodb::error::Result<Vec<RetentionCohort>>;
+
+    async fn get_player_profile(
+        &self,
+        net_id: &str,
+    ) -> mongodb::error::Result<Option<PlayerProfile>>;
+
+    async fn search(
+        &self,
+        query: &SearchQuery,
+        limit: u64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<SearchHit>>;
+
+    async fn get_feedback(
+        &self,
+        query: &FeedbackQuery,
+        limit: i64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>>;
+
+    async fn get_feedback_item(&self, id: ObjectId)
+        -> mongodb::error::Result<Option<FeedbackItem>>;
+
+    // The comments left in one session, in the order they were left
+    async fn get_session_feedback(
+        &self,
+        session_object_id: ObjectId,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>>;
+
+    // Returns the updated comment, or None if there's no comment with that id
+    async fn update_feedback(
+        &self,
+        id: ObjectId,
+        update: &FeedbackUpdate,
+    ) -> mongodb::error::Result<Option<FeedbackItem>>;
+
+    // Marks the comment as being filed as a GitLab issue, unless it already has an issue or is
+    // being filed by someone else. Returns whether it was marked.
+    async fn claim_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<bool>;
+
+    // Undoes claim_feedback_gitlab_issue after filing the issue failed
+    async fn release_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<()>;
+
+    async fn set_feedback_gitlab_issue(
+        &self,
+        id: ObjectId,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()>;
+
+    // Saves an uploaded crash file and returns the attachment document to store with the crash
+    async fn store_crash_attachment(
+        &self,
+        config: &CrashReportConfig,
+        crash_id: &ObjectId,
+        kind: AttachmentKind,
+        file: &mut TempFile<'_>,
+    ) -> Result<Document, BoxError>;
+
+    // Removes the stored files of a crash's attachments
+    async fn delete_crash_attachments(&self, crash: &Document) -> Result<(), BoxError>;
+
+    // Stores a crash and counts it towards its signature's crash group. Returns the group.
+    async fn add_crash(&self, crash: Document) -> mongodb::error::Result<CrashGroup>;
+
+    // Most frequent first
+    async fn get_crash_groups(&self, limit: i64) -> mongodb::error::Result<Vec<CrashGroup>>;
+
+    async fn get_crash_group(&self, signature: &str) -> mongodb::error::Result<Option<CrashGroup>>;
+
+    // Same as the feedback ones, for crash groups
+    async fn claim_crash_group_gitlab_issue(&self, signature: &str)
+        -> mongodb::error::Result<bool>;
+
+    async fn release_crash_group_gitlab_issue(&self, signature: &str)
+        -> mongodb::error::Result<()>;
+
+    async fn set_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()>;
+
+    // Returns how many were stored
+    async fn add_events(&self, events: &[GameplayEvent]) -> mongodb::error::Result<u64>;
+
+    async fn count_events(&self, query: &EventQuery) -> mongodb::error::Result<u64>;
+
+    // Biggest groups first
+    async fn group_events(
+        &self,
+        query: &EventQuery,
+        grouping: &EventGrouping,
+    ) -> mongodb::error::Result<Vec<EventCount>>;
+
+    async fn get_funnel(
+        &self,
+        query: &FunnelQuery,
+    ) -> mongodb::error::Result<Vec<FunnelStepResult>>;
+
+    // Returns how many were stored
+    async fn add_positions(&self, samples: &[PositionSample]) -> mongodb::error::Result<u64>;
+
+    // Bounds of every position recorded on a map, for maps without configured bounds
+    async fn get_position_bounds(
+        &self,
+        map: &str,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<Option<config::MapBounds>>;
+
+    // Counts the positions that fall into each cell of `grid`
+    async fn bin_positions(
+        &self,
+        grid: &mut HeatmapGrid,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<()>;
+
+    // Deletes or anonymizes everything stored about a player, see privacy::erase
+    async fn erase(
+        &self,
+        config: &config::Config,
+        secrets: &Secrets,
+        request: &ErasureRequest,
+    ) -> Result<ErasureReport, BoxError>;
+
+    // Prunes every session past its retention period, see retention::run
+    async fn prune_sessions(&self, config: &RetentionConfig) -> Result<RetentionReport, BoxError>;
+}
+
+#[rocket::async_trait]
+impl Storage for Database {
+    async fn migrate(&self) -> Result<(), BoxError> {
+        // Migrations can rely on the unique indexes, like the one rollups upsert on
+        self.ensure_indexes().await?;
+        migrations::run_migrations(self, false).await?;
+        Ok(self.backfill_feedback().await?)
+    }
+
+    async fn add_session(
+        &self,
+        session: &AnalyticsSession,
+    ) -> mongodb::error::Result<AddSessionResult> {
+        Database::add_session(self, session).await
+    }
+
+    async fn add_sessions(
+        &self,
+        sessions: &[AnalyticsSession],
+    ) -> mongodb::error::Result<Vec<Result<AddSessionResult, String>>> {
+        Database::add_sessions(self, sessions).await
+    }
+
+    async fn get_session_document(&self, id: ObjectId) -> mongodb::error::Result<Option<Document>> {
+        Database::get_session_document(self, id).await
+    }
+
+    async fn count_if_anonymous(&self, session: &AnalyticsSession) -> mongodb::error::Result<bool> {
+        opt_outs::count_if_anonymous(self, session).await
+    }
+
+    async fn is_opted_out(&self, net_id: &str) -> mongodb::error::Result<bool> {
+        opt_outs::is_opted_out(self, net_id).await
+    }
+
+    async fn opted_out(&self, net_ids: &[&str]) -> mongodb::error::Result<HashSet<String>> {
+        opt_outs::opted_out(self, net_ids).await
+    }
+
+    async fn register_opt_out(&self, net_id: &str) -> mongodb::error::Result<bool> {
+        opt_outs::register(self, net_id).await
+    }
+
+    async fn withdraw_opt_out(&self, net_id: &str) -> mongodb::error::Result<bool> {
+        opt_outs::withdraw(self, net_id).await
+    }
+
+    async fn touch_live_session(
+        &self,
+        event: &LiveSessionEvent,
+        cloudflare_info: &CloudflareInfo,
+    ) -> mongodb::error::Result<()> {
+        Database::touch_live_session(self, event, cloudflare_info).await
+    }
+
+    async fn end_live_session(&self, session_id: &str) -> mongodb::error::Result<bool> {
+        Database::end_live_session(self, session_id).await
+    }
+
+    async fn sweep_abandoned_sessions(
+        &self,
+        timeout: chrono::TimeDelta,
+    ) -> mongodb::error::Result<u64> {
+        Database::sweep_abandoned_sessions(self, timeout).await
+    }
+
+    async fn find_net_id_for_session(
+        &self,
+        session_id: &str,
+    ) -> mongodb::error::Result<Option<String>> {
+        Database::find_net_id_for_session(self, session_id).await
+    }
+
+    async fn get_players_stats(&self, filter: &StatsFilter) -> mongodb::error::Result<PlayerStats> {
+        Database::get_players_stats(self, filter).await
+    }
+
+    async fn get_play_time_distribution(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<PlayTimeDistribution> {
+        Database::get_play_time_distribution(self, filter).await
+    }
+
+    async fn get_performance_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<PerformanceStats>> {
+        Database::get_performance_stats(self, filter).await
+    }
+
+    async fn get_daily_stats(
+        &self,
+        filter: &StatsFilter,
+    ) -> mongodb::error::Result<Vec<DailyRollup>> {
+        rollups::get_daily(self, filter).await
+    }
+
+    async fn get_active_players(
+        &self,
+        filter: &StatsFilter,
+        cache_ttl_secs: u64,
+    ) -> mongodb::error::Result<Vec<ActivePlayers>> {
+        reporting::active_players(self, filter, cache_ttl_secs).await
+    }
+
+    async fn get_retention_cohorts(
+        &self,
+        filter: &StatsFilter,
+        cache_ttl_secs: u64,
+    ) -> mongodb::error::Result<Vec<RetentionCohort>> {
+        reporting::retention_cohorts(self, filter, cache_ttl_secs).await
+    }
+
+    async fn get_player_profile(
+        &self,
+        net_id: &str,
+    ) -> mongodb::error::Result<Option<PlayerProfile>> {
+        Database::get_player_profile(self, net_id).await
+    }
+
+    async fn search(
+        &self,
+        query: &SearchQuery,
+        limit: u64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<SearchHit>> {
+        Database::search(self, query, limit, skip).await
+    }
+
+    async fn get_feedback(
+        &self,
+        query: &FeedbackQuery,
+        limit: i64,
+        skip: u64,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>> {
+        Database::get_feedback(self, query, limit, skip).await
+    }
+
+    async fn get_feedback_item(
+        &self,
+        id: ObjectId,
+    ) -> mongodb::error::Result<Option<FeedbackItem>> {
+        Database::get_feedback_item(self, id).await
+    }
+
+    async fn get_session_feedback(
+        &self,
+        session_object_id: ObjectId,
+    ) -> mongodb::error::Result<Vec<FeedbackItem>> {
+        Database::get_session_feedback(self, session_object_id).await
+    }
+
+    async fn update_feedback(
+        &self,
+        id: ObjectId,
+        update: &FeedbackUpdate,
+    ) -> mongodb::error::Result<Option<FeedbackItem>> {
+        Database::update_feedback(self, id, update).await
+    }
+
+    async fn claim_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<bool> {
+        Database::claim_feedback_gitlab_issue(self, id).await
+    }
+
+    async fn release_feedback_gitlab_issue(&self, id: ObjectId) -> mongodb::error::Result<()> {
+        Database::release_feedback_gitlab_issue(self, id).await
+    }
+
+    async fn set_feedback_gitlab_issue(
+        &self,
+        id: ObjectId,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()> {
+        Database::set_feedback_gitlab_issue(self, id, issue).await
+    }
+
+    async fn store_crash_attachment(
+        &self,
+        config: &CrashReportConfig,
+        crash_id: &ObjectId,
+        kind: AttachmentKind,
+        file: &mut TempFile<'_>,
+    ) -> Result<Document, BoxError> {
+        crash::store_attachment(self, config, crash_id, kind, file).await
+    }
+
+    async fn delete_crash_attachments(&self, crash: &Document) -> Result<(), BoxError> {
+        crash::delete_attachments(self, crash).await
+    }
+
+    async fn add_crash(&self, crash: Document) -> mongodb::error::Result<CrashGroup> {
+        Database::add_crash(self, crash).await
+    }
+
+    async fn get_crash_groups(&self, limit: i64) -> mongodb::error::Result<Vec<CrashGroup>> {
+        Database::get_crash_groups(self, limit).await
+    }
+
+    async fn get_crash_group(&self, signature: &str) -> mongodb::error::Result<Option<CrashGroup>> {
+        Database::get_crash_group(self, signature).await
+    }
+
+    async fn claim_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<bool> {
+        Database::claim_crash_group_gitlab_issue(self, signature).await
+    }
+
+    async fn release_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+    ) -> mongodb::error::Result<()> {
+        Database::release_crash_group_gitlab_issue(self, signature).await
+    }
+
+    async fn set_crash_group_gitlab_issue(
+        &self,
+        signature: &str,
+        issue: &GitlabIssue,
+    ) -> mongodb::error::Result<()> {
+        Database::set_crash_group_gitlab_issue(self, signature, issue).await
+    }
+
+    async fn add_events(&self, events: &[GameplayEvent]) -> mongodb::error::Result<u64> {
+        Database::add_events(self, events).await
+    }
+
+    async fn count_events(&self, query: &EventQuery) -> mongodb::error::Result<u64> {
+        Database::count_events(self, query).await
+    }
+
+    async fn group_events(
+        &self,
+        query: &EventQuery,
+        grouping: &EventGrouping,
+    ) -> mongodb::error::Result<Vec<EventCount>> {
+        Database::group_events(self, query, grouping).await
+    }
+
+    async fn get_funnel(
+        &self,
+        query: &FunnelQuery,
+    ) -> mongodb::error::Result<Vec<FunnelStepResult>> {
+        Database::get_funnel(self, query).await
+    }
+
+    async fn add_positions(&self, samples: &[PositionSample]) -> mongodb::error::Result<u64> {
+        Database::add_positions(self, samples).await
+    }
+
+    async fn get_position_bounds(
+        &self,
+        map: &str,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<Option<config::MapBounds>> {
+        Database::get_position_bounds(self, map, event_type).await
+    }
+
+    async fn bin_positions(
+        &self,
+        grid: &mut HeatmapGrid,
+        event_type: Option<&str>,
+    ) -> mongodb::error::Result<()> {
+        Database::bin_positions(self, grid, event_type).await
+    }
+
+    async fn erase(
+        &self,
+        config: &config::Config,
+        secrets: &Secrets,
+        request: &ErasureRequest,
+    ) -> Result<ErasureReport, BoxError> {
+        privacy::erase(self, config, secrets, request).await
+    }
+
+    async fn prune_sessions(&self, config: &RetentionConfig) -> Result<RetentionReport, BoxError> {
+        retention::run(self, config).await
+    }
+}
+
+// Picks the storage backend from the config. Only the mongo backend connects to mongo.
+pub fn create_storage(config: &config::Config) -> Arc<dyn Storage> {
+    match config.storage.backend {
+        StorageBackend::Mongo => Arc::new(database::connect_to_db(config)),
+        StorageBackend::Memory => {
+            println!("Storing everything in memory, it will be lost on restart!");
+            Arc::new(MemoryStorage::new())
+        }
+    }
+}
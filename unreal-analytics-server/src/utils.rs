@@ -6,3 +6,27 @@ pub fn session_duration_to_string(session_duration: &chrono::TimeDelta) -> Strin
         session_duration.num_seconds() % 60
     )
 }
+
+// Parses a date from a query parameter, either a full RFC 3339 date time or just YYYY-MM-DD
+pub fn parse_date_param(date: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Some(date_time.with_timezone(&chrono::Utc));
+    }
+
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(0, 0, 0)
+        .map(|date_time| date_time.and_utc())
+}
+
+// Escapes text so it can be matched literally by a mongo $regex
+pub fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#-".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}